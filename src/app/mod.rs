@@ -1,24 +1,26 @@
-use shell::Shell;
+use error::Error;
+use session::SessionManager;
 use ui::{Event, Ui};
 
 use std::time::Instant;
 
-type Error = String;
-
 pub struct App<U: Ui> {
-    shell: Shell,
+    sessions: SessionManager,
     ui: U,
 }
 
 impl<U: Ui> App<U> {
-    pub fn new(shell: Shell, ui: U) -> Self {
+    pub fn new(sessions: SessionManager, ui: U) -> Self {
         return App {
-            shell: shell,
+            sessions: sessions,
             ui: ui,
         };
     }
 
-    fn exit(self) -> Result<(), Error> {
-        return Err("not implemented".into());
+    fn exit(mut self) -> Result<(), Error> {
+        for (_, result) in self.sessions.close_all() {
+            result?;
+        }
+        return Ok(());
     }
 }
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use input::{Key, Modifiers};
+
+/// the bindings used unless the [keybindings] section of the config changes them
+const DEFAULT_BINDINGS: &[(&str, Action)] = &[
+    ("enter", Action::Submit),
+    ("shift+enter", Action::Newline),
+    ("ctrl+enter", Action::ForceSubmit),
+    ("ctrl+c", Action::Interrupt),
+    ("ctrl+z", Action::Suspend),
+    ("ctrl+backslash", Action::Quit),
+    ("ctrl+d", Action::Eof),
+    ("ctrl+shift+c", Action::Copy),
+    ("ctrl+shift+alt+c", Action::CopyStyled),
+    ("ctrl+shift+v", Action::Paste),
+    ("ctrl+shift+up", Action::ScrollLineUp),
+    ("ctrl+shift+down", Action::ScrollLineDown),
+    ("shift+page_up", Action::ScrollPageUp),
+    ("shift+page_down", Action::ScrollPageDown),
+    ("up", Action::HistoryPrevious),
+    ("down", Action::HistoryNext),
+    ("ctrl+r", Action::SearchHistory),
+    ("tab", Action::Complete),
+    ("ctrl+shift+t", Action::NewTab),
+    ("ctrl+shift+w", Action::CloseTab),
+    ("ctrl+page_down", Action::NextTab),
+    ("ctrl+page_up", Action::PreviousTab),
+    ("ctrl+plus", Action::ZoomIn),
+    ("ctrl+equal", Action::ZoomIn),
+    ("ctrl+minus", Action::ZoomOut),
+    ("ctrl+0", Action::ZoomReset),
+    ("ctrl+shift+q", Action::Exit),
+];
+
+#[derive(Debug)]
+pub enum Error {
+    /// a part of a chord in the config is neither a key nor a modifier
+    Chord { chord: String, part: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Chord {
+                ref chord,
+                ref part,
+            } => write!(
+                f,
+                "invalid key chord `{}` in the [keybindings] section of the config, \
+                 `{}` is not a key or modifier",
+                chord, part
+            ),
+        }
+    }
+}
+
+/// what a key chord does, named in the config in snake case
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// send the input to the shell, or start a new line if no command is running,
+    /// the input isn't a complete command and the shell's config has `smart_enter`
+    Submit,
+    /// send the input to the shell even if it looks unfinished
+    ForceSubmit,
+    /// start a new line in the input
+    Newline,
+    Interrupt,
+    Suspend,
+    Quit,
+    Eof,
+    /// copy the selected output as plain text
+    Copy,
+    /// copy the selected output with its colors and styles
+    CopyStyled,
+    Paste,
+    ScrollLineUp,
+    ScrollLineDown,
+    ScrollPageUp,
+    ScrollPageDown,
+    /// the command before the one in the input, from the first line of the input
+    HistoryPrevious,
+    /// the command after the one in the input, from its last line
+    HistoryNext,
+    /// search the history, or show the next match while searching
+    SearchHistory,
+    Complete,
+    /// open a session in the working directory of the one shown
+    NewTab,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    /// make the output text bigger
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    /// start the shell again
+    Restart,
+    /// close the window and every session
+    Exit,
+    /// unbinds a default, written "none"
+    #[serde(rename = "none")]
+    Unbound,
+}
+
+impl Action {
+    /// true for actions that work while a full-screen program has every other key
+    pub fn is_global(&self) -> bool {
+        match *self {
+            Action::Copy
+            | Action::CopyStyled
+            | Action::Paste
+            | Action::NewTab
+            | Action::CloseTab
+            | Action::NextTab
+            | Action::PreviousTab
+            | Action::ZoomIn
+            | Action::ZoomOut
+            | Action::ZoomReset
+            | Action::Exit => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// a key with the modifiers held when it's pressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Chord {
+    key: Key,
+    modifiers: Modifiers,
+}
+
+impl Chord {
+    /// shift is part of a letter's chord, but not of other characters,
+    /// `+` is the same chord on a keyboard that needs shift for it and one that doesn't
+    fn new(key: Key, modifiers: Modifiers) -> Chord {
+        let (key, shift) = match key {
+            Key::Char(c) if c.is_uppercase() => {
+                (Key::Char(c.to_lowercase().next().unwrap_or(c)), true)
+            }
+            Key::Char(c) if !c.is_alphabetic() => (key, false),
+            _ => (key, modifiers.shift),
+        };
+        return Chord {
+            key: key,
+            modifiers: Modifiers {
+                shift: shift,
+                ..modifiers
+            },
+        };
+    }
+
+    /// read a chord like `ctrl+shift+c`, modifiers first and the key last
+    fn parse(chord: &str) -> Result<Chord, Error> {
+        let error = |part: &str| Error::Chord {
+            chord: chord.to_string(),
+            part: part.to_string(),
+        };
+
+        let lowercase = chord.to_lowercase();
+        let mut parts: Vec<&str> = lowercase.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or("");
+        let key = parse_key(key).ok_or_else(|| error(key))?;
+
+        let mut modifiers = Modifiers::default();
+        for part in parts {
+            match part {
+                "shift" => modifiers.shift = true,
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                _ => return Err(error(part)),
+            }
+        }
+        return Ok(Chord::new(key, modifiers));
+    }
+}
+
+/// a key by its name in a chord, or a single character
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "enter" | "return" => Key::Enter,
+        "tab" => Key::Tab,
+        "backspace" => Key::Backspace,
+        "escape" | "esc" => Key::Escape,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "right" => Key::Right,
+        "left" => Key::Left,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "insert" => Key::Insert,
+        "delete" => Key::Delete,
+        "page_up" => Key::PageUp,
+        "page_down" => Key::PageDown,
+        // characters that can't be written in a chord, or are hard to read in one
+        "space" => Key::Char(' '),
+        "plus" => Key::Char('+'),
+        "minus" => Key::Char('-'),
+        "equal" => Key::Char('='),
+        "backslash" => Key::Char('\\'),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Char(c),
+                (Some('f'), Some(_)) => match name[1..].parse() {
+                    Ok(n @ 1..=12) => Key::Function(n),
+                    _ => return None,
+                },
+                _ => return None,
+            }
+        }
+    };
+    return Some(key);
+}
+
+/// the action for each key chord
+pub struct Bindings {
+    chords: HashMap<Chord, Action>,
+}
+
+impl Bindings {
+    /// the default bindings with those from the config over them
+    pub fn new(config: &HashMap<String, Action>) -> Result<Bindings, Error> {
+        let mut chords = HashMap::new();
+        for &(chord, action) in DEFAULT_BINDINGS {
+            chords.insert(Chord::parse(chord)?, action);
+        }
+        for (chord, &action) in config {
+            chords.insert(Chord::parse(chord)?, action);
+        }
+        return Ok(Bindings { chords: chords });
+    }
+
+    /// what pressing `key` with `modifiers` held does, if anything
+    pub fn action(&self, key: Key, modifiers: Modifiers) -> Option<Action> {
+        match self.chords.get(&Chord::new(key, modifiers)) {
+            Some(&Action::Unbound) | None => return None,
+            Some(&action) => return Some(action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    fn modifiers(shift: bool, ctrl: bool, alt: bool) -> Modifiers {
+        return Modifiers {
+            shift: shift,
+            ctrl: ctrl,
+            alt: alt,
+        };
+    }
+
+    #[test]
+    fn test_parse() {
+        let chord = |key, shift, ctrl, alt| Chord {
+            key: key,
+            modifiers: modifiers(shift, ctrl, alt),
+        };
+        let parse = |text| Chord::parse(text).ok();
+
+        assert_eq!(parse("ctrl+shift+c"), Some(chord(Key::Char('c'), true, true, false)));
+        assert_eq!(parse("Ctrl+C"), Some(chord(Key::Char('c'), false, true, false)));
+        assert_eq!(parse("shift+enter"), Some(chord(Key::Enter, true, false, false)));
+        assert_eq!(parse("alt+f5"), Some(chord(Key::Function(5), false, false, true)));
+        assert_eq!(parse("ctrl+plus"), Some(chord(Key::Char('+'), false, true, false)));
+        assert_eq!(parse("ctrl+shift+plus"), Some(chord(Key::Char('+'), false, true, false)));
+        assert_eq!(parse("ctrl+0"), Some(chord(Key::Char('0'), false, true, false)));
+        assert_eq!(parse("space"), Some(chord(Key::Char(' '), false, false, false)));
+
+        assert!(parse("f13").is_none());
+        assert!(parse("ctrl+").is_none());
+        assert!(parse("hyper+a").is_none());
+        assert!(parse("ctrl+shift+pageup").is_none());
+    }
+
+    #[test]
+    fn test_bindings() {
+        let config: HashMap<String, Action> = toml::from_str(
+            r#"
+            "enter" = "newline"
+            "shift+enter" = "submit"
+            "ctrl+c" = "none"
+            "alt+t" = "new_tab"
+            "#,
+        )
+        .expect("could not parse keybindings");
+        let bindings = Bindings::new(&config).expect("could not read keybindings");
+
+        let none = Modifiers::default();
+        let ctrl = modifiers(false, true, false);
+        let ctrl_shift = modifiers(true, true, false);
+        assert_eq!(bindings.action(Key::Enter, none), Some(Action::Newline));
+        assert_eq!(
+            bindings.action(Key::Enter, modifiers(true, false, false)),
+            Some(Action::Submit)
+        );
+        assert_eq!(bindings.action(Key::Char('c'), ctrl), None);
+        assert_eq!(
+            bindings.action(Key::Char('t'), modifiers(false, false, true)),
+            Some(Action::NewTab)
+        );
+
+        // the defaults that weren't replaced are kept
+        assert_eq!(bindings.action(Key::Char('C'), ctrl), Some(Action::Copy));
+        assert_eq!(bindings.action(Key::Char('c'), ctrl_shift), Some(Action::Copy));
+        assert_eq!(bindings.action(Key::Char('+'), ctrl_shift), Some(Action::ZoomIn));
+        assert_eq!(bindings.action(Key::Char('='), ctrl), Some(Action::ZoomIn));
+        assert_eq!(bindings.action(Key::Char('x'), ctrl), None);
+
+        let broken: HashMap<String, Action> =
+            vec![("ctrl+nope".to_string(), Action::Copy)].into_iter().collect();
+        assert!(Bindings::new(&broken).is_err());
+    }
+}
@@ -0,0 +1,688 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use history::History;
+use shell::Environment;
+
+/// candidates listed at once, the list follows the one that's chosen
+pub const MAX_SHOWN: usize = 12;
+/// characters that end a command, the word after one is a command again
+const COMMAND_SEPARATORS: &str = "|;&(\n";
+/// characters that end a word besides whitespace
+const WORD_SEPARATORS: &str = "|;&()<>";
+/// characters in file names that have to be escaped for the shell
+const SPECIAL: &str = " \t\\'\"$&|;()<>*?[]#!{}`";
+
+/// the word being completed and what's around it
+pub struct Request<'a> {
+    /// the end of the input up to the cursor, what candidates replace
+    pub word: &'a str,
+    /// the first word of the command the word is an argument of,
+    /// none if the word is the command
+    pub program: Option<&'a str>,
+    /// the shell's working directory, relative paths are completed in it
+    pub cwd: Option<&'a Path>,
+    /// the shell's variables
+    pub env: &'a Environment,
+    pub history: &'a History,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    /// replaces the word, it starts with the word as typed
+    pub text: String,
+    /// shown in the list of candidates
+    pub label: String,
+}
+
+impl Candidate {
+    fn new(text: String) -> Candidate {
+        return Candidate {
+            label: text.clone(),
+            text: text,
+        };
+    }
+}
+
+/// a source of candidates for a word
+pub trait Provider {
+    fn complete(&self, request: &Request) -> Vec<Candidate>;
+}
+
+/// executables in the shell's `$PATH` for the first word of a command
+pub struct Commands {
+    cache: RefCell<Option<Executables>>,
+}
+
+/// the executables in a `$PATH`, listed again once it or one of its directories changes
+struct Executables {
+    path: OsString,
+    /// when each directory was last modified, none if it can't be read
+    modified: Vec<Option<SystemTime>>,
+    names: Vec<String>,
+}
+
+impl Commands {
+    pub fn new() -> Commands {
+        return Commands {
+            cache: RefCell::new(None),
+        };
+    }
+}
+
+impl Provider for Commands {
+    fn complete(&self, request: &Request) -> Vec<Candidate> {
+        if request.program.is_some() || request.word.contains('/') || request.word.contains('$') {
+            return Vec::new();
+        }
+        let path = match request.env.get(OsStr::new("PATH")) {
+            Some(path) => path,
+            None => return Vec::new(),
+        };
+
+        let modified = modified(path);
+        let mut cache = self.cache.borrow_mut();
+        let current = match *cache {
+            Some(ref executables) => executables.path == *path && executables.modified == modified,
+            None => false,
+        };
+        if !current {
+            *cache = Some(Executables {
+                path: path.clone(),
+                modified: modified,
+                names: executables(path),
+            });
+        }
+
+        return match *cache {
+            Some(ref executables) => executables
+                .names
+                .iter()
+                .filter(|name| name.starts_with(request.word))
+                .map(|name| Candidate::new(name.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+    }
+}
+
+/// when each directory in `path` was last modified
+fn modified(path: &OsStr) -> Vec<Option<SystemTime>> {
+    return env::split_paths(path)
+        .map(|directory| fs::metadata(directory).and_then(|metadata| metadata.modified()).ok())
+        .collect();
+}
+
+fn executables(path: &OsStr) -> Vec<String> {
+    let mut names = Vec::new();
+    for directory in env::split_paths(path) {
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+    return names;
+}
+
+fn is_executable(path: &Path) -> bool {
+    return fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+}
+
+/// files and directories, relative ones in the shell's directory
+pub struct Paths;
+
+impl Provider for Paths {
+    fn complete(&self, request: &Request) -> Vec<Candidate> {
+        // programs in the current directory have to be run as paths anyway
+        if request.program.is_none() && !request.word.contains('/') {
+            return Vec::new();
+        }
+        if request.word.contains('$') {
+            return Vec::new();
+        }
+
+        let (directory, name) = match request.word.rfind('/') {
+            Some(index) => request.word.split_at(index + 1),
+            None => ("", request.word),
+        };
+        let path = match resolve(&unescape(directory), request.cwd, request.env) {
+            Some(path) => path,
+            None => return Vec::new(),
+        };
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let name = unescape(name);
+        let mut candidates = Vec::new();
+        for entry in entries.filter_map(Result::ok) {
+            let file_name = match entry.file_name().into_string() {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+            // hidden files only once a `.` is typed
+            if !file_name.starts_with(&name) || (file_name.starts_with('.') && name.is_empty()) {
+                continue;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            candidates.push(Candidate {
+                text: format!("{}{}{}", directory, escape(&file_name), suffix),
+                label: format!("{}{}", file_name, suffix),
+            });
+        }
+        return candidates;
+    }
+}
+
+/// the directory a path that ends in `/` names, `~` is the shell's home directory
+fn resolve(directory: &str, cwd: Option<&Path>, env: &Environment) -> Option<PathBuf> {
+    if directory.starts_with("~/") {
+        return env
+            .get(OsStr::new("HOME"))
+            .map(|home| PathBuf::from(home).join(&directory[2..]));
+    }
+    if directory.is_empty() {
+        return cwd.map(Path::to_path_buf);
+    }
+    let path = Path::new(directory);
+    if path.is_absolute() {
+        return Some(path.to_path_buf());
+    }
+    return cwd.map(|cwd| cwd.join(path));
+}
+
+fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    return escaped;
+}
+
+fn unescape(word: &str) -> String {
+    let mut unescaped = String::with_capacity(word.len());
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    return unescaped;
+}
+
+/// names of the shell's variables after a `$` or `${`
+pub struct Variables;
+
+impl Provider for Variables {
+    fn complete(&self, request: &Request) -> Vec<Candidate> {
+        let index = match request.word.rfind('$') {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let (before, name) = request.word.split_at(index + 1);
+        let (braced, name) = if name.starts_with('{') {
+            (true, &name[1..])
+        } else {
+            (false, name)
+        };
+
+        let mut candidates = Vec::new();
+        for variable in request.env.keys() {
+            let variable = match variable.to_str() {
+                Some(variable) => variable,
+                None => continue,
+            };
+            if !variable.starts_with(name) {
+                continue;
+            }
+            let text = if braced {
+                format!("{}{{{}}}", before, variable)
+            } else {
+                format!("{}{}", before, variable)
+            };
+            candidates.push(Candidate {
+                text: text,
+                label: format!("${}", variable),
+            });
+        }
+        return candidates;
+    }
+}
+
+/// words from earlier commands, commands for the first word
+/// and arguments the same program was run with for the others
+pub struct HistoryWords;
+
+impl Provider for HistoryWords {
+    fn complete(&self, request: &Request) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+        for entry in request.history.entries().iter().rev() {
+            let mut words = entry.command.split_whitespace();
+            let program = words.next();
+            let found: Vec<&str> = match request.program {
+                None => program.into_iter().collect(),
+                Some(current) if program == Some(current) => words.collect(),
+                Some(_) => continue,
+            };
+            candidates.extend(
+                found
+                    .into_iter()
+                    .filter(|word| word.starts_with(request.word) && *word != request.word)
+                    .map(|word| Candidate::new(word.to_string())),
+            );
+        }
+        return candidates;
+    }
+}
+
+/// asks every provider for candidates
+pub struct Completer {
+    providers: Vec<Box<dyn Provider>>,
+}
+
+impl Completer {
+    /// a completer with every provider germ has
+    pub fn new() -> Completer {
+        return Completer {
+            providers: vec![
+                Box::new(Variables),
+                Box::new(Commands::new()),
+                Box::new(Paths),
+                Box::new(HistoryWords),
+            ],
+        };
+    }
+
+    /// ask `provider` as well, after the others
+    pub fn add(&mut self, provider: Box<dyn Provider>) {
+        self.providers.push(provider);
+    }
+
+    /// candidates for the word that ends at `cursor`, a byte offset into `text`,
+    /// sorted and without duplicates
+    pub fn complete(
+        &self,
+        text: &str,
+        cursor: usize,
+        cwd: Option<&Path>,
+        env: &Environment,
+        history: &History,
+    ) -> Option<Completions> {
+        let line = &text[..cursor];
+        let start = word_start(line);
+        let command = match line[..start].rfind(|c: char| COMMAND_SEPARATORS.contains(c)) {
+            Some(index) => &line[index + 1..start],
+            None => &line[..start],
+        };
+        let request = Request {
+            word: &line[start..],
+            program: command.split_whitespace().next(),
+            cwd: cwd,
+            env: env,
+            history: history,
+        };
+
+        // the first provider to offer a text labels it
+        let mut candidates = BTreeMap::new();
+        for provider in self.providers.iter() {
+            for candidate in provider.complete(&request) {
+                if !candidates.contains_key(&candidate.text) {
+                    candidates.insert(candidate.text.clone(), candidate);
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+
+        return Some(Completions {
+            start: start,
+            end: cursor,
+            candidates: candidates.into_iter().map(|(_, candidate)| candidate).collect(),
+            index: None,
+            text: text.to_string(),
+        });
+    }
+}
+
+/// where the word that ends `line` starts, a backslash keeps a space in the word
+fn word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c.is_whitespace() || WORD_SEPARATORS.contains(c) {
+            start = index + c.len_utf8();
+        }
+    }
+    return start;
+}
+
+/// the input after a completion and the byte offset of the cursor in it
+#[derive(Debug, PartialEq)]
+pub struct Edit {
+    pub text: String,
+    pub cursor: usize,
+}
+
+/// the candidates for a word in the input, pressing tab again goes through them
+pub struct Completions {
+    /// the byte range of the word in the input, candidates replace it
+    start: usize,
+    end: usize,
+    candidates: Vec<Candidate>,
+    /// the candidate in the input, none until tab is pressed again
+    index: Option<usize>,
+    /// the input as the last completion left it
+    text: String,
+}
+
+impl Completions {
+    pub fn candidates(&self) -> &[Candidate] {
+        return &self.candidates;
+    }
+
+    /// the candidate in the input
+    pub fn index(&self) -> Option<usize> {
+        return self.index;
+    }
+
+    /// at most `MAX_SHOWN` candidates that include the chosen one
+    /// and the index of the first of them
+    pub fn shown(&self) -> (usize, &[Candidate]) {
+        let index = self.index.unwrap_or(0);
+        let first = (index + 1).saturating_sub(MAX_SHOWN);
+        let last = (first + MAX_SHOWN).min(self.candidates.len());
+        return (first, &self.candidates[first..last]);
+    }
+
+    /// put a candidate in the input
+    pub fn choose(&mut self, index: usize) -> Edit {
+        let text = self.candidates[index].text.clone();
+        self.index = Some(index);
+        return self.replace(&text);
+    }
+
+    /// true unless the input was edited since the last completion
+    fn is_current(&self, text: &str) -> bool {
+        return self.text == text;
+    }
+
+    fn replace(&mut self, word: &str) -> Edit {
+        let mut text = String::with_capacity(self.text.len() + word.len());
+        text.push_str(&self.text[..self.start]);
+        text.push_str(word);
+        let cursor = text.len();
+        text.push_str(&self.text[self.end..]);
+
+        self.end = cursor;
+        self.text = text.clone();
+        return Edit {
+            text: text,
+            cursor: cursor,
+        };
+    }
+}
+
+/// the longest start all candidates share
+fn common_prefix(candidates: &[Candidate]) -> &str {
+    let first = &candidates[0].text;
+    let mut len = first.len();
+    for candidate in candidates[1..].iter() {
+        len = first
+            .bytes()
+            .zip(candidate.text.bytes())
+            .take(len)
+            .take_while(|&(a, b)| a == b)
+            .count();
+    }
+    while !first.is_char_boundary(len) {
+        len -= 1;
+    }
+    return &first[..len];
+}
+
+/// what tab does to the input: complete the word before the cursor as far as
+/// all candidates agree, or put the next candidate in if it was just pressed,
+/// `completions` keeps the candidates while there's a choice to make
+pub fn tab(
+    completions: &mut Option<Completions>,
+    completer: &Completer,
+    text: &str,
+    cursor: usize,
+    cwd: Option<&Path>,
+    env: &Environment,
+    history: &History,
+) -> Option<Edit> {
+    if let Some(mut current) = completions.take() {
+        if current.is_current(text) {
+            let index = current
+                .index
+                .map_or(0, |index| (index + 1) % current.candidates.len());
+            let edit = current.choose(index);
+            *completions = Some(current);
+            return Some(edit);
+        }
+    }
+
+    let mut current = completer.complete(text, cursor, cwd, env, history)?;
+    if current.candidates.len() == 1 {
+        let mut edit = current.choose(0);
+        // a finished word gets a space, a directory can be completed further
+        let followed = edit.text[edit.cursor..].starts_with(char::is_whitespace);
+        if !edit.text[..edit.cursor].ends_with('/') && !followed {
+            edit.text.insert(edit.cursor, ' ');
+            edit.cursor += 1;
+        }
+        return Some(edit);
+    }
+
+    let prefix = common_prefix(&current.candidates).to_string();
+    let edit = if prefix.len() > current.end - current.start {
+        Some(current.replace(&prefix))
+    } else {
+        None
+    };
+    *completions = Some(current);
+    return edit;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use history::Entry;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    struct Words(Vec<&'static str>);
+
+    impl Provider for Words {
+        fn complete(&self, request: &Request) -> Vec<Candidate> {
+            return self
+                .0
+                .iter()
+                .filter(|word| word.starts_with(request.word))
+                .map(|word| Candidate::new(word.to_string()))
+                .collect();
+        }
+    }
+
+    fn texts(completions: Option<Completions>) -> Vec<String> {
+        return completions
+            .map(|completions| completions.candidates)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|candidate| candidate.text)
+            .collect();
+    }
+
+    #[test]
+    fn test_providers() {
+        let directory = env::temp_dir().join(format!("germ-{}-completion", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("sub dir")).unwrap();
+        fs::write(directory.join("file.txt"), "").unwrap();
+        fs::write(directory.join(".hidden"), "").unwrap();
+
+        let completer = Completer::new();
+        let history = History::new(10);
+        let mut env = Environment::new();
+        env.insert(OsString::from("GERM_COMPLETION_TEST"), OsString::from("1"));
+        env.insert(OsString::from("HOME"), directory.clone().into_os_string());
+        let complete = |text: &str| {
+            let cwd = Some(directory.as_path());
+            return texts(completer.complete(text, text.len(), cwd, &env, &history));
+        };
+        assert_eq!(complete("ls "), vec!["file.txt", "sub\\ dir/"]);
+        assert_eq!(complete("ls s"), vec!["sub\\ dir/"]);
+        assert_eq!(complete("cat sub\\ dir/../f"), vec!["sub\\ dir/../file.txt"]);
+        assert_eq!(complete("ls ."), vec![".hidden"]);
+        assert_eq!(complete("./fi"), vec!["./file.txt"]);
+        assert_eq!(complete("cd ~/su"), vec!["~/sub\\ dir/"]);
+        assert_eq!(complete("echo $HO"), vec!["$HOME"]);
+        assert_eq!(
+            complete("echo a${GERM_COMPLETION_T"),
+            vec!["a${GERM_COMPLETION_TEST}"]
+        );
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_commands() {
+        let directory = env::temp_dir().join(format!("germ-{}-commands", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let executable = |name: &str, mode: u32| {
+            let path = directory.join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        executable("germ-one", 0o755);
+        executable("germ-data", 0o644);
+
+        let commands = Commands::new();
+        let history = History::new(10);
+        let mut env = Environment::new();
+        env.insert(OsString::from("PATH"), directory.clone().into_os_string());
+        let complete = |word: &str| {
+            let request = Request {
+                word: word,
+                program: None,
+                cwd: None,
+                env: &env,
+                history: &history,
+            };
+            let mut names: Vec<String> = commands
+                .complete(&request)
+                .into_iter()
+                .map(|candidate| candidate.text)
+                .collect();
+            names.sort();
+            return names;
+        };
+        assert_eq!(complete("germ-"), vec!["germ-one"]);
+
+        // the directory is only listed again once an entry is added or removed
+        fs::set_permissions(directory.join("germ-one"), fs::Permissions::from_mode(0o644))
+            .unwrap();
+        assert_eq!(complete("germ-"), vec!["germ-one"]);
+        // modification times can be as coarse as a clock tick
+        thread::sleep(Duration::from_millis(50));
+        fs::remove_file(directory.join("germ-data")).unwrap();
+        executable("germ-two", 0o755);
+        assert_eq!(complete("germ-"), vec!["germ-two"]);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_history_words() {
+        let mut history = History::new(10);
+        history.push(Entry::new("git commit --amend", None)).unwrap();
+        history.push(Entry::new("grep --color x", None)).unwrap();
+
+        let mut completer = Completer {
+            providers: Vec::new(),
+        };
+        completer.add(Box::new(HistoryWords));
+        let env = Environment::new();
+        let complete =
+            |text: &str| texts(completer.complete(text, text.len(), None, &env, &history));
+        assert_eq!(complete("g"), vec!["git", "grep"]);
+        assert_eq!(complete("ls; git --"), vec!["--amend"]);
+        assert_eq!(complete("git commit --amend"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_tab() {
+        let mut completer = Completer {
+            providers: Vec::new(),
+        };
+        completer.add(Box::new(Words(vec!["make", "make-check", "make-clean", "zip"])));
+        let history = History::new(10);
+        let env = Environment::new();
+        let mut completions = None;
+        let mut press = |text: &str, cursor: usize| {
+            return tab(&mut completions, &completer, text, cursor, None, &env, &history);
+        };
+
+        let edit = |text: &str, cursor: usize| {
+            return Some(Edit {
+                text: text.to_string(),
+                cursor: cursor,
+            });
+        };
+        // the common prefix first, then each candidate in turn
+        assert_eq!(press("ma | x", 2), edit("make | x", 4));
+        assert_eq!(press("make | x", 4), edit("make | x", 4));
+        assert_eq!(press("make | x", 4), edit("make-check | x", 10));
+        assert_eq!(press("make-check | x", 10), edit("make-clean | x", 10));
+        assert_eq!(press("make-clean | x", 10), edit("make | x", 4));
+        // a single candidate is finished with a space
+        assert_eq!(press("make | z", 8), edit("make | zip ", 11));
+        assert_eq!(press("q", 1), None);
+    }
+
+    #[test]
+    fn test_shown() {
+        let mut completions = Completions {
+            start: 0,
+            end: 0,
+            candidates: (0..20).map(|i| Candidate::new(i.to_string())).collect(),
+            index: None,
+            text: String::new(),
+        };
+        assert_eq!(completions.shown().0, 0);
+        completions.choose(15);
+        let (first, shown) = completions.shown();
+        assert_eq!((first, shown.len()), (4, MAX_SHOWN));
+        assert_eq!(shown.last().map(|c| c.text.as_str()), Some("15"));
+    }
+}
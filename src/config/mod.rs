@@ -1,15 +1,72 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::read_to_string;
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
 
+use bindings::Action;
+use encoding::Encoding;
+
 use toml;
-use xdg::BaseDirectories;
+use xdg::{BaseDirectories, BaseDirectoriesError};
+
+const CONFIG_FILE: &str = "germ/config.toml";
+
+#[derive(Debug)]
+pub enum Error {
+    /// the xdg base directories could not be determined
+    Xdg(BaseDirectoriesError),
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        /// line and column of the problem, starting at 1
+        position: Option<(usize, usize)>,
+        source: toml::de::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Xdg(ref e) => write!(f, "could not find the xdg config directory: {}", e),
+            Error::Read {
+                ref path,
+                ref source,
+            } => write!(f, "could not read config file {}: {}", path.display(), source),
+            Error::Parse {
+                ref path,
+                position: Some((line, column)),
+                ref source,
+            } => write!(
+                f,
+                "invalid config file {}:{}:{}\n{}",
+                path.display(),
+                line,
+                column,
+                source
+            ),
+            Error::Parse {
+                ref path,
+                position: None,
+                ref source,
+            } => write!(f, "invalid config file {}\n{}", path.display(), source),
+        }
+    }
+}
 
 #[derive(Deserialize)]
 pub struct Config {
     pub font: Font,
     pub graphics: Graphics,
     pub shell: Shell,
+    pub terminal: Option<Terminal>,
+    pub history: Option<History>,
+    /// key chords like "ctrl+shift+c" and the actions they're bound to
+    pub keybindings: Option<HashMap<String, Action>>,
 }
 
 #[derive(Deserialize)]
@@ -24,35 +81,121 @@ pub struct Graphics {
 }
 
 #[derive(Deserialize)]
+pub struct Terminal {
+    /// lines of output kept after they scroll off the screen
+    pub scrollback_lines: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct History {
+    /// submitted commands kept, the oldest are dropped first
+    pub max_entries: Option<usize>,
+}
+
+#[derive(Clone, Deserialize)]
 pub struct Shell {
     pub path: String,
+    /// extra arguments passed to the shell, for bash `--norc` and `--rcfile`
+    /// choose the rc file that germ's own rc file sources
+    pub args: Option<Vec<String>>,
+    /// variables added to the shell's environment
+    pub env: Option<HashMap<String, String>>,
+    /// variables removed from the shell's environment
+    pub env_remove: Option<Vec<String>>,
+    /// the directory the shell starts in, `~` is expanded
+    pub working_directory: Option<String>,
+    /// start the shell as a login shell, bash sources the login startup files
+    /// from germ's rc file instead, since a login shell wouldn't source it
+    pub login: Option<bool>,
+    /// milliseconds to wait at each step of shutting the shell down
+    pub exit_timeout_ms: Option<u64>,
+    /// how shell output is decoded, "utf-8" or "latin-1"
+    pub encoding: Option<Encoding>,
+    /// submit only complete commands, enter starts a new line in an unfinished one
+    pub smart_enter: Option<bool>,
 }
 
-impl Config {
-    fn load<P: AsRef<Path>>(config_file: P) -> Result<Config, String> {
-        match read_to_string(&config_file) {
-            Ok(string) => {
-                toml::from_str(&string).map_err(|e| format!("could not parse toml config:\n{}", e))
-            }
-            Err(io_err) => Err(format!(
-                "could not read config file: {:?}\n{}",
-                config_file.as_ref(),
-                io_err
-            )),
-        }
+impl Shell {
+    pub fn new(path: PathBuf) -> Shell {
+        return Shell {
+            path: path.to_string_lossy().into_owned(),
+            args: None,
+            env: None,
+            env_remove: None,
+            working_directory: None,
+            login: None,
+            exit_timeout_ms: None,
+            encoding: None,
+            smart_enter: None,
+        };
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
+impl Config {
+    /// load the config file from the xdg config directory
+    pub fn load_default() -> Result<Config, Error> {
         let config_file: PathBuf = BaseDirectories::new()
-            .expect("could not read xdg config directory")
+            .map_err(Error::Xdg)?
             .get_config_home()
-            .join("germ/config.toml");
+            .join(CONFIG_FILE);
+
+        return Config::load(config_file);
+    }
 
-        return match Config::load(config_file) {
-            Ok(config) => config,
-            Err(e) => panic!(format!("could not load default config:\n{}", e)),
+    pub fn load<P: AsRef<Path>>(config_file: P) -> Result<Config, Error> {
+        let path = config_file.as_ref().to_path_buf();
+        let string = match read_to_string(&path) {
+            Ok(string) => string,
+            Err(e) => {
+                return Err(Error::Read {
+                    path: path,
+                    source: e,
+                })
+            }
         };
+
+        return Config::parse(path, &string);
+    }
+
+    /// `path` is only used to report errors
+    fn parse(path: PathBuf, string: &str) -> Result<Config, Error> {
+        return toml::from_str(string).map_err(|e| Error::Parse {
+            path: path,
+            position: e.line_col().map(|(line, column)| (line + 1, column + 1)),
+            source: e,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT_CONFIG: &str = include_str!("../../assets/default_config.toml");
+    const TEST_PATH: &str = "config.toml";
+
+    #[test]
+    fn test_parse_default() {
+        Config::parse(TEST_PATH.into(), DEFAULT_CONFIG)
+            .expect("could not parse the default config");
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let broken = DEFAULT_CONFIG.replacen("[shell]", "[shell", 1);
+        let line = broken
+            .lines()
+            .position(|line| line == "[shell")
+            .expect("could not find the broken line")
+            + 1;
+
+        match Config::parse(TEST_PATH.into(), &broken) {
+            Err(Error::Parse {
+                position: Some((error_line, _)),
+                ..
+            }) => assert_eq!(error_line, line),
+            Err(e) => assert!(false, "unexpected error: {}", e),
+            Ok(_) => assert!(false, "broken config was parsed"),
+        }
     }
 }
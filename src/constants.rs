@@ -1,3 +1,5 @@
 pub const DEFAULT_FONT: &'static str = "Andale Mono";
 pub const DEFAULT_DIMENSIONS: [u32; 2] = [480, 800];
 pub const DEFAULT_TITLE: &'static str = "germ";
+/// how long a window has to keep its size before the shell is told about it
+pub const RESIZE_DEBOUNCE_MS: u64 = 100;
@@ -0,0 +1,124 @@
+use std::str;
+
+const REPLACEMENT: char = '\u{fffd}';
+
+/// the character encoding of a byte stream
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum Encoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// ISO 8859-1, every byte is a character
+    #[serde(rename = "latin-1")]
+    Latin1,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        return Encoding::Utf8;
+    }
+}
+
+/// decodes a byte stream that arrives in arbitrary chunks
+/// a multibyte sequence split between chunks is held until the rest arrives,
+/// invalid bytes are replaced with U+FFFD
+pub struct Decoder {
+    encoding: Encoding,
+    partial: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Decoder {
+        return Decoder {
+            encoding: encoding,
+            partial: Vec::new(),
+        };
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        match self.encoding {
+            Encoding::Utf8 => return self.decode_utf8(bytes),
+            Encoding::Latin1 => return bytes.iter().map(|&byte| char::from(byte)).collect(),
+        }
+    }
+
+    /// end the stream, replacing any incomplete sequence
+    pub fn finish(&mut self) -> String {
+        let mut text = String::new();
+        if !self.partial.is_empty() {
+            self.partial.clear();
+            text.push(REPLACEMENT);
+        }
+        return text;
+    }
+
+    fn decode_utf8(&mut self, bytes: &[u8]) -> String {
+        let mut data = Vec::with_capacity(self.partial.len() + bytes.len());
+        data.extend_from_slice(&self.partial);
+        data.extend_from_slice(bytes);
+        self.partial.clear();
+
+        let mut text = String::with_capacity(data.len());
+        let mut rest = &data[..];
+        loop {
+            match str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // checked by `from_utf8` above
+                    text.push_str(unsafe { str::from_utf8_unchecked(valid) });
+
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(REPLACEMENT);
+                            rest = &after[len..];
+                        }
+                        // the input ends partway through a sequence
+                        None => {
+                            self.partial.extend_from_slice(after);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        return text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_sequence() {
+        let bytes = "naïve → ✓".as_bytes();
+        let mut decoder = Decoder::new(Encoding::Utf8);
+
+        // split at every position, including inside multibyte sequences
+        for split in 0..bytes.len() {
+            let mut text = decoder.decode(&bytes[..split]);
+            text.push_str(&decoder.decode(&bytes[split..]));
+            assert_eq!(text, "naïve → ✓");
+        }
+    }
+
+    #[test]
+    fn test_invalid_bytes() {
+        let mut decoder = Decoder::new(Encoding::Utf8);
+        assert_eq!(decoder.decode(b"a\xffb\xc3(c"), "a\u{fffd}b\u{fffd}(c");
+
+        assert_eq!(decoder.decode(b"end\xe2\x86"), "end");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+        assert_eq!(decoder.finish(), "");
+    }
+
+    #[test]
+    fn test_latin1() {
+        let mut decoder = Decoder::new(Encoding::Latin1);
+        assert_eq!(decoder.decode(b"caf\xe9 \xa9"), "café ©");
+    }
+}
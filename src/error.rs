@@ -0,0 +1,66 @@
+use std::fmt;
+
+use bindings;
+use config;
+use session;
+use shell;
+use ui;
+
+/// anything that can keep germ from running
+#[derive(Debug)]
+pub enum Error {
+    Config(config::Error),
+    Bindings(bindings::Error),
+    Session(session::Error),
+    Shell(shell::Error),
+    Ui(ui::Error),
+}
+
+impl From<config::Error> for Error {
+    fn from(error: config::Error) -> Self {
+        return Error::Config(error);
+    }
+}
+
+impl From<bindings::Error> for Error {
+    fn from(error: bindings::Error) -> Self {
+        return Error::Bindings(error);
+    }
+}
+
+impl From<session::Error> for Error {
+    fn from(error: session::Error) -> Self {
+        match error {
+            session::Error::Shell(e) => return Error::Shell(e),
+            e => return Error::Session(e),
+        }
+    }
+}
+
+impl From<shell::Error> for Error {
+    fn from(error: shell::Error) -> Self {
+        return Error::Shell(error);
+    }
+}
+
+impl From<ui::Error> for Error {
+    fn from(error: ui::Error) -> Self {
+        match error {
+            ui::Error::Shell(e) => return Error::Shell(e),
+            ui::Error::Session(e) => return Error::from(e),
+            e => return Error::Ui(e),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Config(ref e) => write!(f, "{}", e),
+            Error::Bindings(ref e) => write!(f, "{}", e),
+            Error::Session(ref e) => write!(f, "{}", e),
+            Error::Shell(ref e) => write!(f, "{}", e),
+            Error::Ui(ref e) => write!(f, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use xdg::{BaseDirectories, BaseDirectoriesError};
+
+const HISTORY_FILE: &str = "germ/history";
+/// commands can hold secrets, so only the user can read the file
+const FILE_MODE: u32 = 0o600;
+/// commands kept when the config doesn't set `max_entries`
+pub const DEFAULT_HISTORY_LIMIT: usize = 10_000;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+#[derive(Debug)]
+pub enum Error {
+    /// the xdg base directories could not be determined
+    Xdg(BaseDirectoriesError),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Xdg(ref e) => write!(f, "could not find the xdg data directory: {}", e),
+            Error::Io {
+                ref path,
+                ref source,
+            } => write!(f, "could not access history file {}: {}", path.display(), source),
+        }
+    }
+}
+
+/// a submitted command and what's known about how it ran
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub command: String,
+    /// seconds since the unix epoch when the command was last submitted
+    pub time: u64,
+    /// how many times the command was submitted
+    pub runs: u32,
+    /// the shell's working directory when the command was last submitted
+    pub cwd: Option<PathBuf>,
+    /// set once the shell reports the command finished
+    pub exit_code: Option<i32>,
+}
+
+impl Entry {
+    /// an entry for a command submitted now
+    pub fn new(command: &str, cwd: Option<&Path>) -> Entry {
+        return Entry {
+            command: command.trim_end().to_string(),
+            time: now(),
+            runs: 1,
+            cwd: cwd.map(Path::to_path_buf),
+            exit_code: None,
+        };
+    }
+
+    /// how well the entry matches a search, commands run often and recently come first
+    fn score(&self, now: u64) -> u64 {
+        let age = now.saturating_sub(self.time);
+        let weight = if age < HOUR {
+            16
+        } else if age < DAY {
+            8
+        } else if age < WEEK {
+            2
+        } else {
+            1
+        };
+        return self.runs as u64 * weight;
+    }
+
+    /// a line of the history file,
+    /// the time, exit code, runs, directory and command separated by tabs
+    fn to_line(&self) -> String {
+        let exit_code = self.exit_code.map(|code| code.to_string()).unwrap_or_default();
+        let cwd = self
+            .cwd
+            .as_ref()
+            .map(|cwd| cwd.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        return format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            self.time,
+            exit_code,
+            self.runs,
+            escape(&cwd),
+            escape(&self.command)
+        );
+    }
+
+    fn from_line(line: &str) -> Option<Entry> {
+        let mut fields = line.splitn(5, '\t');
+        let time = fields.next()?.parse().ok()?;
+        let exit_code = match fields.next()? {
+            "" => None,
+            code => Some(code.parse().ok()?),
+        };
+        let runs = fields.next()?.parse().ok()?;
+        let cwd = unescape(fields.next()?);
+        let cwd = if cwd.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(cwd))
+        };
+        let command = unescape(fields.next()?);
+        if command.is_empty() {
+            return None;
+        }
+
+        return Some(Entry {
+            command: command,
+            time: time,
+            runs: runs,
+            cwd: cwd,
+            exit_code: exit_code,
+        });
+    }
+}
+
+/// seconds since the unix epoch
+fn now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+}
+
+/// backslash escape tabs and newlines so a field stays on its line
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    return unescaped;
+}
+
+/// submitted commands, oldest first, without duplicates
+///
+/// the file is only ever appended to, a command that's run again or finishes
+/// gets a new line and the older lines for it are dropped the next time
+/// the file is compacted
+pub struct History {
+    /// each run of a command, oldest first, `None` once the command is run again
+    runs: VecDeque<Option<Entry>>,
+    /// how many runs were taken off the front of `runs`
+    dropped: usize,
+    /// the position of each command's latest run, counting the dropped runs
+    index: HashMap<String, usize>,
+    limit: usize,
+    /// where entries are saved, in memory only if not set
+    path: Option<PathBuf>,
+    /// lines in the file, compacted when it grows past twice the limit
+    lines: usize,
+    /// the run shown while walking the history, `runs.len()` for the draft
+    position: usize,
+    /// the line being edited before walking the history started
+    draft: String,
+}
+
+impl History {
+    /// a history that isn't saved
+    pub fn new(limit: usize) -> History {
+        return History {
+            runs: VecDeque::new(),
+            dropped: 0,
+            index: HashMap::new(),
+            limit: limit,
+            path: None,
+            lines: 0,
+            position: 0,
+            draft: String::new(),
+        };
+    }
+
+    /// load the history file from the xdg data directory
+    pub fn load_default(limit: usize) -> Result<History, Error> {
+        let history_file: PathBuf = BaseDirectories::new()
+            .map_err(Error::Xdg)?
+            .get_data_home()
+            .join(HISTORY_FILE);
+
+        return History::load(history_file, limit);
+    }
+
+    /// a file that doesn't exist yet is an empty history
+    pub fn load<P: AsRef<Path>>(history_file: P, limit: usize) -> Result<History, Error> {
+        let path = history_file.as_ref().to_path_buf();
+        let mut history = History::new(limit);
+        history.read(&path)?;
+        if history.lines > history.index.len() {
+            history.write(&path)?;
+        }
+        history.path = Some(path);
+        return Ok(history);
+    }
+
+    pub fn entries(&self) -> Vec<&Entry> {
+        return self.runs.iter().filter_map(Option::as_ref).collect();
+    }
+
+    /// add a submitted command, moving it to the end if it was run before
+    pub fn push(&mut self, mut entry: Entry) -> Result<(), Error> {
+        if entry.command.trim().is_empty() {
+            self.reset();
+            return Ok(());
+        }
+        if let Some(previous) = self.latest(&entry.command) {
+            entry.runs += previous.runs;
+        }
+
+        let line = entry.to_line();
+        self.insert(entry);
+        self.reset();
+        return self.append(&line);
+    }
+
+    /// record the exit code of the latest run of `command`
+    pub fn finish(&mut self, command: &str, exit_code: i32) -> Result<(), Error> {
+        let line = match self.latest_mut(command.trim_end()) {
+            Some(entry) => {
+                entry.exit_code = Some(exit_code);
+                entry.to_line()
+            }
+            None => return Ok(()),
+        };
+        return self.append(&line);
+    }
+
+    /// commands containing `query`, run in `cwd` if it's set,
+    /// the most used and recent first
+    pub fn search(&self, query: &str, cwd: Option<&Path>) -> Vec<&Entry> {
+        return self.search_at(query, cwd, now());
+    }
+
+    fn search_at(&self, query: &str, cwd: Option<&Path>, now: u64) -> Vec<&Entry> {
+        let mut matches: Vec<&Entry> = self
+            .runs
+            .iter()
+            .rev()
+            .filter_map(Option::as_ref)
+            .filter(|e| e.command.contains(query))
+            .filter(|e| cwd.map_or(true, |cwd| e.cwd.as_ref().map(PathBuf::as_path) == Some(cwd)))
+            .collect();
+        // the sort is stable, newer commands stay ahead of ones with the same score
+        matches.sort_by_key(|e| Reverse(e.score(now)));
+        return matches;
+    }
+
+    /// the command before the one shown, `current` is kept as the draft
+    /// if walking the history starts here
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        let position = (0..self.position).rev().find(|&i| self.runs[i].is_some())?;
+        if self.position == self.runs.len() {
+            self.draft = current.to_string();
+        }
+
+        self.position = position;
+        return self.runs[position].as_ref().map(|e| e.command.as_str());
+    }
+
+    /// the command after the one shown, or the draft after the newest command
+    pub fn next(&mut self) -> Option<&str> {
+        if self.position >= self.runs.len() {
+            return None;
+        }
+
+        let len = self.runs.len();
+        self.position = (self.position + 1..len)
+            .find(|&i| self.runs[i].is_some())
+            .unwrap_or(len);
+        if self.position == len {
+            return Some(&self.draft);
+        }
+        return self.runs[self.position].as_ref().map(|e| e.command.as_str());
+    }
+
+    /// stop walking the history, the next `previous` starts at the newest command
+    pub fn reset(&mut self) {
+        self.position = self.runs.len();
+        self.draft.clear();
+    }
+
+    fn latest(&self, command: &str) -> Option<&Entry> {
+        let position = *self.index.get(command)?;
+        return self.runs[position - self.dropped].as_ref();
+    }
+
+    fn latest_mut(&mut self, command: &str) -> Option<&mut Entry> {
+        let position = *self.index.get(command)?;
+        return self.runs[position - self.dropped].as_mut();
+    }
+
+    /// add an entry in memory, dropping an older run of the same command
+    /// and the oldest entries past the limit
+    fn insert(&mut self, entry: Entry) {
+        if let Some(&position) = self.index.get(&entry.command) {
+            let run = &mut self.runs[position - self.dropped];
+            // a line written when the command finished updates it in place
+            if run.as_ref().map_or(false, |run| run.time == entry.time) {
+                *run = Some(entry);
+                return;
+            }
+            *run = None;
+        }
+        self.index
+            .insert(entry.command.clone(), self.dropped + self.runs.len());
+        self.runs.push_back(Some(entry));
+
+        while self.index.len() > self.limit {
+            if let Some(Some(oldest)) = self.runs.pop_front() {
+                self.index.remove(&oldest.command);
+            }
+            self.dropped += 1;
+        }
+        while self.runs.front().map_or(false, Option::is_none) {
+            self.runs.pop_front();
+            self.dropped += 1;
+        }
+
+        // clear out older runs once they outnumber the commands
+        if self.runs.len() > 2 * self.index.len() {
+            self.runs.retain(Option::is_some);
+            for (i, entry) in self.runs.iter().filter_map(Option::as_ref).enumerate() {
+                if let Some(position) = self.index.get_mut(&entry.command) {
+                    *position = self.dropped + i;
+                }
+            }
+        }
+    }
+
+    /// replace the entries with those in the file
+    fn read(&mut self, path: &Path) -> Result<(), Error> {
+        let string = match fs::read_to_string(path) {
+            Ok(string) => string,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(Error::Io {
+                    path: path.to_path_buf(),
+                    source: e,
+                })
+            }
+        };
+
+        self.runs.clear();
+        self.dropped = 0;
+        self.index.clear();
+        for entry in string.lines().filter_map(Entry::from_line) {
+            self.insert(entry);
+        }
+        self.lines = string.lines().count();
+        self.reset();
+        return Ok(());
+    }
+
+    fn append(&mut self, line: &str) -> Result<(), Error> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return Ok(()),
+        };
+
+        create_parent(&path)
+            .and_then(|_| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(FILE_MODE)
+                    .open(&path)?
+                    .write_all(line.as_bytes())
+            })
+            .map_err(|e| Error::Io {
+                path: path.clone(),
+                source: e,
+            })?;
+        self.lines += 1;
+
+        if self.lines >= self.limit.saturating_mul(2) {
+            return self.compact(&path);
+        }
+        return Ok(());
+    }
+
+    /// rewrite the file with one line per entry, reading it again first
+    /// so the lines other windows appended since it was loaded are kept
+    fn compact(&mut self, path: &Path) -> Result<(), Error> {
+        self.read(path)?;
+        return self.write(path);
+    }
+
+    /// replace the file with one line per entry
+    fn write(&mut self, path: &Path) -> Result<(), Error> {
+        let contents: String = self.entries().into_iter().map(Entry::to_line).collect();
+
+        // write a copy first so the history survives a failed write
+        let temporary = path.with_extension("tmp");
+        let result = create_parent(path)
+            .and_then(|_| {
+                OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .mode(FILE_MODE)
+                    .open(&temporary)?
+                    .write_all(contents.as_bytes())
+            })
+            .and_then(|_| fs::rename(&temporary, path));
+        self.lines = self.index.len();
+        return result.map_err(|e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        });
+    }
+}
+
+/// an incremental search through the history, like readline's reverse search
+pub struct Search {
+    query: String,
+    /// the shell's directory when the search started
+    cwd: Option<PathBuf>,
+    /// set to only match commands run in `cwd`
+    in_cwd: bool,
+    /// which match is shown, each `next` moves to a less likely one
+    index: usize,
+    /// the line being edited when the search started
+    draft: String,
+}
+
+impl Search {
+    pub fn new(draft: &str, cwd: Option<&Path>) -> Search {
+        return Search {
+            query: String::new(),
+            cwd: cwd.map(Path::to_path_buf),
+            in_cwd: false,
+            index: 0,
+            draft: draft.to_string(),
+        };
+    }
+
+    pub fn query(&self) -> &str {
+        return &self.query;
+    }
+
+    pub fn draft(&self) -> &str {
+        return &self.draft;
+    }
+
+    /// the directory matches are limited to, if they are
+    pub fn directory(&self) -> Option<&Path> {
+        if !self.in_cwd {
+            return None;
+        }
+        return self.cwd.as_ref().map(PathBuf::as_path);
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.index = 0;
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.index = 0;
+    }
+
+    /// show the next match, the last one stays shown
+    pub fn next(&mut self, history: &History) {
+        if self.index + 1 < history.search(&self.query, self.directory()).len() {
+            self.index += 1;
+        }
+    }
+
+    /// switch between matching every command and only those run in `cwd`
+    pub fn toggle_directory(&mut self) {
+        if self.cwd.is_some() {
+            self.in_cwd = !self.in_cwd;
+            self.index = 0;
+        }
+    }
+
+    /// the match shown, none if nothing matches the query
+    pub fn current<'a>(&self, history: &'a History) -> Option<&'a Entry> {
+        return history
+            .search(&self.query, self.directory())
+            .into_iter()
+            .nth(self.index);
+    }
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => return fs::create_dir_all(parent),
+        None => return Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    fn entry(command: &str, time: u64) -> Entry {
+        return Entry {
+            command: command.to_string(),
+            time: time,
+            runs: 1,
+            cwd: None,
+            exit_code: None,
+        };
+    }
+
+    fn temporary_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("germ-{}-{}", process::id(), name));
+        let _ = fs::remove_file(&path);
+        return path;
+    }
+
+    #[test]
+    fn test_line_round_trip() {
+        let entry = Entry {
+            command: "printf 'a\\tb'\techo \\\nnext".to_string(),
+            time: 1234,
+            runs: 3,
+            cwd: Some(PathBuf::from("/tmp/with\ttab")),
+            exit_code: Some(-1),
+        };
+        let line = entry.to_line();
+        assert_eq!(line.matches('\n').count(), 1);
+        assert_eq!(Entry::from_line(line.trim_end_matches('\n')), Some(entry));
+
+        assert_eq!(Entry::from_line("1\t\t1\t\tls").map(|e| e.exit_code), Some(None));
+        assert_eq!(Entry::from_line("not a time\t0\t1\t\tls"), None);
+        assert_eq!(Entry::from_line("1\t0\t1\t"), None);
+    }
+
+    #[test]
+    fn test_dedupe_and_limit() {
+        let path = temporary_file("history-dedupe");
+        let mut history = History::load(&path, 3).unwrap();
+        for (time, command) in ["ls", "cd /", "ls", "pwd", "make"].iter().enumerate() {
+            history.push(entry(command, time as u64)).unwrap();
+        }
+        history.finish("pwd\n", 2).unwrap();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), FILE_MODE);
+
+        let commands = |history: &History| -> Vec<String> {
+            return history.entries().iter().map(|e| e.command.clone()).collect();
+        };
+        assert_eq!(commands(&history), vec!["ls", "pwd", "make"]);
+
+        // the file is compacted to the same entries when it's loaded again
+        let loaded = History::load(&path, 3).unwrap();
+        assert_eq!(commands(&loaded), vec!["ls", "pwd", "make"]);
+        assert_eq!(loaded.entries()[0].runs, 2);
+        assert_eq!(loaded.entries()[1].exit_code, Some(2));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(mode(&path), FILE_MODE);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_compact_keeps_other_windows() {
+        let path = temporary_file("history-windows");
+        let mut first = History::load(&path, 3).unwrap();
+        let mut second = History::load(&path, 3).unwrap();
+        first.push(entry("a", 1)).unwrap();
+        first.push(entry("a", 2)).unwrap();
+        second.push(entry("b", 3)).unwrap();
+        first.push(entry("a", 4)).unwrap();
+        first.push(entry("a", 5)).unwrap();
+        first.push(entry("c", 6)).unwrap();
+        // the sixth line this window writes compacts the file
+        first.push(entry("c", 7)).unwrap();
+
+        let commands: Vec<&str> = first.entries().iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, vec!["b", "a", "c"]);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(History::load(&path, 3).unwrap().entries()[0].command, "b");
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_walk() {
+        let mut history = History::new(10);
+        assert_eq!(history.previous("draft"), None);
+        history.push(entry("first", 1)).unwrap();
+        history.push(entry("second", 2)).unwrap();
+        history.push(entry("  ", 3)).unwrap();
+
+        assert_eq!(history.previous("draft"), Some("second"));
+        assert_eq!(history.previous("second"), Some("first"));
+        assert_eq!(history.previous("first"), None);
+        assert_eq!(history.next(), Some("second"));
+        assert_eq!(history.next(), Some("draft"));
+        assert_eq!(history.next(), None);
+
+        history.previous("other draft");
+        history.reset();
+        assert_eq!(history.next(), None);
+        assert_eq!(history.previous(""), Some("second"));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut history = History::new(10);
+        let mut in_src = entry("make test", 0);
+        in_src.cwd = Some(PathBuf::from("/src"));
+        history.push(in_src).unwrap();
+        history.push(entry("make", 0)).unwrap();
+        history.push(entry("make", 0)).unwrap();
+        history.push(entry("make clean", 2 * WEEK)).unwrap();
+        history.push(entry("ls", 2 * WEEK)).unwrap();
+
+        let commands = |cwd: Option<&Path>| -> Vec<&str> {
+            return history
+                .search_at("make", cwd, 2 * WEEK)
+                .iter()
+                .map(|e| e.command.as_str())
+                .collect();
+        };
+        // a recent command beats one run more often long ago, ties go to the newer one
+        assert_eq!(commands(None), vec!["make clean", "make", "make test"]);
+        assert_eq!(commands(Some(Path::new("/src"))), vec!["make test"]);
+
+        // everything is old by now, so the most run command comes first
+        let mut search = Search::new("draft", Some(Path::new("/src")));
+        search.push('k');
+        assert_eq!(search.current(&history).map(|e| e.command.as_str()), Some("make"));
+        search.next(&history);
+        search.next(&history);
+        search.next(&history);
+        assert_eq!(search.current(&history).map(|e| e.command.as_str()), Some("make test"));
+        search.toggle_directory();
+        search.push('x');
+        assert!(search.current(&history).is_none());
+        search.pop();
+        assert_eq!(search.current(&history).map(|e| e.command.as_str()), Some("make test"));
+        assert_eq!(search.draft(), "draft");
+    }
+}
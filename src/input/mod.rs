@@ -0,0 +1,220 @@
+/// a key press sent straight to a program that reads the terminal itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// F1 to F12
+    Function(u8),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    /// the xterm modifier parameter, 1 if no modifier is held
+    fn parameter(&self) -> u8 {
+        return 1 + self.shift as u8 + 2 * self.alt as u8 + 4 * self.ctrl as u8;
+    }
+}
+
+const ESC: u8 = 0x1b;
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// the bytes an xterm sends for a key press,
+/// `application_cursor` is DECCKM, which full-screen programs set
+pub fn encode(key: Key, modifiers: Modifiers, application_cursor: bool) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let parameter = modifiers.parameter();
+
+    match key {
+        Key::Char(c) => {
+            if modifiers.alt {
+                bytes.push(ESC);
+            }
+            match control_byte(c) {
+                Some(byte) if modifiers.ctrl => bytes.push(byte),
+                _ => {
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+        }
+        Key::Enter => {
+            if modifiers.alt {
+                bytes.push(ESC);
+            }
+            bytes.push(b'\r');
+        }
+        Key::Tab if modifiers.shift => bytes.extend_from_slice(b"\x1b[Z"),
+        Key::Tab => bytes.push(b'\t'),
+        Key::Backspace => {
+            if modifiers.alt {
+                bytes.push(ESC);
+            }
+            bytes.push(if modifiers.ctrl { 0x08 } else { 0x7f });
+        }
+        Key::Escape => bytes.push(ESC),
+        Key::Up => cursor_key(&mut bytes, b'A', parameter, application_cursor),
+        Key::Down => cursor_key(&mut bytes, b'B', parameter, application_cursor),
+        Key::Right => cursor_key(&mut bytes, b'C', parameter, application_cursor),
+        Key::Left => cursor_key(&mut bytes, b'D', parameter, application_cursor),
+        Key::Home => cursor_key(&mut bytes, b'H', parameter, application_cursor),
+        Key::End => cursor_key(&mut bytes, b'F', parameter, application_cursor),
+        Key::Insert => tilde_key(&mut bytes, 2, parameter),
+        Key::Delete => tilde_key(&mut bytes, 3, parameter),
+        Key::PageUp => tilde_key(&mut bytes, 5, parameter),
+        Key::PageDown => tilde_key(&mut bytes, 6, parameter),
+        Key::Function(n @ 1..=4) => {
+            let final_byte = b'P' + n - 1;
+            if parameter > 1 {
+                bytes.extend_from_slice(format!("\x1b[1;{}", parameter).as_bytes());
+                bytes.push(final_byte);
+            } else {
+                bytes.extend_from_slice(&[ESC, b'O', final_byte]);
+            }
+        }
+        Key::Function(n @ 5..=12) => {
+            const CODES: [u8; 8] = [15, 17, 18, 19, 20, 21, 23, 24];
+            tilde_key(&mut bytes, CODES[(n - 5) as usize], parameter);
+        }
+        Key::Function(_) => (),
+    }
+
+    return bytes;
+}
+
+/// the bytes sent for pasted text, newlines become carriage returns like typed enter,
+/// `bracketed` wraps it in markers so the program knows it wasn't typed
+pub fn paste(text: &str, bracketed: bool) -> Vec<u8> {
+    let mut text = text.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        // an end marker in the text would let the rest run as typed input
+        text = format!("{}{}{}", PASTE_START, text.replace(PASTE_END, ""), PASTE_END);
+    }
+    return text.into_bytes();
+}
+
+/// true if text can be pasted without asking,
+/// a newline or control character could run a command the user didn't see
+pub fn is_safe_paste(text: &str) -> bool {
+    return !text.chars().any(|c| c.is_control() && c != '\t');
+}
+
+/// the control character for ctrl and `c`, following xterm
+fn control_byte(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' => return Some(c as u8 - b'a' + 1),
+        'A'..='Z' => return Some(c as u8 - b'A' + 1),
+        '@' | ' ' | '2' => return Some(0x00),
+        '[' | '3' => return Some(0x1b),
+        '\\' | '4' => return Some(0x1c),
+        ']' | '5' => return Some(0x1d),
+        '^' | '6' => return Some(0x1e),
+        '_' | '/' | '7' => return Some(0x1f),
+        '?' | '8' => return Some(0x7f),
+        _ => return None,
+    }
+}
+
+fn cursor_key(bytes: &mut Vec<u8>, final_byte: u8, parameter: u8, application_cursor: bool) {
+    if parameter > 1 {
+        bytes.extend_from_slice(format!("\x1b[1;{}", parameter).as_bytes());
+    } else if application_cursor {
+        bytes.extend_from_slice(b"\x1bO");
+    } else {
+        bytes.extend_from_slice(b"\x1b[");
+    }
+    bytes.push(final_byte);
+}
+
+fn tilde_key(bytes: &mut Vec<u8>, code: u8, parameter: u8) {
+    if parameter > 1 {
+        bytes.extend_from_slice(format!("\x1b[{};{}~", code, parameter).as_bytes());
+    } else {
+        bytes.extend_from_slice(format!("\x1b[{}~", code).as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(key: Key) -> Vec<u8> {
+        return encode(key, Modifiers::default(), false);
+    }
+
+    #[test]
+    fn test_chars() {
+        assert_eq!(plain(Key::Char('é')), "é".as_bytes());
+        assert_eq!(plain(Key::Enter), b"\r");
+        assert_eq!(plain(Key::Backspace), b"\x7f");
+
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(encode(Key::Char('c'), ctrl, false), b"\x03");
+        assert_eq!(encode(Key::Char('['), ctrl, false), b"\x1b");
+
+        let alt = Modifiers {
+            alt: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(encode(Key::Char('f'), alt, false), b"\x1bf");
+    }
+
+    #[test]
+    fn test_cursor_keys() {
+        assert_eq!(plain(Key::Up), b"\x1b[A");
+        assert_eq!(encode(Key::Up, Modifiers::default(), true), b"\x1bOA");
+
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(encode(Key::Right, ctrl, true), b"\x1b[1;5C");
+    }
+
+    #[test]
+    fn test_function_keys() {
+        assert_eq!(plain(Key::Function(1)), b"\x1bOP");
+        assert_eq!(plain(Key::Function(5)), b"\x1b[15~");
+        assert_eq!(plain(Key::Function(12)), b"\x1b[24~");
+        assert_eq!(plain(Key::PageDown), b"\x1b[6~");
+
+        let shift = Modifiers {
+            shift: true,
+            ..Modifiers::default()
+        };
+        assert_eq!(encode(Key::Delete, shift, false), b"\x1b[3;2~");
+        assert_eq!(encode(Key::Tab, shift, false), b"\x1b[Z");
+    }
+
+    #[test]
+    fn test_paste() {
+        assert_eq!(paste("ls\r\nrm x\n", false), b"ls\rrm x\r");
+        assert_eq!(paste("a\x1b[201~b", true), b"\x1b[200~ab\x1b[201~");
+
+        assert!(is_safe_paste("git log\t--oneline"));
+        assert!(!is_safe_paste("rm -rf build\n"));
+        assert!(!is_safe_paste("echo \x1b[31m"));
+    }
+}
@@ -1,15 +1,17 @@
 #![feature(const_str_as_bytes)]
 #![feature(test)]
-#![feature(try_trait)]
 
 extern crate test;
+extern crate clipboard;
 #[macro_use]
 extern crate conrod;
 extern crate font_loader;
+extern crate libc;
 extern crate futures;
 #[macro_use]
 extern crate serde_derive;
 extern crate toml;
+extern crate unicode_width;
 extern crate volatile;
 extern crate xdg;
 
@@ -17,30 +19,66 @@ extern crate gdk;
 extern crate gio;
 extern crate glib;
 extern crate gtk;
+extern crate pango;
 
 mod app;
+mod bindings;
+mod completion;
 mod config;
 mod constants;
+mod encoding;
+mod error;
+mod history;
+mod input;
+mod parser;
+mod pty;
+mod screen;
+mod session;
 mod shell;
 mod stream;
 mod ui;
 
 use ui::Ui;
 
-use shell::Shell;
+use bindings::Bindings;
+use constants::DEFAULT_TITLE;
+use error::Error;
+use history::{History, DEFAULT_HISTORY_LIMIT};
+use session::SessionManager;
 use ui::backend::gtk::Gtk;
 use ui::Config;
 
 const DEFAULT_DIMENSIONS: [i32; 2] = [600, 600];
 
-fn main() -> Result<(), String> {
-    let config: Config = Config::default();
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("germ: {}", e);
+        ::std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let config: Config = Config::load_default()?;
+    let bindings = Bindings::new(&config.keybindings.clone().unwrap_or_default())?;
+
+    let mut ui: Gtk = Gtk::create(DEFAULT_TITLE.into(), DEFAULT_DIMENSIONS)?;
 
-    let mut ui: Gtk =
-        Gtk::create("test title".into(), DEFAULT_DIMENSIONS).expect("unable to create gtk app");
+    let mut sessions = SessionManager::new();
+    if let Some(lines) = config.terminal.as_ref().and_then(|t| t.scrollback_lines) {
+        sessions.set_scrollback_limit(lines);
+    }
+    let session = sessions.create(config.shell.clone())?;
 
-    let shell_path = config.shell.path.clone();
-    let shell = Shell::create(shell_path.into()).expect("could not create shell");
+    let limit = config
+        .history
+        .as_ref()
+        .and_then(|h| h.max_entries)
+        .unwrap_or(DEFAULT_HISTORY_LIMIT);
+    // a broken history shouldn't keep the terminal from starting
+    let history = History::load_default(limit).unwrap_or_else(|e| {
+        eprintln!("commands will not be saved: {}", e);
+        History::new(limit)
+    });
 
-    return ui.show(shell);
+    return ui.show(sessions, history, bindings, session).map_err(Error::from);
 }
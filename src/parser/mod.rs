@@ -0,0 +1,565 @@
+use std::mem;
+
+/// more parameters than this are dropped
+const MAX_PARAMS: usize = 32;
+/// sequences with more intermediates than this are ignored
+const MAX_INTERMEDIATES: usize = 2;
+/// limit the memory an unterminated OSC or DCS string can take
+const MAX_STRING_LEN: usize = 64 * 1024;
+
+/// the final part of a CSI, DCS or ESC sequence
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequence {
+    /// each parameter is a list of `:` separated subparameters,
+    /// omitted values are 0
+    pub params: Vec<Vec<u16>>,
+    /// includes private markers such as the `?` in `CSI ? 25 h`
+    pub intermediates: Vec<char>,
+    pub action: char,
+}
+
+impl Sequence {
+    /// the first value of a parameter, or `default` if it's missing or 0
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params.get(index).and_then(|param| param.first()) {
+            Some(&value) if value != 0 => return value,
+            _ => return default,
+        }
+    }
+
+    pub fn has_intermediate(&self, intermediate: char) -> bool {
+        return self.intermediates.contains(&intermediate);
+    }
+}
+
+/// what the terminal should do with the parsed output
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// text to show, consecutive characters are combined
+    Print(String),
+    /// a C0 or C1 control such as newline or backspace
+    Execute(char),
+    Csi(Sequence),
+    Esc(Sequence),
+    /// operating system command, split on `;`
+    Osc(Vec<String>),
+    /// device control string with its data
+    Dcs(Sequence, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    DcsEntry,
+    DcsParam,
+    DcsIntermediate,
+    DcsPassthrough,
+    DcsIgnore,
+    OscString,
+    SosPmApcString,
+}
+
+/// turns terminal output into actions
+/// follows the DEC ANSI parser state machine described at https://vt100.net/emu/dec_ansi_parser
+/// input is decoded text, so C1 controls are the characters U+0080 to U+009F
+pub struct Parser {
+    state: State,
+    params: Vec<Vec<u16>>,
+    /// the parameter being read, `params` holds the ones before it
+    param: Vec<u16>,
+    intermediates: Vec<char>,
+    /// too many intermediates, the sequence won't be dispatched
+    ignoring: bool,
+    /// collected OSC or DCS data
+    string: String,
+    /// the header of the DCS being collected
+    dcs: Option<Sequence>,
+    /// an OSC or DCS just ended with ESC, so the next `\` completes the terminator
+    terminating: bool,
+    print: String,
+    actions: Vec<Action>,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        return Parser {
+            state: State::Ground,
+            params: Vec::new(),
+            param: Vec::new(),
+            intermediates: Vec::new(),
+            ignoring: false,
+            string: String::new(),
+            dcs: None,
+            terminating: false,
+            print: String::new(),
+            actions: Vec::new(),
+        };
+    }
+
+    /// parse a chunk of output
+    /// sequences split between chunks are completed by later calls
+    pub fn parse(&mut self, text: &str) -> Vec<Action> {
+        for c in text.chars() {
+            self.advance(c);
+        }
+        self.flush_print();
+        return mem::replace(&mut self.actions, Vec::new());
+    }
+
+    fn advance(&mut self, c: char) {
+        let code = c as u32;
+
+        // transitions that apply in every state
+        match code {
+            0x18 | 0x1a | 0x80..=0x8f | 0x91..=0x97 | 0x99 | 0x9a => {
+                self.execute(c);
+                return self.transition(State::Ground);
+            }
+            0x1b => {
+                self.terminating = self.in_string();
+                return self.transition(State::Escape);
+            }
+            0x90 => return self.transition(State::DcsEntry),
+            0x98 | 0x9e | 0x9f => return self.transition(State::SosPmApcString),
+            0x9b => return self.transition(State::CsiEntry),
+            0x9c => return self.transition(State::Ground),
+            0x9d => return self.transition(State::OscString),
+            _ => (),
+        }
+
+        let terminating = mem::replace(&mut self.terminating, false);
+
+        match self.state {
+            State::Ground => match code {
+                0x00..=0x1f => self.execute(c),
+                0x7f => (),
+                _ => self.print.push(c),
+            },
+            State::Escape => match code {
+                0x00..=0x1f => self.execute(c),
+                0x20..=0x2f => {
+                    self.collect(c);
+                    self.transition(State::EscapeIntermediate);
+                }
+                0x50 => self.transition(State::DcsEntry),
+                0x58 | 0x5e | 0x5f => self.transition(State::SosPmApcString),
+                0x5b => self.transition(State::CsiEntry),
+                // the second half of ST after a string
+                0x5c if terminating => self.transition(State::Ground),
+                0x5d => self.transition(State::OscString),
+                0x30..=0x7e => {
+                    self.esc_dispatch(c);
+                    self.transition(State::Ground);
+                }
+                _ => (),
+            },
+            State::EscapeIntermediate => match code {
+                0x00..=0x1f => self.execute(c),
+                0x20..=0x2f => self.collect(c),
+                0x30..=0x7e => {
+                    self.esc_dispatch(c);
+                    self.transition(State::Ground);
+                }
+                _ => (),
+            },
+            State::CsiEntry => match code {
+                0x00..=0x1f => self.execute(c),
+                0x20..=0x2f => {
+                    self.collect(c);
+                    self.transition(State::CsiIntermediate);
+                }
+                0x30..=0x3b => {
+                    self.param(c);
+                    self.transition(State::CsiParam);
+                }
+                0x3c..=0x3f => {
+                    self.collect(c);
+                    self.transition(State::CsiParam);
+                }
+                0x40..=0x7e => {
+                    self.csi_dispatch(c);
+                    self.transition(State::Ground);
+                }
+                _ => (),
+            },
+            State::CsiParam => match code {
+                0x00..=0x1f => self.execute(c),
+                0x20..=0x2f => {
+                    self.collect(c);
+                    self.transition(State::CsiIntermediate);
+                }
+                0x30..=0x3b => self.param(c),
+                0x3c..=0x3f => self.transition(State::CsiIgnore),
+                0x40..=0x7e => {
+                    self.csi_dispatch(c);
+                    self.transition(State::Ground);
+                }
+                _ => (),
+            },
+            State::CsiIntermediate => match code {
+                0x00..=0x1f => self.execute(c),
+                0x20..=0x2f => self.collect(c),
+                0x30..=0x3f => self.transition(State::CsiIgnore),
+                0x40..=0x7e => {
+                    self.csi_dispatch(c);
+                    self.transition(State::Ground);
+                }
+                _ => (),
+            },
+            State::CsiIgnore => match code {
+                0x00..=0x1f => self.execute(c),
+                0x40..=0x7e => self.transition(State::Ground),
+                _ => (),
+            },
+            State::DcsEntry => match code {
+                0x20..=0x2f => {
+                    self.collect(c);
+                    self.transition(State::DcsIntermediate);
+                }
+                0x30..=0x3b => {
+                    self.param(c);
+                    self.transition(State::DcsParam);
+                }
+                0x3c..=0x3f => {
+                    self.collect(c);
+                    self.transition(State::DcsParam);
+                }
+                0x40..=0x7e => self.hook(c),
+                _ => (),
+            },
+            State::DcsParam => match code {
+                0x20..=0x2f => {
+                    self.collect(c);
+                    self.transition(State::DcsIntermediate);
+                }
+                0x30..=0x3b => self.param(c),
+                0x3c..=0x3f => self.transition(State::DcsIgnore),
+                0x40..=0x7e => self.hook(c),
+                _ => (),
+            },
+            State::DcsIntermediate => match code {
+                0x20..=0x2f => self.collect(c),
+                0x30..=0x3f => self.transition(State::DcsIgnore),
+                0x40..=0x7e => self.hook(c),
+                _ => (),
+            },
+            State::DcsPassthrough => match code {
+                0x7f => (),
+                _ => self.put(c),
+            },
+            State::OscString => match code {
+                // xterm also ends OSC with BEL
+                0x07 => self.transition(State::Ground),
+                0x00..=0x1f => (),
+                _ => self.put(c),
+            },
+            State::DcsIgnore | State::SosPmApcString => (),
+        }
+    }
+
+    fn in_string(&self) -> bool {
+        match self.state {
+            State::DcsPassthrough
+            | State::DcsIgnore
+            | State::OscString
+            | State::SosPmApcString => return true,
+            _ => return false,
+        }
+    }
+
+    /// run the exit action of the current state and the entry action of the next
+    fn transition(&mut self, state: State) {
+        match self.state {
+            State::OscString => self.osc_dispatch(),
+            State::DcsPassthrough => self.unhook(),
+            _ => (),
+        }
+
+        match state {
+            State::Escape | State::CsiEntry | State::DcsEntry => self.clear(),
+            State::OscString => self.string.clear(),
+            _ => (),
+        }
+
+        self.state = state;
+    }
+
+    fn clear(&mut self) {
+        self.params.clear();
+        self.param.clear();
+        self.intermediates.clear();
+        self.ignoring = false;
+    }
+
+    fn collect(&mut self, c: char) {
+        if self.intermediates.len() < MAX_INTERMEDIATES {
+            self.intermediates.push(c);
+        } else {
+            self.ignoring = true;
+        }
+    }
+
+    fn param(&mut self, c: char) {
+        match c {
+            ';' => {
+                let param = mem::replace(&mut self.param, Vec::new());
+                self.push_param(param);
+            }
+            ':' => {
+                if self.param.is_empty() {
+                    self.param.push(0);
+                }
+                self.param.push(0);
+            }
+            _ => {
+                let digit = (c as u32 - '0' as u32) as u16;
+                if self.param.is_empty() {
+                    self.param.push(0);
+                }
+                if let Some(value) = self.param.last_mut() {
+                    *value = value.saturating_mul(10).saturating_add(digit);
+                }
+            }
+        }
+    }
+
+    fn push_param(&mut self, mut param: Vec<u16>) {
+        if param.is_empty() {
+            param.push(0);
+        }
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(param);
+        }
+    }
+
+    /// the collected sequence, or `None` if it should be ignored
+    fn finish_sequence(&mut self, action: char) -> Option<Sequence> {
+        // a trailing parameter is only pushed if one was started,
+        // so `CSI m` has no parameters while `CSI ; m` has two
+        if !self.param.is_empty() || !self.params.is_empty() {
+            let param = mem::replace(&mut self.param, Vec::new());
+            self.push_param(param);
+        }
+
+        if self.ignoring {
+            return None;
+        }
+
+        return Some(Sequence {
+            params: mem::replace(&mut self.params, Vec::new()),
+            intermediates: mem::replace(&mut self.intermediates, Vec::new()),
+            action: action,
+        });
+    }
+
+    fn execute(&mut self, c: char) {
+        self.flush_print();
+        self.actions.push(Action::Execute(c));
+    }
+
+    fn esc_dispatch(&mut self, c: char) {
+        if let Some(sequence) = self.finish_sequence(c) {
+            self.flush_print();
+            self.actions.push(Action::Esc(sequence));
+        }
+    }
+
+    fn csi_dispatch(&mut self, c: char) {
+        if let Some(sequence) = self.finish_sequence(c) {
+            self.flush_print();
+            self.actions.push(Action::Csi(sequence));
+        }
+    }
+
+    fn hook(&mut self, c: char) {
+        self.dcs = self.finish_sequence(c);
+        self.string.clear();
+        self.transition(State::DcsPassthrough);
+    }
+
+    fn put(&mut self, c: char) {
+        if self.string.len() < MAX_STRING_LEN {
+            self.string.push(c);
+        }
+    }
+
+    fn unhook(&mut self) {
+        if let Some(sequence) = self.dcs.take() {
+            let data = mem::replace(&mut self.string, String::new());
+            self.flush_print();
+            self.actions.push(Action::Dcs(sequence, data));
+        }
+    }
+
+    fn osc_dispatch(&mut self) {
+        let string = mem::replace(&mut self.string, String::new());
+        self.flush_print();
+        self.actions
+            .push(Action::Osc(string.split(';').map(String::from).collect()));
+    }
+
+    fn flush_print(&mut self) {
+        if !self.print.is_empty() {
+            let text = mem::replace(&mut self.print, String::new());
+            self.actions.push(Action::Print(text));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// recorded terminal output and the actions expected from it, one per line
+    const FIXTURES: &[(&str, &[u8], &str)] = &[
+        (
+            "bash_prompt",
+            include_bytes!("fixtures/bash_prompt.in"),
+            include_str!("fixtures/bash_prompt.out"),
+        ),
+        (
+            "dcs",
+            include_bytes!("fixtures/dcs.in"),
+            include_str!("fixtures/dcs.out"),
+        ),
+        (
+            "ls_color",
+            include_bytes!("fixtures/ls_color.in"),
+            include_str!("fixtures/ls_color.out"),
+        ),
+        (
+            "osc",
+            include_bytes!("fixtures/osc.in"),
+            include_str!("fixtures/osc.out"),
+        ),
+        (
+            "tput",
+            include_bytes!("fixtures/tput.in"),
+            include_str!("fixtures/tput.out"),
+        ),
+    ];
+
+    fn describe(action: &Action) -> String {
+        match *action {
+            Action::Print(ref text) => format!("print {:?}", text),
+            Action::Execute(c) => format!("execute {:#04x}", c as u32),
+            Action::Csi(ref sequence) => format!(
+                "csi {:?} {:?} {:?}",
+                sequence.intermediates, sequence.params, sequence.action
+            ),
+            Action::Esc(ref sequence) => {
+                format!("esc {:?} {:?}", sequence.intermediates, sequence.action)
+            }
+            Action::Osc(ref params) => format!("osc {:?}", params),
+            Action::Dcs(ref sequence, ref data) => format!(
+                "dcs {:?} {:?} {:?} {:?}",
+                sequence.intermediates, sequence.params, sequence.action, data
+            ),
+        }
+    }
+
+    /// combine prints that were split up by chunk boundaries
+    fn merge_prints(actions: Vec<Action>) -> Vec<Action> {
+        let mut merged: Vec<Action> = Vec::new();
+        for action in actions {
+            if let (Some(&mut Action::Print(ref mut last)), &Action::Print(ref text)) =
+                (merged.last_mut(), &action)
+            {
+                last.push_str(text);
+                continue;
+            }
+            merged.push(action);
+        }
+        return merged;
+    }
+
+    fn csi(text: &str) -> Sequence {
+        match Parser::new().parse(text).pop() {
+            Some(Action::Csi(sequence)) => return sequence,
+            action => panic!("expected a CSI sequence, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_fixtures() {
+        for &(name, input, expected) in FIXTURES {
+            let input = ::std::str::from_utf8(input).expect("fixtures are utf-8");
+            let actions: Vec<String> = Parser::new().parse(input).iter().map(describe).collect();
+            let expected: Vec<&str> = expected.lines().collect();
+
+            assert_eq!(actions, expected, "fixture {} was parsed differently", name);
+        }
+    }
+
+    #[test]
+    fn test_split_input() {
+        for &(name, input, _) in FIXTURES {
+            let input = ::std::str::from_utf8(input).expect("fixtures are utf-8");
+            let whole = Parser::new().parse(input);
+
+            let mut parser = Parser::new();
+            let mut split = Vec::new();
+            for c in input.chars() {
+                split.extend(parser.parse(&c.to_string()));
+            }
+
+            assert_eq!(merge_prints(split), whole, "fixture {} depends on chunking", name);
+        }
+    }
+
+    #[test]
+    fn test_params() {
+        let sequence = csi("\x1b[5;;12H");
+        assert_eq!(sequence.params, vec![vec![5], vec![0], vec![12]]);
+        assert_eq!(sequence.param(0, 1), 5);
+        assert_eq!(sequence.param(1, 1), 1);
+        assert_eq!(sequence.param(3, 1), 1);
+
+        assert!(csi("\x1b[m").params.is_empty());
+        assert_eq!(csi("\x1b[99999m").params, vec![vec![u16::max_value()]]);
+    }
+
+    #[test]
+    fn test_subparams() {
+        let sequence = csi("\x1b[38:2::10:20:30m");
+        assert_eq!(sequence.params, vec![vec![38, 2, 0, 10, 20, 30]]);
+    }
+
+    #[test]
+    fn test_c1_controls() {
+        let sequence = csi("\u{9b}?25l");
+        assert!(sequence.has_intermediate('?'));
+        assert_eq!(sequence.param(0, 0), 25);
+        assert_eq!(sequence.action, 'l');
+
+        assert_eq!(
+            Parser::new().parse("a\u{85}b"),
+            vec![
+                Action::Print("a".into()),
+                Action::Execute('\u{85}'),
+                Action::Print("b".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ignored_sequences() {
+        // too many intermediates
+        assert_eq!(Parser::new().parse("\x1b[ !\"pok"), vec![Action::Print("ok".into())]);
+        // a private marker after parameters
+        assert_eq!(Parser::new().parse("\x1b[1?hok"), vec![Action::Print("ok".into())]);
+        // cancelled with CAN
+        assert_eq!(
+            Parser::new().parse("\x1b[1\x18ok"),
+            vec![Action::Execute('\x18'), Action::Print("ok".into())]
+        );
+        // privacy messages are dropped
+        assert_eq!(Parser::new().parse("\x1b^secret\x1b\\ok"), vec![Action::Print("ok".into())]);
+    }
+}
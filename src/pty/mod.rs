@@ -0,0 +1,248 @@
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+
+use libc;
+
+const PTS_NAME_LEN: usize = 128;
+
+/// the master side of a pseudo-terminal pair
+pub struct Pty {
+    master: File,
+    /// the read and write ends of a pipe closed along with the `Pty`,
+    /// readers wait on the read end
+    dropped: (File, File),
+}
+
+impl Pty {
+    /// allocate a new master/slave pair
+    /// returns the master and an open handle to the slave
+    /// neither is inherited by spawned processes unless attached explicitly
+    pub fn open() -> io::Result<(Pty, File)> {
+        let master_fd =
+            unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+        if master_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // take ownership right away so the fd is closed on error
+        let master = unsafe { File::from_raw_fd(master_fd) };
+
+        if unsafe { libc::grantpt(master_fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::unlockpt(master_fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let slave_name = slave_name(master_fd)?;
+        let slave_fd = unsafe {
+            libc::open(
+                slave_name.as_ptr(),
+                libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+            )
+        };
+        if slave_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = unsafe { File::from_raw_fd(slave_fd) };
+
+        let mut pipe_fds = [0; 2];
+        if unsafe { libc::pipe2(pipe_fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let dropped = unsafe {
+            (
+                File::from_raw_fd(pipe_fds[0]),
+                File::from_raw_fd(pipe_fds[1]),
+            )
+        };
+
+        return Ok((
+            Pty {
+                master: master,
+                dropped: dropped,
+            },
+            slave,
+        ));
+    }
+
+    /// get a new handle to the master side
+    /// used to split reading and writing between threads
+    pub fn try_clone_master(&self) -> io::Result<File> {
+        return self.master.try_clone();
+    }
+
+    /// get a handle for reading the master side from another thread
+    pub fn reader(&self) -> io::Result<Reader> {
+        return Ok(Reader {
+            master: self.master.try_clone()?,
+            dropped: self.dropped.0.try_clone()?,
+        });
+    }
+
+    pub fn master_fd(&self) -> RawFd {
+        return self.master.as_raw_fd();
+    }
+
+    /// set the terminal size in character cells
+    /// if it changed the kernel sends SIGWINCH to the foreground process group
+    pub fn resize(&self, rows: u16, columns: u16) -> io::Result<()> {
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if unsafe { libc::ioctl(self.master_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
+    }
+
+    /// true if the line discipline collects input into lines before the program reads it,
+    /// programs that read each key press themselves turn this off
+    pub fn is_canonical(&self) -> io::Result<bool> {
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(self.master_fd(), &mut termios) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(termios.c_lflag & libc::ICANON != 0);
+    }
+}
+
+/// reads the master side until the slave is closed or the `Pty` is dropped,
+/// so a background job that keeps the slave open can't keep the reader blocked
+pub struct Reader {
+    master: File,
+    dropped: File,
+}
+
+impl Read for Reader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut fds = [
+            libc::pollfd {
+                fd: self.master.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.dropped.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // nothing is written to the pipe, so it's only ready once its write end is closed
+        if fds[1].revents != 0 {
+            return Ok(0);
+        }
+        return self.master.read(buffer);
+    }
+}
+
+/// attach the slave side of a pty to a command's standard streams
+/// and make the spawned child a session leader with the slave as its
+/// controlling terminal
+pub fn attach(command: &mut Command, slave: &File) -> io::Result<()> {
+    command
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave.try_clone()?));
+
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // stdin is the slave at this point
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            return Ok(());
+        });
+    }
+
+    return Ok(());
+}
+
+fn slave_name(master_fd: RawFd) -> io::Result<CString> {
+    let mut buffer = vec![0 as libc::c_char; PTS_NAME_LEN];
+    let result = unsafe { libc::ptsname_r(master_fd, buffer.as_mut_ptr(), buffer.len()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+
+    let name = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+    return Ok(name.to_owned());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_open() {
+        let (pty, mut slave) = Pty::open().expect("could not open pty");
+        let mut master = pty.try_clone_master().expect("could not clone master");
+
+        slave.write_all(b"hello").expect("could not write to slave");
+
+        let mut buffer = [0u8; 5];
+        master
+            .read_exact(&mut buffer)
+            .expect("could not read from master");
+
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[test]
+    fn test_reader() {
+        let (pty, mut slave) = Pty::open().expect("could not open pty");
+        let mut reader = pty.reader().expect("could not get reader");
+
+        slave.write_all(b"hello").expect("could not write to slave");
+        let mut buffer = [0u8; 5];
+        reader
+            .read_exact(&mut buffer)
+            .expect("could not read from master");
+        assert_eq!(&buffer, b"hello");
+
+        // the slave is still open, but nothing is left to read it
+        drop(pty);
+        assert_eq!(reader.read(&mut buffer).expect("could not read from master"), 0);
+    }
+
+    #[test]
+    fn test_resize() {
+        let (pty, slave) = Pty::open().expect("could not open pty");
+        pty.resize(30, 100).expect("could not resize pty");
+
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        assert!(unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } >= 0);
+        assert_eq!((size.ws_row, size.ws_col), (30, 100));
+    }
+
+    #[test]
+    fn test_canonical() {
+        let (pty, slave) = Pty::open().expect("could not open pty");
+        assert!(pty.is_canonical().expect("could not get terminal mode"));
+
+        let mut termios: libc::termios = unsafe { mem::zeroed() };
+        unsafe {
+            assert!(libc::tcgetattr(slave.as_raw_fd(), &mut termios) >= 0);
+            libc::cfmakeraw(&mut termios);
+            assert!(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) >= 0);
+        }
+        assert!(!pty.is_canonical().expect("could not get terminal mode"));
+    }
+}
@@ -0,0 +1,179 @@
+use unicode_width::UnicodeWidthChar;
+
+/// the character of the cell covered by the right half of a wide character
+const SPACER: char = '\0';
+
+/// the xterm colors for the 16 ansi palette entries
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Color {
+    /// the renderer's foreground or background color
+    Default,
+    /// one of the 256 palette colors, the first 16 are the ansi colors
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// the red, green and blue values of the color,
+    /// `None` for the renderer's default
+    pub fn rgb(&self) -> Option<(u8, u8, u8)> {
+        match *self {
+            Color::Default => return None,
+            Color::Rgb(r, g, b) => return Some((r, g, b)),
+            Color::Indexed(i @ 0..=15) => return Some(ANSI_COLORS[i as usize]),
+            // a 6x6x6 color cube
+            Color::Indexed(i @ 16..=231) => {
+                let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+                let i = i - 16;
+                return Some((level(i / 36), level(i / 6 % 6), level(i % 6)));
+            }
+            // a grayscale ramp
+            Color::Indexed(i) => {
+                let gray = 8 + (i - 232) * 10;
+                return Some((gray, gray, gray));
+            }
+        }
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        return Color::Default;
+    }
+}
+
+/// how a cell is drawn, set with SGR sequences
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Attributes {
+    pub foreground: Color,
+    pub background: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub inverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+    /// the OSC 8 hyperlink the cell is part of, an index into the screen's links
+    pub link: Option<u32>,
+}
+
+impl Attributes {
+    /// the SGR sequence that draws text this way, starting with a reset
+    pub fn sgr(&self) -> String {
+        let mut params = vec!["0".to_string()];
+        let flags = [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+            (self.blink, "5"),
+            (self.inverse, "7"),
+            (self.hidden, "8"),
+            (self.strikethrough, "9"),
+        ];
+        for &(set, param) in flags.iter() {
+            if set {
+                params.push(param.to_string());
+            }
+        }
+        if let Some(param) = color_param(self.foreground, 30, 90, 38) {
+            params.push(param);
+        }
+        if let Some(param) = color_param(self.background, 40, 100, 48) {
+            params.push(param);
+        }
+        return format!("\x1b[{}m", params.join(";"));
+    }
+}
+
+/// the SGR parameter for a color, given the parameters that start
+/// the 8 colors, the 8 bright colors and an extended color
+fn color_param(color: Color, base: u8, bright: u8, extended: u8) -> Option<String> {
+    match color {
+        Color::Default => return None,
+        Color::Indexed(i @ 0..=7) => return Some((base + i).to_string()),
+        Color::Indexed(i @ 8..=15) => return Some((bright + i - 8).to_string()),
+        Color::Indexed(i) => return Some(format!("{};5;{}", extended, i)),
+        Color::Rgb(r, g, b) => return Some(format!("{};2;{};{};{}", extended, r, g, b)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub c: char,
+    pub attributes: Attributes,
+}
+
+impl Cell {
+    /// an empty cell, erased cells keep the background color
+    pub fn blank(attributes: Attributes) -> Cell {
+        return Cell {
+            c: ' ',
+            attributes: Attributes {
+                background: attributes.background,
+                ..Attributes::default()
+            },
+        };
+    }
+
+    /// the cell after a wide character, which has no text of its own
+    pub fn spacer(attributes: Attributes) -> Cell {
+        return Cell {
+            c: SPACER,
+            attributes: attributes,
+        };
+    }
+
+    pub fn is_blank(&self) -> bool {
+        return self.c == ' ' && self.attributes == Attributes::default();
+    }
+
+    pub fn is_spacer(&self) -> bool {
+        return self.c == SPACER;
+    }
+}
+
+/// the columns a character takes, 2 for wide characters like CJK
+/// and 0 for combining marks and control characters
+pub fn char_width(c: char) -> usize {
+    return c.width().unwrap_or(0);
+}
+
+/// the columns a string takes
+pub fn text_width(text: &str) -> usize {
+    return text.chars().map(char_width).sum();
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        return Cell::blank(Attributes::default());
+    }
+}
+
+/// a run of cells in a row that are drawn the same way
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    pub text: String,
+    pub attributes: Attributes,
+}
@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+use super::cell::{Attributes, Cell, Run};
+
+#[derive(Clone, Debug)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// the text continues on the next row because it was too long
+    pub wrapped: bool,
+}
+
+impl Row {
+    pub fn new(columns: usize, attributes: Attributes) -> Row {
+        return Row {
+            cells: vec![Cell::blank(attributes); columns],
+            wrapped: false,
+        };
+    }
+
+    pub fn resize(&mut self, columns: usize) {
+        self.split_wide(columns, columns, Attributes::default());
+        self.cells.resize(columns, Cell::default());
+    }
+
+    pub fn clear(&mut self, attributes: Attributes) {
+        self.erase(0, self.cells.len(), attributes);
+        self.wrapped = false;
+    }
+
+    /// blank the cells in `start..end`
+    pub fn erase(&mut self, start: usize, end: usize, attributes: Attributes) {
+        let end = end.min(self.cells.len());
+        self.split_wide(start, end, attributes);
+        for cell in &mut self.cells[start.min(end)..end] {
+            *cell = Cell::blank(attributes);
+        }
+    }
+
+    /// blank the other half of wide characters that changing `start..end` cuts,
+    /// an empty range is a cut between two cells
+    pub fn split_wide(&mut self, start: usize, end: usize, attributes: Attributes) {
+        if start > 0 && start < self.cells.len() && self.cells[start].is_spacer() {
+            self.cells[start - 1] = Cell::blank(attributes);
+        }
+        if end < self.cells.len() && self.cells[end].is_spacer() {
+            self.cells[end] = Cell::blank(attributes);
+        }
+    }
+
+    /// the row's characters without trailing blanks
+    pub fn text(&self) -> String {
+        return self.cells[..self.len()]
+            .iter()
+            .filter(|cell| !cell.is_spacer())
+            .map(|cell| cell.c)
+            .collect();
+    }
+
+    /// the cells grouped by attributes, without trailing blanks
+    /// the cells covered by wide characters add no text
+    pub fn runs(&self) -> Vec<Run> {
+        let mut runs: Vec<Run> = Vec::new();
+        for cell in &self.cells[..self.len()] {
+            if cell.is_spacer() {
+                continue;
+            }
+            if let Some(run) = runs.last_mut() {
+                if run.attributes == cell.attributes {
+                    run.text.push(cell.c);
+                    continue;
+                }
+            }
+            runs.push(Run {
+                text: cell.c.to_string(),
+                attributes: cell.attributes,
+            });
+        }
+        return runs;
+    }
+
+    /// the number of cells up to the last one that isn't blank
+    fn len(&self) -> usize {
+        return self
+            .cells
+            .iter()
+            .rposition(|cell| !cell.is_blank())
+            .map(|i| i + 1)
+            .unwrap_or(0);
+    }
+}
+
+/// the rows of one screen
+pub struct Grid {
+    rows: VecDeque<Row>,
+    columns: usize,
+}
+
+impl Grid {
+    pub fn new(rows: usize, columns: usize) -> Grid {
+        return Grid {
+            rows: (0..rows)
+                .map(|_| Row::new(columns, Attributes::default()))
+                .collect(),
+            columns: columns,
+        };
+    }
+
+    pub fn rows(&self) -> usize {
+        return self.rows.len();
+    }
+
+    pub fn columns(&self) -> usize {
+        return self.columns;
+    }
+
+    pub fn row(&self, row: usize) -> &Row {
+        return &self.rows[row];
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut Row {
+        return &mut self.rows[row];
+    }
+
+    pub fn cell_mut(&mut self, row: usize, column: usize) -> &mut Cell {
+        return &mut self.rows[row].cells[column];
+    }
+
+    /// move the rows in `top..=bottom` up, filling the bottom with blank rows
+    /// returns the rows that were pushed out of the region
+    pub fn scroll_up(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        count: usize,
+        attributes: Attributes,
+    ) -> Vec<Row> {
+        let count = count.min(bottom + 1 - top);
+        let mut removed = Vec::with_capacity(count);
+
+        // scrolling the whole screen is cheap on a deque
+        if top == 0 && bottom + 1 == self.rows.len() {
+            for _ in 0..count {
+                removed.extend(self.rows.pop_front());
+                self.rows.push_back(Row::new(self.columns, attributes));
+            }
+            return removed;
+        }
+
+        for _ in 0..count {
+            if let Some(row) = self.rows.remove(top) {
+                removed.push(row);
+            }
+            self.rows
+                .insert(bottom, Row::new(self.columns, attributes));
+        }
+        return removed;
+    }
+
+    /// move the rows in `top..=bottom` down, filling the top with blank rows
+    pub fn scroll_down(&mut self, top: usize, bottom: usize, count: usize, attributes: Attributes) {
+        let count = count.min(bottom + 1 - top);
+        for _ in 0..count {
+            self.rows.remove(bottom);
+            self.rows.insert(top, Row::new(self.columns, attributes));
+        }
+    }
+
+    /// call `f` with the attributes of every cell, to change them in place
+    pub fn for_each_attributes<F: FnMut(&mut Attributes)>(&mut self, f: &mut F) {
+        for row in self.rows.iter_mut() {
+            for cell in row.cells.iter_mut() {
+                f(&mut cell.attributes);
+            }
+        }
+    }
+
+    pub fn clear(&mut self, attributes: Attributes) {
+        for row in self.rows.iter_mut() {
+            row.clear(attributes);
+        }
+    }
+
+    /// change the size, dropping `from_top` rows off the top first
+    pub fn resize(&mut self, rows: usize, columns: usize, from_top: usize) -> Vec<Row> {
+        let removed: Vec<Row> = self.rows.drain(..from_top.min(self.rows.len())).collect();
+
+        self.rows.truncate(rows);
+        while self.rows.len() < rows {
+            self.rows.push_back(Row::new(columns, Attributes::default()));
+        }
+        for row in self.rows.iter_mut() {
+            row.resize(columns);
+        }
+        self.columns = columns;
+
+        return removed;
+    }
+}
@@ -0,0 +1,193 @@
+use std::env;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// url schemes that are linked when they show up in the output
+const SCHEMES: [&str; 4] = ["https://", "http://", "ftp://", "file://"];
+
+/// a link in a row of text, covering the columns `start..end`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub start: usize,
+    pub end: usize,
+    pub url: String,
+}
+
+impl Link {
+    pub fn contains(&self, column: usize) -> bool {
+        return self.start <= column && column < self.end;
+    }
+}
+
+/// find urls and paths to files that exist in a line of text,
+/// relative paths are looked up in `cwd`
+pub fn detect(text: &str, cwd: Option<&Path>) -> Vec<Link> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut links = Vec::new();
+
+    let mut start = 0;
+    while start < chars.len() {
+        if chars[start].is_whitespace() {
+            start += 1;
+            continue;
+        }
+        let end = chars[start..]
+            .iter()
+            .position(|c| c.is_whitespace())
+            .map(|len| start + len)
+            .unwrap_or(chars.len());
+
+        if let Some(link) = detect_word(&chars, start, end, cwd) {
+            links.push(link);
+        }
+        start = end;
+    }
+
+    return links;
+}
+
+/// a link in `chars[start..end]`, a word without whitespace
+fn detect_word(chars: &[char], start: usize, end: usize, cwd: Option<&Path>) -> Option<Link> {
+    let (start, end) = trim_punctuation(chars, start, end);
+    let word: String = chars[start..end].iter().collect();
+
+    // a url can follow something like `href=`
+    for scheme in SCHEMES.iter() {
+        if let Some(index) = word.find(scheme) {
+            if word.len() == index + scheme.len() {
+                return None;
+            }
+            let start = start + word[..index].chars().count();
+            return Some(Link {
+                start: start,
+                end: end,
+                url: word[index..].to_string(),
+            });
+        }
+    }
+
+    if !word.contains('/') {
+        return None;
+    }
+    // compilers and grep add `:line:column` after the path
+    let path = strip_position(&word);
+    let path = resolve(path, cwd)?;
+    if !path.exists() {
+        return None;
+    }
+    return Some(Link {
+        start: start,
+        end: end,
+        url: file_url(&path),
+    });
+}
+
+/// shrink `start..end` to leave out surrounding quotes and brackets
+/// and punctuation that ends a sentence
+fn trim_punctuation(chars: &[char], mut start: usize, mut end: usize) -> (usize, usize) {
+    while start < end && "\"'`<([{".contains(chars[start]) {
+        start += 1;
+    }
+    while start < end {
+        let trim = match chars[end - 1] {
+            '.' | ',' | ';' | ':' | '!' | '?' | '"' | '\'' | '`' | '>' => true,
+            // keep closing brackets that are part of the link, like in wikipedia urls
+            ')' => !unbalanced(&chars[start..end - 1], '(', ')'),
+            ']' => !unbalanced(&chars[start..end - 1], '[', ']'),
+            '}' => !unbalanced(&chars[start..end - 1], '{', '}'),
+            _ => false,
+        };
+        if !trim {
+            break;
+        }
+        end -= 1;
+    }
+    return (start, end);
+}
+
+/// true if `chars` has an `open` without a matching `close`
+fn unbalanced(chars: &[char], open: char, close: char) -> bool {
+    let opened = chars.iter().filter(|&&c| c == open).count();
+    let closed = chars.iter().filter(|&&c| c == close).count();
+    return opened > closed;
+}
+
+/// the path without a trailing `:line` or `:line:column`
+fn strip_position(word: &str) -> &str {
+    let mut path = word;
+    for _ in 0..2 {
+        match path.rfind(':') {
+            Some(index)
+                if index + 1 < path.len()
+                    && path[index + 1..].chars().all(|c| c.is_ascii_digit()) =>
+            {
+                path = &path[..index];
+            }
+            _ => break,
+        }
+    }
+    return path;
+}
+
+fn resolve(path: &str, cwd: Option<&Path>) -> Option<PathBuf> {
+    if path.starts_with("~/") {
+        return env::var_os("HOME").map(|home| PathBuf::from(home).join(&path[2..]));
+    }
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Some(path.to_path_buf());
+    }
+    return cwd.map(|cwd| cwd.join(path));
+}
+
+/// a `file://` url for an absolute path, with reserved characters percent encoded
+pub fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    return url;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(text: &str) -> Vec<(usize, usize, String)> {
+        return detect(text, Some(Path::new("/")))
+            .into_iter()
+            .map(|link| (link.start, link.end, link.url))
+            .collect();
+    }
+
+    #[test]
+    fn test_urls() {
+        assert_eq!(
+            urls("see https://example.com/a_(b), or (http://x.org)."),
+            vec![
+                (4, 29, "https://example.com/a_(b)".to_string()),
+                (35, 47, "http://x.org".to_string()),
+            ]
+        );
+        assert_eq!(
+            urls("href=\"https://é.com\""),
+            vec![(6, 19, "https://é.com".to_string())]
+        );
+        assert_eq!(urls("https:// and plain/words"), vec![]);
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            urls("error at tmp:3:14 in /tmp/, /missing/file"),
+            vec![(21, 26, "file:///tmp/".to_string())]
+        );
+        assert_eq!(urls("  etc/hosts:12:3"), vec![(2, 16, "file:///etc/hosts".to_string())]);
+        assert_eq!(file_url(Path::new("/a b/%")), "file:///a%20b/%25");
+    }
+}
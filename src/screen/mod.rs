@@ -0,0 +1,1446 @@
+pub mod cell;
+pub mod grid;
+pub mod links;
+pub mod scrollback;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::mem;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+use parser::{Action, Parser, Sequence};
+
+pub use self::cell::{char_width, text_width, Attributes, Cell, Color, Run};
+pub use self::grid::Row;
+pub use self::links::Link;
+pub use self::scrollback::{Scrollback, DEFAULT_SCROLLBACK_LINES};
+
+use self::grid::Grid;
+
+pub const DEFAULT_ROWS: usize = 24;
+pub const DEFAULT_COLUMNS: usize = 80;
+
+const TAB_WIDTH: usize = 8;
+/// hyperlink targets kept before the ones nothing refers to anymore are dropped
+const LINKS_BEFORE_PRUNING: usize = 1024;
+/// texts whose detected links are remembered before they're all forgotten
+const DETECTED_TEXTS: usize = 1024;
+
+/// DEC special graphics, selected with `ESC ( 0`, for the characters `_` to `~`
+const LINE_DRAWING: [char; 32] = [
+    ' ', '◆', '▒', '␉', '␌', '␍', '␊', '°', '±', '␤', '␋', '┘', '┐', '┌', '└', '┼', '⎺', '⎻', '─',
+    '⎼', '⎽', '├', '┤', '┴', '┬', '│', '≤', '≥', 'π', '≠', '£', '·',
+];
+
+/// modes set with SM/RM and DECSET/DECRST
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Modes {
+    /// DECAWM, wrap to the next line at the right margin
+    pub autowrap: bool,
+    /// DECOM, cursor positions are relative to the scroll region
+    pub origin: bool,
+    /// IRM, printing shifts the rest of the line right
+    pub insert: bool,
+    /// DECTCEM
+    pub cursor_visible: bool,
+    /// DECCKM, arrow keys send application sequences
+    pub application_cursor: bool,
+    /// pasted text should be wrapped in `CSI 200 ~` and `CSI 201 ~`
+    pub bracketed_paste: bool,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        return Modes {
+            autowrap: true,
+            origin: false,
+            insert: false,
+            cursor_visible: true,
+            application_cursor: false,
+            bracketed_paste: false,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Cursor {
+    row: usize,
+    column: usize,
+    attributes: Attributes,
+    /// a character was printed in the last column,
+    /// the next one wraps if autowrap is on
+    wrap_pending: bool,
+    /// G0 is the DEC line drawing set
+    line_drawing: bool,
+}
+
+/// the state of a terminal: a grid of cells, a cursor and modes,
+/// updated by the parsed output of the shell
+pub struct Screen {
+    primary: Grid,
+    alternate: Grid,
+    alternate_active: bool,
+    /// rows scrolled off the top of the primary screen
+    scrollback: Scrollback,
+    rows: usize,
+    columns: usize,
+    cursor: Cursor,
+    /// DECSC for the primary and alternate screen
+    saved_cursor: [Option<Cursor>; 2],
+    /// rows `scroll_top..=scroll_bottom` scroll
+    scroll_top: usize,
+    scroll_bottom: usize,
+    tabs: Vec<bool>,
+    modes: Modes,
+    /// the character printed last, for REP
+    last_printed: Option<char>,
+    parser: Parser,
+    /// replies to queries, to be written back to the shell
+    responses: String,
+    /// set with OSC 0 or OSC 2
+    title: Option<String>,
+    /// the working directory reported with OSC 7
+    cwd: Option<PathBuf>,
+    /// the targets of OSC 8 hyperlinks, cells refer to them by index
+    links: Vec<String>,
+    /// how many links were left the last time unused ones were dropped
+    live_links: usize,
+    /// the links detected in the text of rows, finding paths looks for files on disk
+    /// so it's done once for each text until the working directory changes
+    detected: RefCell<HashMap<String, Vec<Link>>>,
+}
+
+impl Screen {
+    pub fn new(rows: usize, columns: usize) -> Screen {
+        let rows = rows.max(1);
+        let columns = columns.max(1);
+        return Screen {
+            primary: Grid::new(rows, columns),
+            alternate: Grid::new(rows, columns),
+            alternate_active: false,
+            scrollback: Scrollback::new(DEFAULT_SCROLLBACK_LINES),
+            rows: rows,
+            columns: columns,
+            cursor: Cursor::default(),
+            saved_cursor: [None, None],
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            tabs: default_tabs(columns),
+            modes: Modes::default(),
+            last_printed: None,
+            parser: Parser::new(),
+            responses: String::new(),
+            title: None,
+            cwd: None,
+            links: Vec::new(),
+            live_links: 0,
+            detected: RefCell::new(HashMap::new()),
+        };
+    }
+
+    pub fn rows(&self) -> usize {
+        return self.rows;
+    }
+
+    pub fn columns(&self) -> usize {
+        return self.columns;
+    }
+
+    pub fn row(&self, row: usize) -> &Row {
+        return self.grid().row(row);
+    }
+
+    /// `(row, column)`, starting at 0
+    pub fn cursor(&self) -> (usize, usize) {
+        return (self.cursor.row, self.cursor.column);
+    }
+
+    pub fn modes(&self) -> &Modes {
+        return &self.modes;
+    }
+
+    pub fn alternate_active(&self) -> bool {
+        return self.alternate_active;
+    }
+
+    /// the window title set by the program, if any
+    pub fn title(&self) -> Option<&str> {
+        return self.title.as_ref().map(String::as_str);
+    }
+
+    /// the working directory the shell last reported
+    pub fn cwd(&self) -> Option<&Path> {
+        return self.cwd.as_ref().map(PathBuf::as_path);
+    }
+
+    pub fn scrollback(&self) -> &Scrollback {
+        return &self.scrollback;
+    }
+
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scrollback.set_limit(lines);
+    }
+
+    /// how many lines the view can be scrolled back,
+    /// the alternate screen has no scrollback
+    pub fn max_offset(&self) -> usize {
+        if self.alternate_active {
+            return 0;
+        }
+        return self.scrollback.len();
+    }
+
+    /// the runs of a row of the view scrolled back `offset` lines into the scrollback
+    pub fn view_runs(&self, offset: usize, row: usize) -> Vec<Run> {
+        let offset = offset.min(self.max_offset());
+        if row < offset {
+            return self
+                .scrollback
+                .line(self.scrollback.len() - offset + row)
+                .runs();
+        }
+        return self.runs(row - offset);
+    }
+
+    /// the text of the view scrolled back `offset` lines, from the `(row, column)`
+    /// `start` up to `end`, with SGR sequences for the attributes if `styled`
+    pub fn view_text(
+        &self,
+        offset: usize,
+        start: (usize, usize),
+        end: (usize, usize),
+        styled: bool,
+    ) -> String {
+        let reset = Attributes::default().sgr();
+        let mut sgr = reset.clone();
+        let mut text = String::new();
+        for row in start.0..(end.0 + 1).min(self.rows) {
+            if row > start.0 {
+                text.push('\n');
+            }
+            let from = if row == start.0 { start.1 } else { 0 };
+            let to = if row == end.0 { end.1 } else { self.columns };
+
+            let mut column = 0;
+            for run in self.view_runs(offset, row) {
+                let run_sgr = run.attributes.sgr();
+                for c in run.text.chars() {
+                    if from <= column && column < to {
+                        if styled && run_sgr != sgr {
+                            text.push_str(&run_sgr);
+                            sgr = run_sgr.clone();
+                        }
+                        text.push(c);
+                    }
+                    column += char_width(c);
+                }
+            }
+        }
+        if sgr != reset {
+            text.push_str(&reset);
+        }
+        return text;
+    }
+
+    /// the hyperlinks and detected urls and paths in a row of the view
+    /// scrolled back `offset` lines, in the order they appear
+    pub fn view_links(&self, offset: usize, row: usize) -> Vec<Link> {
+        let runs = self.view_runs(offset, row);
+        let mut links: Vec<Link> = Vec::new();
+        let mut text = String::new();
+        let mut column = 0;
+        for run in &runs {
+            let len = text_width(&run.text);
+            if let Some(id) = run.attributes.link {
+                let url = &self.links[id as usize];
+                let extends = match links.last() {
+                    Some(link) => link.end == column && link.url == *url,
+                    None => false,
+                };
+                if extends {
+                    links.last_mut().expect("no link to extend").end += len;
+                } else {
+                    links.push(Link {
+                        start: column,
+                        end: column + len,
+                        url: url.clone(),
+                    });
+                }
+            }
+            text.push_str(&run.text);
+            column += len;
+        }
+
+        // explicit links take priority over what looks like one,
+        // detection counts characters rather than columns
+        let detected: Vec<Link> = self
+            .detect_links(&text)
+            .into_iter()
+            .map(|found| Link {
+                start: column_at(&runs, found.start),
+                end: column_at(&runs, found.end),
+                ..found
+            })
+            .filter(|found| {
+                !links
+                    .iter()
+                    .any(|link| found.start < link.end && link.start < found.end)
+            })
+            .collect();
+        links.extend(detected);
+        links.sort_by_key(|link| link.start);
+        return links;
+    }
+
+    /// the urls and paths to files in a row's text, found once for each text
+    fn detect_links(&self, text: &str) -> Vec<Link> {
+        let mut detected = self.detected.borrow_mut();
+        if let Some(links) = detected.get(text) {
+            return links.clone();
+        }
+        if detected.len() >= DETECTED_TEXTS {
+            detected.clear();
+        }
+        let links = links::detect(text, self.cwd());
+        detected.insert(text.to_string(), links.clone());
+        return links;
+    }
+
+    /// the cells of a row grouped by attributes, without trailing blanks
+    pub fn runs(&self, row: usize) -> Vec<Run> {
+        return self.row(row).runs();
+    }
+
+    /// like `runs`, with the cell under the cursor inverted if the cursor is shown
+    /// for renderers without a cursor of their own
+    pub fn runs_with_cursor(&self, row: usize) -> Vec<Run> {
+        if row != self.cursor.row || !self.modes.cursor_visible {
+            return self.runs(row);
+        }
+
+        let mut cursor_row = self.row(row).clone();
+        // the cursor covers the whole of a wide character
+        let mut column = self.cursor.column;
+        if column > 0 && cursor_row.cells[column].is_spacer() {
+            column -= 1;
+        }
+        let cell = &mut cursor_row.cells[column];
+        cell.attributes.inverse = !cell.attributes.inverse;
+        return cursor_row.runs();
+    }
+
+    /// the visible text, one line per row
+    pub fn text(&self) -> String {
+        return (0..self.rows)
+            .map(|row| self.row(row).text())
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+
+    /// replies to status queries that should be written to the shell
+    pub fn take_responses(&mut self) -> String {
+        return mem::replace(&mut self.responses, String::new());
+    }
+
+    /// process terminal output
+    pub fn write(&mut self, text: &str) {
+        let actions = self.parser.parse(text);
+        self.perform(actions);
+    }
+
+    pub fn resize(&mut self, rows: usize, columns: usize) {
+        let rows = rows.max(1);
+        let columns = columns.max(1);
+        if rows == self.rows && columns == self.columns {
+            return;
+        }
+
+        // keep the cursor on screen by dropping rows off the top
+        let from_top = (self.cursor.row + 1).saturating_sub(rows);
+        for row in self.primary.resize(rows, columns, from_top) {
+            self.scrollback.push(&row);
+        }
+        self.alternate.resize(rows, columns, from_top);
+
+        self.rows = rows;
+        self.columns = columns;
+        self.cursor.row -= from_top;
+        self.cursor.column = self.cursor.column.min(columns - 1);
+        self.cursor.wrap_pending = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.tabs = default_tabs(columns);
+    }
+
+    fn grid(&self) -> &Grid {
+        if self.alternate_active {
+            return &self.alternate;
+        }
+        return &self.primary;
+    }
+
+    fn grid_mut(&mut self) -> &mut Grid {
+        if self.alternate_active {
+            return &mut self.alternate;
+        }
+        return &mut self.primary;
+    }
+
+    fn perform(&mut self, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Print(text) => {
+                    for c in text.chars() {
+                        self.print(c);
+                    }
+                }
+                Action::Execute(c) => self.execute(c),
+                Action::Csi(sequence) => self.csi(&sequence),
+                Action::Esc(sequence) => self.esc(&sequence),
+                Action::Osc(params) => self.osc(&params),
+                // handled by later consumers
+                Action::Dcs(_, _) => (),
+            }
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        let c = match c {
+            '_'..='~' if self.cursor.line_drawing => LINE_DRAWING[c as usize - '_' as usize],
+            c => c,
+        };
+
+        // a cell holds a single character, so combining marks are dropped
+        let width = match char_width(c) {
+            0 => return,
+            width => width.min(self.columns),
+        };
+
+        if self.cursor.wrap_pending && self.modes.autowrap {
+            self.wrap();
+        }
+        self.cursor.wrap_pending = false;
+
+        // a wide character that doesn't fit at the end of the line goes on the next one
+        if self.cursor.column + width > self.columns {
+            if self.modes.autowrap {
+                let (row, column) = (self.cursor.row, self.cursor.column);
+                let attributes = self.cursor.attributes;
+                self.grid_mut()
+                    .row_mut(row)
+                    .erase(column, column + width, attributes);
+                self.wrap();
+            } else {
+                self.cursor.column = self.columns - width;
+            }
+        }
+
+        if self.modes.insert {
+            self.insert_cells(width);
+        }
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        let attributes = self.cursor.attributes;
+        self.grid_mut()
+            .row_mut(row)
+            .split_wide(column, column + width, attributes);
+        *self.grid_mut().cell_mut(row, column) = Cell {
+            c: c,
+            attributes: attributes,
+        };
+        for spacer in column + 1..column + width {
+            *self.grid_mut().cell_mut(row, spacer) = Cell::spacer(attributes);
+        }
+        self.last_printed = Some(c);
+
+        if column + width < self.columns {
+            self.cursor.column += width;
+        } else {
+            self.cursor.column = self.columns - 1;
+            self.cursor.wrap_pending = true;
+        }
+    }
+
+    /// continue on the next line, marking the current one as wrapped
+    fn wrap(&mut self) {
+        let row = self.cursor.row;
+        self.grid_mut().row_mut(row).wrapped = true;
+        self.cursor.column = 0;
+        self.linefeed();
+    }
+
+    fn execute(&mut self, c: char) {
+        match c {
+            // backspace
+            '\x08' => self.move_to_column(self.cursor.column.saturating_sub(1)),
+            '\t' => self.tab_forward(1),
+            '\n' | '\x0b' | '\x0c' | '\u{84}' => self.linefeed(),
+            '\r' => self.move_to_column(0),
+            // NEL
+            '\u{85}' => {
+                self.move_to_column(0);
+                self.linefeed();
+            }
+            // HTS
+            '\u{88}' => self.tabs[self.cursor.column] = true,
+            // RI
+            '\u{8d}' => self.reverse_index(),
+            _ => (),
+        }
+    }
+
+    fn osc(&mut self, params: &[String]) {
+        let (command, rest) = match params.split_first() {
+            Some((command, rest)) => (command, rest),
+            None => return,
+        };
+        match command.as_str() {
+            // the icon name and title, or just the title
+            "0" | "2" => {
+                // a title may contain the separator
+                let title = rest.join(";");
+                self.title = if title.is_empty() { None } else { Some(title) };
+            }
+            "7" => {
+                if let Some(cwd) = rest.first().and_then(|url| parse_file_url(url)) {
+                    // relative paths lead somewhere else now
+                    if self.cwd.as_ref() != Some(&cwd) {
+                        self.detected.borrow_mut().clear();
+                    }
+                    self.cwd = Some(cwd);
+                }
+            }
+            // `8 ; params ; url` starts a hyperlink, an empty url ends it
+            "8" => {
+                let url = rest.get(1..).map(|url| url.join(";")).unwrap_or_default();
+                self.cursor.attributes.link = if url.is_empty() {
+                    None
+                } else {
+                    Some(self.link_id(url))
+                };
+            }
+            _ => (),
+        }
+    }
+
+    /// the index of a hyperlink target, reusing the last one if it's the same
+    fn link_id(&mut self, url: String) -> u32 {
+        if self.links.last() != Some(&url) {
+            // links leave with the lines the scrollback drops,
+            // so the unused ones are dropped once they could outnumber the rest
+            if self.links.len() >= LINKS_BEFORE_PRUNING.max(2 * self.live_links) {
+                self.prune_links();
+            }
+            self.links.push(url);
+        }
+        return (self.links.len() - 1) as u32;
+    }
+
+    /// drop the hyperlink targets no cell, line or cursor refers to and renumber the rest
+    fn prune_links(&mut self) {
+        let mut used = vec![false; self.links.len()];
+        self.for_each_attributes(&mut |attributes| {
+            if let Some(id) = attributes.link {
+                used[id as usize] = true;
+            }
+        });
+
+        let mut ids = Vec::with_capacity(used.len());
+        let mut links = Vec::new();
+        for (url, used) in mem::replace(&mut self.links, Vec::new()).into_iter().zip(used) {
+            ids.push(links.len() as u32);
+            if used {
+                links.push(url);
+            }
+        }
+        self.for_each_attributes(&mut |attributes| {
+            if let Some(ref mut id) = attributes.link {
+                *id = ids[*id as usize];
+            }
+        });
+
+        self.live_links = links.len();
+        self.links = links;
+    }
+
+    /// call `f` with every set of attributes that can refer to a hyperlink
+    fn for_each_attributes<F: FnMut(&mut Attributes)>(&mut self, f: &mut F) {
+        self.primary.for_each_attributes(f);
+        self.alternate.for_each_attributes(f);
+        self.scrollback.for_each_attributes(f);
+        f(&mut self.cursor.attributes);
+        for saved in self.saved_cursor.iter_mut() {
+            if let Some(ref mut cursor) = *saved {
+                f(&mut cursor.attributes);
+            }
+        }
+    }
+
+    fn esc(&mut self, sequence: &Sequence) {
+        match (sequence.intermediates.first(), sequence.action) {
+            (None, 'D') => self.linefeed(),
+            (None, 'E') => {
+                self.move_to_column(0);
+                self.linefeed();
+            }
+            (None, 'H') => self.tabs[self.cursor.column] = true,
+            (None, 'M') => self.reverse_index(),
+            (None, '7') => self.save_cursor(),
+            (None, '8') => self.restore_cursor(),
+            (None, 'c') => self.reset(),
+            (Some(&'('), '0') => self.cursor.line_drawing = true,
+            (Some(&'('), _) => self.cursor.line_drawing = false,
+            // DECALN, fill the screen with E
+            (Some(&'#'), '8') => {
+                let attributes = self.cursor.attributes;
+                for row in 0..self.rows {
+                    for column in 0..self.columns {
+                        *self.grid_mut().cell_mut(row, column) = Cell {
+                            c: 'E',
+                            attributes: attributes,
+                        };
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn csi(&mut self, sequence: &Sequence) {
+        let private = sequence.has_intermediate('?');
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        let count = sequence.param(0, 1) as usize;
+
+        match sequence.action {
+            '@' if !private => self.insert_cells(count),
+            'A' => self.move_up(count),
+            'B' | 'e' => self.move_down(count),
+            'C' | 'a' => self.move_to_column(column + count),
+            'D' => self.move_to_column(column.saturating_sub(count)),
+            'E' => {
+                self.move_down(count);
+                self.move_to_column(0);
+            }
+            'F' => {
+                self.move_up(count);
+                self.move_to_column(0);
+            }
+            'G' | '`' => self.move_to_column(count - 1),
+            'H' | 'f' => self.move_to(
+                sequence.param(0, 1) as usize - 1,
+                sequence.param(1, 1) as usize - 1,
+            ),
+            'I' => self.tab_forward(count),
+            'J' => self.erase_display(sequence.param(0, 0)),
+            'K' => self.erase_line(sequence.param(0, 0)),
+            'L' => self.insert_lines(count),
+            'M' => self.delete_lines(count),
+            'P' => self.delete_cells(count),
+            'S' if !private => self.scroll_up(count),
+            'T' => self.scroll_down(count),
+            'X' => {
+                let attributes = self.cursor.attributes;
+                self.grid_mut()
+                    .row_mut(row)
+                    .erase(column, column + count, attributes);
+                self.cursor.wrap_pending = false;
+            }
+            'Z' => self.tab_backward(count),
+            'b' => {
+                if let Some(c) = self.last_printed {
+                    for _ in 0..count {
+                        self.print(c);
+                    }
+                }
+            }
+            'c' if sequence.intermediates.is_empty() => {
+                // a VT102
+                self.responses.push_str("\x1b[?6c");
+            }
+            'd' => {
+                let row = self.origin_offset() + count - 1;
+                self.move_to_row(row);
+            }
+            'g' => match sequence.param(0, 0) {
+                0 => self.tabs[column] = false,
+                3 => self.tabs = vec![false; self.columns],
+                _ => (),
+            },
+            'h' => self.set_modes(sequence, true),
+            'l' => self.set_modes(sequence, false),
+            'm' if sequence.intermediates.is_empty() => self.sgr(&sequence.params),
+            'n' if !private => match sequence.param(0, 0) {
+                5 => self.responses.push_str("\x1b[0n"),
+                6 => {
+                    let report_row = row - self.origin_offset() + 1;
+                    self.responses
+                        .push_str(&format!("\x1b[{};{}R", report_row, column + 1));
+                }
+                _ => (),
+            },
+            'r' if !private => {
+                let top = sequence.param(0, 1) as usize - 1;
+                let bottom = (sequence.param(1, self.rows as u16) as usize).min(self.rows) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            }
+            's' if !private => self.save_cursor(),
+            'u' if !private => self.restore_cursor(),
+            _ => (),
+        }
+    }
+
+    fn set_modes(&mut self, sequence: &Sequence, enable: bool) {
+        let private = sequence.has_intermediate('?');
+        for param in &sequence.params {
+            match (private, param.first().cloned().unwrap_or(0)) {
+                (false, 4) => self.modes.insert = enable,
+                (true, 1) => self.modes.application_cursor = enable,
+                (true, 6) => {
+                    self.modes.origin = enable;
+                    self.move_to(0, 0);
+                }
+                (true, 7) => self.modes.autowrap = enable,
+                (true, 25) => self.modes.cursor_visible = enable,
+                (true, 47) | (true, 1047) => {
+                    if !enable && self.alternate_active && param[0] == 1047 {
+                        self.alternate.clear(Attributes::default());
+                    }
+                    self.alternate_active = enable;
+                }
+                (true, 1048) => {
+                    if enable {
+                        self.save_cursor();
+                    } else {
+                        self.restore_cursor();
+                    }
+                }
+                (true, 1049) => {
+                    if enable {
+                        self.save_cursor();
+                        self.alternate_active = true;
+                        self.alternate.clear(Attributes::default());
+                    } else {
+                        self.alternate_active = false;
+                        self.restore_cursor();
+                    }
+                }
+                (true, 2004) => self.modes.bracketed_paste = enable,
+                _ => (),
+            }
+        }
+    }
+
+    fn sgr(&mut self, params: &[Vec<u16>]) {
+        if params.is_empty() {
+            self.cursor.attributes = reset_attributes(self.cursor.attributes);
+            return;
+        }
+
+        let mut i = 0;
+        while i < params.len() {
+            let param = &params[i];
+            let attributes = &mut self.cursor.attributes;
+            match param[0] {
+                0 => *attributes = reset_attributes(*attributes),
+                1 => attributes.bold = true,
+                2 => attributes.dim = true,
+                3 => attributes.italic = true,
+                // `4:0` turns underline off, other styles are drawn as a single underline
+                4 => attributes.underline = param.get(1).map(|&style| style != 0).unwrap_or(true),
+                5 | 6 => attributes.blink = true,
+                7 => attributes.inverse = true,
+                8 => attributes.hidden = true,
+                9 => attributes.strikethrough = true,
+                21 => attributes.underline = true,
+                22 => {
+                    attributes.bold = false;
+                    attributes.dim = false;
+                }
+                23 => attributes.italic = false,
+                24 => attributes.underline = false,
+                25 => attributes.blink = false,
+                27 => attributes.inverse = false,
+                28 => attributes.hidden = false,
+                29 => attributes.strikethrough = false,
+                code @ 30..=37 => attributes.foreground = Color::Indexed(code as u8 - 30),
+                38 => {
+                    let (color, used) = extended_color(params, i);
+                    if let Some(color) = color {
+                        attributes.foreground = color;
+                    }
+                    i += used;
+                }
+                39 => attributes.foreground = Color::Default,
+                code @ 40..=47 => attributes.background = Color::Indexed(code as u8 - 40),
+                48 => {
+                    let (color, used) = extended_color(params, i);
+                    if let Some(color) = color {
+                        attributes.background = color;
+                    }
+                    i += used;
+                }
+                49 => attributes.background = Color::Default,
+                code @ 90..=97 => attributes.foreground = Color::Indexed(code as u8 - 90 + 8),
+                code @ 100..=107 => attributes.background = Color::Indexed(code as u8 - 100 + 8),
+                _ => (),
+            }
+            i += 1;
+        }
+    }
+
+    /// the first row cursor positions are relative to
+    fn origin_offset(&self) -> usize {
+        if self.modes.origin {
+            return self.scroll_top;
+        }
+        return 0;
+    }
+
+    fn move_to(&mut self, row: usize, column: usize) {
+        let row = row + self.origin_offset();
+        self.move_to_row(row);
+        self.move_to_column(column);
+    }
+
+    fn move_to_row(&mut self, row: usize) {
+        let (top, bottom) = if self.modes.origin {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.rows - 1)
+        };
+        self.cursor.row = row.max(top).min(bottom);
+        self.cursor.wrap_pending = false;
+    }
+
+    fn move_to_column(&mut self, column: usize) {
+        self.cursor.column = column.min(self.columns - 1);
+        self.cursor.wrap_pending = false;
+    }
+
+    /// move up, stopping at the top of the scroll region if the cursor is in it
+    fn move_up(&mut self, count: usize) {
+        let top = if self.cursor.row >= self.scroll_top {
+            self.scroll_top
+        } else {
+            0
+        };
+        self.cursor.row = self.cursor.row.saturating_sub(count).max(top);
+        self.cursor.wrap_pending = false;
+    }
+
+    fn move_down(&mut self, count: usize) {
+        let bottom = if self.cursor.row <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.rows - 1
+        };
+        self.cursor.row = (self.cursor.row + count).min(bottom);
+        self.cursor.wrap_pending = false;
+    }
+
+    fn linefeed(&mut self) {
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows {
+            self.cursor.row += 1;
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn reverse_index(&mut self) {
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let attributes = self.cursor.attributes;
+        let removed = self.grid_mut().scroll_up(top, bottom, count, attributes);
+
+        // only rows leaving the top of the primary screen are kept
+        if top == 0 && !self.alternate_active {
+            for row in &removed {
+                self.scrollback.push(row);
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let attributes = self.cursor.attributes;
+        self.grid_mut().scroll_down(top, bottom, count, attributes);
+    }
+
+    fn insert_lines(&mut self, count: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        let (bottom, attributes) = (self.scroll_bottom, self.cursor.attributes);
+        self.grid_mut().scroll_down(row, bottom, count, attributes);
+        self.move_to_column(0);
+    }
+
+    fn delete_lines(&mut self, count: usize) {
+        let row = self.cursor.row;
+        if row < self.scroll_top || row > self.scroll_bottom {
+            return;
+        }
+        let (bottom, attributes) = (self.scroll_bottom, self.cursor.attributes);
+        self.grid_mut().scroll_up(row, bottom, count, attributes);
+        self.move_to_column(0);
+    }
+
+    fn insert_cells(&mut self, count: usize) {
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        let attributes = self.cursor.attributes;
+        {
+            let line = self.grid_mut().row_mut(row);
+            let len = line.cells.len();
+            let count = count.min(len - column);
+            // wide characters split by the insert or pushed half off the end
+            line.split_wide(column, column, attributes);
+            line.split_wide(len - count, len - count, attributes);
+            let cells = &mut line.cells;
+            cells.truncate(len - count);
+            for _ in 0..count {
+                cells.insert(column, Cell::blank(attributes));
+            }
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn delete_cells(&mut self, count: usize) {
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        let attributes = self.cursor.attributes;
+        {
+            let line = self.grid_mut().row_mut(row);
+            let count = count.min(line.cells.len() - column);
+            line.split_wide(column, column + count, attributes);
+            let cells = &mut line.cells;
+            cells.drain(column..column + count);
+            for _ in 0..count {
+                cells.push(Cell::blank(attributes));
+            }
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let (row, attributes) = (self.cursor.row, self.cursor.attributes);
+        let rows = self.rows;
+        match mode {
+            0 => {
+                self.erase_line(0);
+                for row in row + 1..rows {
+                    self.grid_mut().row_mut(row).clear(attributes);
+                }
+            }
+            1 => {
+                self.erase_line(1);
+                for row in 0..row {
+                    self.grid_mut().row_mut(row).clear(attributes);
+                }
+            }
+            2 => self.grid_mut().clear(attributes),
+            3 => self.scrollback.clear(),
+            _ => (),
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let (row, column) = (self.cursor.row, self.cursor.column);
+        let (columns, attributes) = (self.columns, self.cursor.attributes);
+        {
+            let line = self.grid_mut().row_mut(row);
+            match mode {
+                0 => {
+                    line.erase(column, columns, attributes);
+                    line.wrapped = false;
+                }
+                1 => line.erase(0, column + 1, attributes),
+                2 => line.clear(attributes),
+                _ => (),
+            }
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn tab_forward(&mut self, count: usize) {
+        for _ in 0..count {
+            let next = (self.cursor.column + 1..self.columns).find(|&column| self.tabs[column]);
+            self.cursor.column = next.unwrap_or(self.columns - 1);
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn tab_backward(&mut self, count: usize) {
+        for _ in 0..count {
+            let previous = (0..self.cursor.column).rev().find(|&column| self.tabs[column]);
+            self.cursor.column = previous.unwrap_or(0);
+        }
+        self.cursor.wrap_pending = false;
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor[self.alternate_active as usize] = Some(self.cursor);
+    }
+
+    fn restore_cursor(&mut self) {
+        match self.saved_cursor[self.alternate_active as usize] {
+            Some(cursor) => self.cursor = cursor,
+            None => {
+                self.cursor = Cursor::default();
+                self.modes.origin = false;
+            }
+        }
+        self.cursor.row = self.cursor.row.min(self.rows - 1);
+        self.cursor.column = self.cursor.column.min(self.columns - 1);
+    }
+
+    /// RIS, everything but the size goes back to how it started
+    fn reset(&mut self) {
+        let (rows, columns) = (self.rows, self.columns);
+        let parser = mem::replace(&mut self.parser, Parser::new());
+        let scrollback = mem::replace(&mut self.scrollback, Scrollback::new(0));
+        let links = mem::replace(&mut self.links, Vec::new());
+        *self = Screen::new(rows, columns);
+        // the parser may be partway through the rest of the output
+        self.parser = parser;
+        // the scrollback can still have links
+        self.scrollback = scrollback;
+        self.links = links;
+        self.prune_links();
+    }
+}
+
+/// the column where the character `offset` characters into a row's `runs` starts,
+/// counting a column for each character past the end
+pub fn column_at(runs: &[Run], offset: usize) -> usize {
+    let mut chars = runs.iter().flat_map(|run| run.text.chars());
+    return (0..offset).map(|_| chars.next().map_or(1, char_width)).sum();
+}
+
+/// how many characters of a row's `runs` start before `column`,
+/// counting a character for each column past the end
+pub fn offset_at(runs: &[Run], column: usize) -> usize {
+    let mut offset = 0;
+    let mut start = 0;
+    for c in runs.iter().flat_map(|run| run.text.chars()) {
+        if start >= column {
+            return offset;
+        }
+        start += char_width(c);
+        offset += 1;
+    }
+    return offset + column.saturating_sub(start);
+}
+
+fn default_tabs(columns: usize) -> Vec<bool> {
+    return (0..columns)
+        .map(|column| column > 0 && column % TAB_WIDTH == 0)
+        .collect();
+}
+
+/// SGR 0 keeps the hyperlink
+fn reset_attributes(attributes: Attributes) -> Attributes {
+    return Attributes {
+        link: attributes.link,
+        ..Attributes::default()
+    };
+}
+
+/// parse the color after SGR 38 or 48 in either the `38;5;n` or `38:5:n` form
+/// returns the color and how many extra parameters it used
+fn extended_color(params: &[Vec<u16>], index: usize) -> (Option<Color>, usize) {
+    let param = &params[index];
+    if param.len() > 1 {
+        // subparameters, rgb may include a color space id: `38:2:id:r:g:b`
+        let color = match (param[1], param.len()) {
+            (5, 3) => Some(Color::Indexed(param[2] as u8)),
+            (2, 5) => Some(Color::Rgb(param[2] as u8, param[3] as u8, param[4] as u8)),
+            (2, len) if len >= 6 => Some(Color::Rgb(param[3] as u8, param[4] as u8, param[5] as u8)),
+            _ => None,
+        };
+        return (color, 0);
+    }
+
+    let value = |offset: usize| params.get(index + offset).map(|param| param[0] as u8);
+    match params.get(index + 1).map(|param| param[0]) {
+        Some(5) => return (value(2).map(Color::Indexed), 2),
+        Some(2) => match (value(2), value(3), value(4)) {
+            (Some(r), Some(g), Some(b)) => return (Some(Color::Rgb(r, g, b)), 4),
+            _ => return (None, params.len()),
+        },
+        _ => return (None, 0),
+    }
+}
+
+/// the path of a `file://host/path` url, with percent escapes decoded
+fn parse_file_url(url: &str) -> Option<PathBuf> {
+    if !url.starts_with("file://") {
+        return None;
+    }
+    let rest = &url["file://".len()..];
+    // the host is whatever comes before the path
+    let path = &rest[rest.find('/')?..];
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| ::std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escape) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    return Some(PathBuf::from(OsString::from_vec(decoded)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(text: &str) -> Screen {
+        let mut screen = Screen::new(4, 10);
+        screen.write(text);
+        return screen;
+    }
+
+    fn lines(screen: &Screen) -> Vec<String> {
+        return (0..screen.rows()).map(|row| screen.row(row).text()).collect();
+    }
+
+    #[test]
+    fn test_print_and_wrap() {
+        let screen = screen("hello\r\nabcdefghijkl");
+        assert_eq!(lines(&screen), vec!["hello", "abcdefghij", "kl", ""]);
+        assert!(screen.row(1).wrapped);
+        assert_eq!(screen.cursor(), (2, 2));
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        let screen = screen("[#  ] 25%\r[## ] 50%\r[###] 99%\x1b[K\r\n");
+        assert_eq!(lines(&screen)[0], "[###] 99%");
+        assert_eq!(screen.cursor(), (1, 0));
+
+        let screen = self::screen("fetch 100%\r\x1b[Kdone");
+        assert_eq!(lines(&screen)[0], "done");
+    }
+
+    #[test]
+    fn test_cursor_motion() {
+        let screen = screen("\x1b[3;4Hx\x1b[2Ay\x1b[10Cz\x1b[Hw");
+        assert_eq!(lines(&screen), vec!["w   y    z", "", "   x", ""]);
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let screen = screen("1\r\n2\r\n3\r\n4\r\n5");
+        assert_eq!(lines(&screen), vec!["2", "3", "4", "5"]);
+
+        // only rows 2 and 3 scroll
+        let screen = self::screen("top\x1b[2;3r\x1b[3;1Ha\r\nb\r\nc");
+        assert_eq!(lines(&screen), vec!["top", "b", "c", ""]);
+    }
+
+    #[test]
+    fn test_erase() {
+        let screen = screen("abc\r\ndef\r\nghi\x1b[2;2H\x1b[J");
+        assert_eq!(lines(&screen), vec!["abc", "d", "", ""]);
+
+        let screen = self::screen("abcdef\x1b[1;3H\x1b[1K");
+        assert_eq!(lines(&screen), vec!["   def", "", "", ""]);
+
+        let screen = self::screen("abcdef\x1b[1;2H\x1b[2P\x1b[1@");
+        assert_eq!(lines(&screen), vec!["a def", "", "", ""]);
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        let mut screen = screen("shell$ vim");
+        screen.write("\x1b[?1049h\x1b[Hediting");
+        assert!(screen.alternate_active());
+        assert_eq!(lines(&screen)[0], "editing");
+
+        screen.write("\x1b[?1049l");
+        assert!(!screen.alternate_active());
+        assert_eq!(lines(&screen)[0], "shell$ vim");
+        assert_eq!(screen.cursor(), (0, 10 - 1));
+    }
+
+    #[test]
+    fn test_sgr() {
+        let screen = screen("\x1b[1;31ma\x1b[38;5;200;48:2::1:2:3mb\x1b[0;4mc\x1b[mD");
+        let cell = |column: usize| screen.row(0).cells[column].attributes;
+
+        assert!(cell(0).bold);
+        assert_eq!(cell(0).foreground, Color::Indexed(1));
+        assert_eq!(cell(1).foreground, Color::Indexed(200));
+        assert_eq!(cell(1).background, Color::Rgb(1, 2, 3));
+        assert!(!cell(2).bold);
+        assert!(cell(2).underline);
+        assert_eq!(cell(3), Attributes::default());
+
+        assert_eq!(screen.runs(0).len(), 4);
+    }
+
+    #[test]
+    fn test_palette() {
+        assert_eq!(Color::Default.rgb(), None);
+        assert_eq!(Color::Indexed(1).rgb(), Some((0xcd, 0, 0)));
+        assert_eq!(Color::Indexed(16).rgb(), Some((0, 0, 0)));
+        assert_eq!(Color::Indexed(196).rgb(), Some((0xff, 0, 0)));
+        assert_eq!(Color::Indexed(255).rgb(), Some((0xee, 0xee, 0xee)));
+        assert_eq!(Color::Rgb(1, 2, 3).rgb(), Some((1, 2, 3)));
+    }
+
+    #[test]
+    fn test_responses() {
+        let mut screen = screen("ab\x1b[6n\x1b[c");
+        assert_eq!(screen.take_responses(), "\x1b[1;3R\x1b[?6c");
+        assert_eq!(screen.take_responses(), "");
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut screen = screen("\x1b[?25l\x1b[?2004h");
+        assert!(!screen.modes().cursor_visible);
+        assert!(screen.modes().bracketed_paste);
+
+        screen.write("\x1b[?7l0123456789ab");
+        assert_eq!(lines(&screen)[0], "012345678b");
+    }
+
+    #[test]
+    fn test_tabs_and_line_drawing() {
+        let mut screen = Screen::new(1, 20);
+        screen.write("a\tb\x1b(0qqj\x1b(Bq");
+        assert_eq!(screen.text(), "a       b──┘q");
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut screen = screen("1\r\n2\r\n3\r\n4");
+        screen.resize(2, 5);
+        assert_eq!(lines(&screen), vec!["3", "4"]);
+        assert_eq!(screen.cursor(), (1, 1));
+
+        screen.resize(3, 20);
+        assert_eq!(lines(&screen), vec!["3", "4", ""]);
+        assert_eq!(screen.columns(), 20);
+
+        // rows dropped off the top are kept
+        let scrollback: Vec<&str> = (0..screen.scrollback().len())
+            .map(|i| screen.scrollback().line(i).text())
+            .collect();
+        assert_eq!(scrollback, vec!["1", "2"]);
+    }
+
+    #[test]
+    fn test_cursor_runs() {
+        let screen = screen("ab\x1b[1;5H");
+        let runs = screen.runs_with_cursor(0);
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].text, "ab  ");
+        assert_eq!(runs[1].text, " ");
+        assert!(runs[1].attributes.inverse);
+        assert_eq!(screen.runs_with_cursor(1).len(), 0);
+    }
+
+    #[test]
+    fn test_scrollback() {
+        let mut screen = Screen::new(2, 10);
+        screen.set_scrollback_limit(3);
+        screen.write("1\r\n\x1b[31m2\x1b[m!\r\n3\r\n4\r\n5\r\n6");
+        assert_eq!(screen.scrollback().len(), 3);
+        assert_eq!(screen.scrollback().line(0).text(), "2!");
+
+        let runs = screen.scrollback().line(0).runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].attributes.foreground, Color::Indexed(1));
+        assert_eq!(runs[1].text, "!");
+
+        let view = |screen: &Screen, offset: usize| -> Vec<String> {
+            return (0..screen.rows())
+                .map(|row| {
+                    screen
+                        .view_runs(offset, row)
+                        .into_iter()
+                        .map(|run| run.text)
+                        .collect()
+                })
+                .collect();
+        };
+        assert_eq!(view(&screen, 0), vec!["5", "6"]);
+        assert_eq!(view(&screen, 1), vec!["4", "5"]);
+        assert_eq!(view(&screen, 10), vec!["2!", "3"]);
+
+        // the alternate screen doesn't scroll into the scrollback
+        screen.write("\x1b[?1049h\r\n\r\n\r\n");
+        assert_eq!(screen.scrollback().len(), 3);
+        assert_eq!(screen.max_offset(), 0);
+        screen.write("\x1b[?1049l\x1b[3J");
+        assert_eq!(screen.scrollback().len(), 0);
+    }
+
+    #[test]
+    fn test_title_and_cwd() {
+        let mut screen = screen("\x1b]0;germ\x07\x1b]2;a;b\x1b\\");
+        assert_eq!(screen.title(), Some("a;b"));
+        screen.write("\x1b]2;\x07");
+        assert_eq!(screen.title(), None);
+
+        screen.write("\x1b]7;file://host/tmp/a%20b\x07");
+        assert_eq!(screen.cwd(), Some(Path::new("/tmp/a b")));
+        // not a file url
+        screen.write("\x1b]7;/home\x07");
+        assert_eq!(screen.cwd(), Some(Path::new("/tmp/a b")));
+        assert_eq!(screen.text().trim(), "");
+    }
+
+    #[test]
+    fn test_links() {
+        let screen = screen("\x1b]8;id=1;https://a.b/c;d\x07ab\x1b[1mc\x1b]8;;\x07 /tmp");
+        let links = screen.view_links(0, 0);
+        assert_eq!(links.len(), 2);
+        assert_eq!((links[0].start, links[0].end), (0, 3));
+        assert_eq!(links[0].url, "https://a.b/c;d");
+        assert_eq!(links[1].url, "file:///tmp");
+        assert!(links[1].contains(6));
+        assert!(!links[1].contains(8));
+    }
+
+    #[test]
+    fn test_detected_links() {
+        let mut screen = screen("\x1b]7;file:///\x07etc/hosts");
+        assert_eq!(screen.view_links(0, 0)[0].url, "file:///etc/hosts");
+        assert_eq!(screen.detected.borrow().len(), 1);
+
+        // the same text is only looked at again in another directory
+        screen.view_links(0, 0);
+        assert_eq!(screen.detected.borrow().len(), 1);
+        screen.write("\x1b]7;file:///nonexistent\x07");
+        assert!(screen.view_links(0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_links_are_pruned() {
+        let mut screen = screen("");
+        screen.set_scrollback_limit(2);
+        let count = 3 * LINKS_BEFORE_PRUNING;
+        for i in 0..count {
+            screen.write(&format!("\x1b]8;;https://a.b/{}\x07{}\x1b]8;;\x07\r\n", i, i));
+        }
+        assert!(screen.links.len() <= LINKS_BEFORE_PRUNING);
+
+        // the links on the screen and in the scrollback still lead where they did
+        let url = |i| format!("https://a.b/{}", i);
+        assert_eq!(screen.view_links(0, 2)[0].url, url(count - 1));
+        assert_eq!(screen.view_links(2, 0)[0].url, url(count - 5));
+        assert_eq!(screen.view_links(2, 1)[0].url, url(count - 4));
+
+        // only the scrollback's are left after a reset
+        screen.write("\x1bc");
+        assert_eq!(screen.links.len(), 2);
+        assert_eq!(screen.view_links(2, 1)[0].url, url(count - 4));
+    }
+
+    #[test]
+    fn test_view_text() {
+        let screen = screen("ab\x1b[1;31mcd\r\nef\x1b[m gh");
+        assert_eq!(screen.view_text(0, (0, 1), (1, 2), false), "bcd\nef");
+        assert_eq!(
+            screen.view_text(0, (0, 0), (1, 1), true),
+            "ab\x1b[0;1;31mcd\ne\x1b[0m"
+        );
+        assert_eq!(
+            Attributes {
+                foreground: Color::Indexed(9),
+                background: Color::Rgb(1, 2, 3),
+                ..Attributes::default()
+            }
+            .sgr(),
+            "\x1b[0;91;48;2;1;2;3m"
+        );
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        let mut screen = screen("a日本b\r\n123456789漢");
+        assert_eq!(lines(&screen), vec!["a日本b", "123456789", "漢", ""]);
+        assert!(screen.row(0).cells[2].is_spacer());
+        assert_eq!(screen.row(0).cells[5].c, 'b');
+        assert!(screen.row(1).wrapped);
+        assert_eq!(screen.cursor(), (2, 2));
+
+        // combining marks have no cell of their own
+        screen.write("e\u{301}");
+        assert_eq!(screen.cursor(), (2, 3));
+
+        // overwriting half of a wide character blanks the other half
+        screen.write("\x1b[1;3Hx");
+        assert_eq!(screen.row(0).text(), "a x本b");
+
+        assert_eq!(screen.view_text(0, (0, 3), (0, 6), false), "本b");
+        screen.write("\x1b[1;5H");
+        assert_eq!(screen.runs_with_cursor(0)[1].text, "本");
+        assert!(screen.runs_with_cursor(0)[1].attributes.inverse);
+    }
+
+    #[test]
+    fn test_wide_insert_and_delete() {
+        // ICH and DCH on the second half of a wide character blank all of it
+        let mut screen = screen("ab本cd\x1b[1;4H\x1b[@");
+        assert_eq!(screen.row(0).text(), "ab   cd");
+        screen.write("\r\n本cd\x1b[2;2H\x1b[P");
+        assert_eq!(screen.row(1).text(), " cd");
+        screen.write("\r\nab本cd\x1b[3;3H\x1b[P");
+        assert_eq!(screen.row(2).text(), "ab cd");
+        assert!((0..10).all(|column| !screen.row(2).cells[column].is_spacer()));
+
+        // a wide character pushed half off the end is dropped
+        screen.write("\r\n12345678本\x1b[4;1H\x1b[@");
+        assert_eq!(screen.row(3).text(), " 12345678");
+
+        // so is one split by insert mode
+        let mut inserting = Screen::new(4, 10);
+        inserting.write("本\x1b[1;2H\x1b[4hx");
+        assert_eq!(inserting.row(0).text(), " x");
+    }
+
+    #[test]
+    fn test_columns_and_offsets() {
+        let screen = screen("日本 /tmp");
+        let runs = screen.runs(0);
+        assert_eq!(column_at(&runs, 2), 4);
+        assert_eq!(column_at(&runs, 9), 11);
+        assert_eq!(offset_at(&runs, 4), 2);
+        assert_eq!(offset_at(&runs, 1), 1);
+        assert_eq!(offset_at(&runs, 11), 9);
+
+        let links = screen.view_links(0, 0);
+        assert_eq!((links[0].start, links[0].end), (5, 9));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use super::cell::{Attributes, Run};
+use super::grid::Row;
+
+pub const DEFAULT_SCROLLBACK_LINES: usize = 10_000;
+
+/// a row that scrolled off the screen,
+/// stored as its text and where each run of attributes starts
+/// rather than a cell per column
+#[derive(Clone, Debug)]
+pub struct Line {
+    text: String,
+    /// byte offsets into `text` and the attributes from there on
+    styles: Vec<(usize, Attributes)>,
+}
+
+impl Line {
+    pub fn from_row(row: &Row) -> Line {
+        let runs = row.runs();
+        let mut text = String::new();
+        let mut styles = Vec::with_capacity(runs.len());
+        for run in runs {
+            styles.push((text.len(), run.attributes));
+            text.push_str(&run.text);
+        }
+
+        text.shrink_to_fit();
+        return Line {
+            text: text,
+            styles: styles,
+        };
+    }
+
+    pub fn text(&self) -> &str {
+        return &self.text;
+    }
+
+    pub fn runs(&self) -> Vec<Run> {
+        return self
+            .styles
+            .iter()
+            .enumerate()
+            .map(|(i, &(start, attributes))| {
+                let end = self
+                    .styles
+                    .get(i + 1)
+                    .map(|&(end, _)| end)
+                    .unwrap_or(self.text.len());
+                Run {
+                    text: self.text[start..end].to_string(),
+                    attributes: attributes,
+                }
+            })
+            .collect();
+    }
+}
+
+/// lines that scrolled off the top of the screen, oldest first
+/// the oldest lines are dropped once there are more than `limit`
+pub struct Scrollback {
+    lines: VecDeque<Line>,
+    limit: usize,
+}
+
+impl Scrollback {
+    pub fn new(limit: usize) -> Scrollback {
+        return Scrollback {
+            lines: VecDeque::new(),
+            limit: limit,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.lines.len();
+    }
+
+    /// the line `index` lines after the oldest
+    pub fn line(&self, index: usize) -> &Line {
+        return &self.lines[index];
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    pub fn push(&mut self, row: &Row) {
+        if self.limit == 0 {
+            return;
+        }
+        self.lines.push_back(Line::from_row(row));
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// call `f` with the attributes of every run, to change them in place
+    pub fn for_each_attributes<F: FnMut(&mut Attributes)>(&mut self, f: &mut F) {
+        for line in self.lines.iter_mut() {
+            for &mut (_, ref mut attributes) in line.styles.iter_mut() {
+                f(attributes);
+            }
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.lines.len() > self.limit {
+            self.lines.pop_front();
+        }
+    }
+}
@@ -0,0 +1,652 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use config;
+use input;
+use input::{Key, Modifiers};
+use screen::{Screen, DEFAULT_COLUMNS, DEFAULT_ROWS, DEFAULT_SCROLLBACK_LINES};
+use shell;
+use shell::{CommandStatus, Completion, Shell};
+use stream::Waker;
+
+pub type SessionId = u64;
+
+#[derive(Debug)]
+pub enum Error {
+    /// no session has the given id, it may have been closed
+    NotFound(SessionId),
+    Shell(shell::Error),
+}
+
+impl From<shell::Error> for Error {
+    fn from(error: shell::Error) -> Self {
+        return Error::Shell(error);
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        return Error::Shell(shell::Error::Io(error));
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotFound(id) => write!(f, "there is no session {}", id),
+            Error::Shell(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// something that happened to a session
+#[derive(Debug)]
+pub enum Event {
+    Created,
+    /// decoded terminal output, already drawn on the session's screen
+    Output(String),
+    CommandFinished(Completion),
+    /// the shell's output could not be read
+    Error(shell::Error),
+    /// the shell exited on its own, the session stays open until closed
+    Exited(ExitStatus),
+    Restarted,
+    /// the session was closed and its id is no longer valid
+    Closed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Running,
+    /// output has ended but the shell hasn't been reaped yet
+    Closing,
+    Exited(ExitStatus),
+}
+
+/// a shell, the screen its output is drawn on and what's known about its lifecycle
+pub struct Session {
+    id: SessionId,
+    shell: Shell,
+    screen: Screen,
+    state: State,
+    /// a command is running and has turned off canonical mode, checked as output arrives
+    /// and when commands finish rather than every time it's asked for
+    raw_mode: bool,
+}
+
+impl Session {
+    pub fn id(&self) -> SessionId {
+        return self.id;
+    }
+
+    pub fn shell(&self) -> &Shell {
+        return &self.shell;
+    }
+
+    pub fn shell_mut(&mut self) -> &mut Shell {
+        return &mut self.shell;
+    }
+
+    pub fn screen(&self) -> &Screen {
+        return &self.screen;
+    }
+
+    /// the title the running program asked for, if any
+    pub fn title(&self) -> Option<&str> {
+        return self.screen.title();
+    }
+
+    /// the shell's working directory, once its prompt has reported it
+    pub fn cwd(&self) -> Option<&Path> {
+        return self.screen.cwd();
+    }
+
+    /// true while a full-screen or raw mode program is running,
+    /// it should get the whole window and every key press
+    pub fn passthrough(&self) -> bool {
+        if self.screen.alternate_active() {
+            return true;
+        }
+        return self.raw_mode;
+    }
+
+    /// send a key press to the program reading the terminal
+    pub fn send_key(&mut self, key: Key, modifiers: Modifiers) -> io::Result<()> {
+        let bytes = input::encode(key, modifiers, self.screen.modes().application_cursor);
+        return self.shell.send(&bytes);
+    }
+
+    /// send pasted text to the program reading the terminal,
+    /// marked as a paste if it asked for bracketed paste
+    pub fn paste(&mut self, text: &str) -> io::Result<()> {
+        let bytes = input::paste(text, self.screen.modes().bracketed_paste);
+        return self.shell.send(&bytes);
+    }
+
+    /// resize the screen and tell the shell about it
+    pub fn resize(&mut self, rows: usize, columns: usize) -> Result<(), Error> {
+        self.screen.resize(rows, columns);
+        self.shell.resize(clamp_u16(rows), clamp_u16(columns))?;
+        return Ok(());
+    }
+
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        match self.state {
+            State::Exited(status) => return Some(status),
+            _ => return None,
+        }
+    }
+
+    fn poll(&mut self) -> Option<Event> {
+        // completions are found while output is processed,
+        // so they follow the output that finished them
+        if let Some(completion) = self.shell.poll_completion() {
+            self.update_raw_mode();
+            return Some(Event::CommandFinished(completion));
+        }
+
+        match self.state {
+            State::Exited(_) => return None,
+            State::Running => match self.shell.poll_output() {
+                Ok(Some(output)) => {
+                    // programs change the terminal mode before drawing for it
+                    self.update_raw_mode();
+                    self.draw(&output);
+                    return Some(Event::Output(output));
+                }
+                Ok(None) => return None,
+                Err(shell::Error::Closed) => self.state = State::Closing,
+                Err(e) => {
+                    self.state = State::Closing;
+                    return Some(Event::Error(e));
+                }
+            },
+            State::Closing => (),
+        }
+
+        match self.shell.poll_exit() {
+            Ok(Some(status)) => {
+                self.state = State::Exited(status);
+                return Some(Event::Exited(status));
+            }
+            Ok(None) => return None,
+            Err(e) => return Some(Event::Error(e.into())),
+        }
+    }
+
+    fn update_raw_mode(&mut self) {
+        // the shell's own line editor reads keys itself while it waits for a command
+        self.raw_mode =
+            self.shell.status() == Some(CommandStatus::Running) && self.shell.raw_mode();
+    }
+
+    fn draw(&mut self, output: &str) {
+        self.screen.write(output);
+
+        // answer queries like the cursor position report
+        let responses = self.screen.take_responses();
+        if !responses.is_empty() {
+            if let Err(e) = self.shell.execute(&responses) {
+                eprintln!("could not reply to terminal query:\n{}", e);
+            }
+        }
+    }
+}
+
+/// creates, tracks and tears down shells
+/// the manager doesn't depend on any ui,
+/// a frontend drives it by calling `poll` when woken
+pub struct SessionManager {
+    sessions: BTreeMap<SessionId, Session>,
+    events: VecDeque<(SessionId, Event)>,
+    next_id: SessionId,
+    /// the session polled last, polling resumes after it
+    cursor: SessionId,
+    waker: Option<Waker>,
+    /// rows and columns of every session's terminal
+    size: (usize, usize),
+    /// lines of scrollback each session keeps
+    scrollback_limit: usize,
+}
+
+impl SessionManager {
+    pub fn new() -> SessionManager {
+        return SessionManager {
+            sessions: BTreeMap::new(),
+            events: VecDeque::new(),
+            next_id: 0,
+            cursor: 0,
+            waker: None,
+            size: (DEFAULT_ROWS, DEFAULT_COLUMNS),
+            scrollback_limit: DEFAULT_SCROLLBACK_LINES,
+        };
+    }
+
+    /// called from another thread whenever any session has output
+    pub fn set_waker(&mut self, waker: Waker) {
+        for session in self.sessions.values() {
+            session.shell.set_waker(waker.clone());
+        }
+        self.waker = Some(waker);
+    }
+
+    pub fn create(&mut self, config: config::Shell) -> Result<SessionId, Error> {
+        let mut shell = Shell::from_config(config)?;
+        if let Some(ref waker) = self.waker {
+            shell.set_waker(waker.clone());
+        }
+
+        let (rows, columns) = self.size;
+        shell.resize(clamp_u16(rows), clamp_u16(columns))?;
+        let screen = self.new_screen();
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.sessions.insert(
+            id,
+            Session {
+                id: id,
+                shell: shell,
+                screen: screen,
+                state: State::Running,
+                raw_mode: false,
+            },
+        );
+        self.push_event(id, Event::Created);
+
+        return Ok(id);
+    }
+
+    /// create a session that starts in the working directory of the session `from`,
+    /// or where `config` says if it's not known
+    pub fn create_from(
+        &mut self,
+        mut config: config::Shell,
+        from: SessionId,
+    ) -> Result<SessionId, Error> {
+        let cwd = self
+            .get(from)
+            .ok_or(Error::NotFound(from))?
+            .cwd()
+            .and_then(Path::to_str)
+            .map(String::from);
+        if cwd.is_some() {
+            config.working_directory = cwd;
+        }
+        return self.create(config);
+    }
+
+    /// create a session running the same shell as the session `from`, in its working directory
+    pub fn duplicate(&mut self, from: SessionId) -> Result<SessionId, Error> {
+        let config = self
+            .get(from)
+            .ok_or(Error::NotFound(from))?
+            .shell
+            .config()
+            .clone();
+        return self.create_from(config, from);
+    }
+
+    pub fn get(&self, id: SessionId) -> Option<&Session> {
+        return self.sessions.get(&id);
+    }
+
+    pub fn get_mut(&mut self, id: SessionId) -> Option<&mut Session> {
+        return self.sessions.get_mut(&id);
+    }
+
+    pub fn shell_mut(&mut self, id: SessionId) -> Result<&mut Shell, Error> {
+        return self
+            .get_mut(id)
+            .map(Session::shell_mut)
+            .ok_or(Error::NotFound(id));
+    }
+
+    /// ids of the open sessions, oldest first
+    pub fn ids(&self) -> Vec<SessionId> {
+        return self.sessions.keys().cloned().collect();
+    }
+
+    /// the session after `id`, or before it if not `forward`, wrapping around at the ends
+    pub fn adjacent(&self, id: SessionId, forward: bool) -> Option<SessionId> {
+        let ids = self.ids();
+        let index = ids.iter().position(|&other| other == id)?;
+        let index = if forward {
+            (index + 1) % ids.len()
+        } else {
+            (index + ids.len() - 1) % ids.len()
+        };
+        return Some(ids[index]);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.sessions.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.sessions.is_empty();
+    }
+
+    /// true if a shell's output has ended but it hasn't exited yet,
+    /// `poll` should be called again later to pick up its exit status
+    pub fn awaiting_exit(&self) -> bool {
+        return self
+            .sessions
+            .values()
+            .any(|session| session.state == State::Closing);
+    }
+
+    /// limit the scrollback of every session, and of sessions created later
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scrollback_limit = lines;
+        for session in self.sessions.values_mut() {
+            session.screen.set_scrollback_limit(lines);
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        return self.size;
+    }
+
+    /// set the terminal size of every session, and of sessions created later
+    pub fn resize(&mut self, rows: usize, columns: usize) {
+        let (rows, columns) = (rows.max(1), columns.max(1));
+        if self.size == (rows, columns) {
+            return;
+        }
+        self.size = (rows, columns);
+
+        for session in self.sessions.values_mut() {
+            if let Err(e) = session.resize(rows, columns) {
+                eprintln!("could not resize session {}:\n{}", session.id, e);
+            }
+        }
+    }
+
+    /// get the next event from any session without blocking
+    pub fn poll(&mut self) -> Option<(SessionId, Event)> {
+        if let Some(event) = self.events.pop_front() {
+            return Some(event);
+        }
+
+        // start after the session that was polled last
+        // so a busy session can't starve the others
+        let cursor = self.cursor;
+        let ids: Vec<SessionId> = self
+            .sessions
+            .range(cursor + 1..)
+            .chain(self.sessions.range(..cursor + 1))
+            .map(|(&id, _)| id)
+            .collect();
+
+        for id in ids {
+            let event = self.sessions.get_mut(&id).and_then(Session::poll);
+            if let Some(event) = event {
+                self.cursor = id;
+                return Some((id, event));
+            }
+        }
+
+        return None;
+    }
+
+    /// exit a session's shell if it's still running and start a new one in its place
+    pub fn restart(&mut self, id: SessionId) -> Result<(), Error> {
+        let screen = self.new_screen();
+        {
+            let session = self.get_mut(id).ok_or(Error::NotFound(id))?;
+            session.shell.restart()?;
+            session.screen = screen;
+            session.state = State::Running;
+            session.raw_mode = false;
+        }
+        self.push_event(id, Event::Restarted);
+        return Ok(());
+    }
+
+    /// shut a session's shell down and forget the session
+    pub fn close(&mut self, id: SessionId) -> Result<ExitStatus, Error> {
+        let mut session = self.sessions.remove(&id).ok_or(Error::NotFound(id))?;
+        // drop events for the session that no one can act on anymore
+        self.events.retain(|&(event_id, _)| event_id != id);
+        self.push_event(id, Event::Closed);
+
+        let status = session.shell.exit()?;
+        return Ok(status);
+    }
+
+    pub fn close_all(&mut self) -> Vec<(SessionId, Result<ExitStatus, Error>)> {
+        return self
+            .ids()
+            .into_iter()
+            .map(|id| (id, self.close(id)))
+            .collect();
+    }
+
+    fn new_screen(&self) -> Screen {
+        let (rows, columns) = self.size;
+        let mut screen = Screen::new(rows, columns);
+        screen.set_scrollback_limit(self.scrollback_limit);
+        return screen;
+    }
+
+    fn push_event(&mut self, id: SessionId, event: Event) {
+        self.events.push_back((id, event));
+    }
+}
+
+fn clamp_u16(n: usize) -> u16 {
+    return n.min(u16::max_value() as usize) as u16;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    use shell::Control;
+
+    const BASH_SHELL_PATH: &str = "/bin/bash";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn test_config() -> config::Shell {
+        let mut config = config::Shell::new(BASH_SHELL_PATH.into());
+        config.args = Some(vec!["--norc".into()]);
+        return config;
+    }
+
+    /// poll until `f` returns true for an event, returning the output of `id` seen so far
+    fn poll_until<F>(sessions: &mut SessionManager, id: SessionId, mut f: F) -> String
+    where
+        F: FnMut(SessionId, &Event) -> bool,
+    {
+        let mut output = String::new();
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < TIMEOUT, "timed out\noutput: {}", output);
+
+            if let Some((event_id, event)) = sessions.poll() {
+                if let (true, &Event::Output(ref chunk)) = (event_id == id, &event) {
+                    output.push_str(chunk);
+                }
+                if f(event_id, &event) {
+                    return output;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut sessions = SessionManager::new();
+        let first = sessions.create(test_config()).expect("could not create session");
+        let second = sessions.create(test_config()).expect("could not create session");
+        assert_ne!(first, second);
+        assert_eq!(sessions.ids(), vec![first, second]);
+        assert_eq!(sessions.adjacent(first, true), Some(second));
+        assert_eq!(sessions.adjacent(second, true), Some(first));
+        assert_eq!(sessions.adjacent(first, false), Some(second));
+
+        sessions
+            .shell_mut(first)
+            .expect("could not find first session")
+            .submit("echo fir''st")
+            .expect("could not submit to first session");
+        sessions
+            .shell_mut(second)
+            .expect("could not find second session")
+            .submit("echo sec''ond")
+            .expect("could not submit to second session");
+
+        let mut finished = Vec::new();
+        let output = poll_until(&mut sessions, first, |id, event| {
+            if let Event::CommandFinished(_) = *event {
+                finished.push(id);
+            }
+            finished.len() == 2
+        });
+        assert!(output.contains("first\r\n"));
+        assert!(!output.contains("second"));
+
+        let screen = sessions.get(first).expect("could not find first session").screen();
+        assert!(screen.text().contains("first\n"));
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut sessions = SessionManager::new();
+        let id = sessions.create(test_config()).expect("could not create session");
+        sessions.resize(30, 100);
+        assert_eq!(sessions.get(id).map(|session| session.screen().rows()), Some(30));
+
+        sessions
+            .shell_mut(id)
+            .expect("could not find session")
+            .submit("stty size")
+            .expect("could not submit to session");
+        let output = poll_until(&mut sessions, id, |_, event| match *event {
+            Event::CommandFinished(_) => true,
+            _ => false,
+        });
+        assert!(output.contains("30 100\r\n"));
+    }
+
+    /// poll until the shell of `id` reports `cwd` as its working directory
+    fn wait_for_cwd(sessions: &mut SessionManager, id: SessionId, cwd: &str) {
+        let start = Instant::now();
+        while sessions.get(id).and_then(Session::cwd) != Some(Path::new(cwd)) {
+            assert!(start.elapsed() < TIMEOUT, "the shell did not report {}", cwd);
+            sessions.poll();
+        }
+    }
+
+    #[test]
+    fn test_cwd() {
+        let mut config = test_config();
+        config.working_directory = Some("/".into());
+        let mut sessions = SessionManager::new();
+        let first = sessions.create(config).expect("could not create session");
+        wait_for_cwd(&mut sessions, first, "/");
+
+        sessions
+            .shell_mut(first)
+            .expect("could not find session")
+            .submit("cd /tmp")
+            .expect("could not submit to session");
+        wait_for_cwd(&mut sessions, first, "/tmp");
+
+        let second = sessions
+            .create_from(test_config(), first)
+            .expect("could not create session");
+        wait_for_cwd(&mut sessions, second, "/tmp");
+
+        let third = sessions.duplicate(second).expect("could not create session");
+        wait_for_cwd(&mut sessions, third, "/tmp");
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let mut sessions = SessionManager::new();
+        let id = sessions.create(test_config()).expect("could not create session");
+        assert!(!sessions.get(id).expect("could not find session").passthrough());
+
+        sessions
+            .shell_mut(id)
+            .expect("could not find session")
+            .submit("printf '\\033[?1049h'")
+            .expect("could not submit to session");
+        poll_until(&mut sessions, id, |_, event| match *event {
+            Event::CommandFinished(_) => true,
+            _ => false,
+        });
+        assert!(sessions.get(id).expect("could not find session").passthrough());
+    }
+
+    #[test]
+    fn test_raw_mode_passthrough() {
+        let mut sessions = SessionManager::new();
+        let id = sessions.create(test_config()).expect("could not create session");
+
+        sessions
+            .shell_mut(id)
+            .expect("could not find session")
+            .submit("stty -icanon; printf rea''dy; sleep 60")
+            .expect("could not submit to session");
+        poll_until(&mut sessions, id, |_, event| match *event {
+            Event::Output(ref text) => text.contains("ready"),
+            _ => false,
+        });
+        assert!(sessions.get(id).expect("could not find session").passthrough());
+
+        sessions
+            .shell_mut(id)
+            .expect("could not find session")
+            .send_control(Control::Interrupt)
+            .expect("could not interrupt command");
+        poll_until(&mut sessions, id, |_, event| match *event {
+            Event::CommandFinished(_) => true,
+            _ => false,
+        });
+        assert!(!sessions.get(id).expect("could not find session").passthrough());
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        let mut sessions = SessionManager::new();
+        let id = sessions.create(test_config()).expect("could not create session");
+        match sessions.poll() {
+            Some((event_id, Event::Created)) => assert_eq!(event_id, id),
+            event => assert!(false, "expected a created event, got {:?}", event),
+        }
+
+        sessions
+            .shell_mut(id)
+            .expect("could not find session")
+            .submit("exit 3")
+            .expect("could not submit exit");
+        poll_until(&mut sessions, id, |_, event| match *event {
+            Event::Exited(status) => status.code() == Some(3),
+            _ => false,
+        });
+        assert!(sessions.get(id).and_then(Session::exit_status).is_some());
+
+        sessions.restart(id).expect("could not restart session");
+        poll_until(&mut sessions, id, |_, event| match *event {
+            Event::Restarted => true,
+            _ => false,
+        });
+        assert!(sessions.get(id).and_then(Session::exit_status).is_none());
+
+        sessions.close(id).expect("could not close session");
+        assert!(sessions.is_empty());
+        match sessions.close(id) {
+            Err(Error::NotFound(missing)) => assert_eq!(missing, id),
+            _ => assert!(false, "closed a session twice"),
+        }
+    }
+}
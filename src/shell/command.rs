@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, SystemTime};
+
+use super::integration::{Marker, Scanner};
+
+pub type CommandId = u64;
+
+/// the state of the most recent command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandStatus {
+    Running,
+    Succeeded,
+    Failed(i32),
+}
+
+impl CommandStatus {
+    pub fn from_exit_code(exit_code: i32) -> CommandStatus {
+        if exit_code == 0 {
+            return CommandStatus::Succeeded;
+        }
+        return CommandStatus::Failed(exit_code);
+    }
+}
+
+impl fmt::Display for CommandStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CommandStatus::Running => write!(f, "running"),
+            CommandStatus::Succeeded => write!(f, "succeeded (0)"),
+            CommandStatus::Failed(code) => write!(f, "failed ({})", code),
+        }
+    }
+}
+
+/// a record of a finished command
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub id: CommandId,
+    pub command: String,
+    pub exit_code: i32,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    /// range of bytes in the shell output produced by the command
+    pub output: Range<usize>,
+}
+
+impl Completion {
+    pub fn status(&self) -> CommandStatus {
+        return CommandStatus::from_exit_code(self.exit_code);
+    }
+
+    pub fn duration(&self) -> Duration {
+        return self
+            .finished
+            .duration_since(self.started)
+            .unwrap_or(Duration::from_secs(0));
+    }
+}
+
+struct Pending {
+    id: CommandId,
+    command: String,
+    started: SystemTime,
+    output_start: usize,
+}
+
+/// matches submitted commands with the completion markers
+/// the shell prints before each prompt
+pub struct Tracker {
+    scanner: Scanner,
+    /// the command the shell is running, input submitted meanwhile is read by it
+    pending: Option<Pending>,
+    completions: VecDeque<Completion>,
+    /// number of output bytes seen after markers were removed
+    offset: usize,
+    next_id: CommandId,
+    /// the first marker comes from the initial prompt, not a command
+    ready: bool,
+    /// false if the shell prints no markers, commands are never known to finish
+    integrated: bool,
+    last_status: Option<CommandStatus>,
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        return Tracker {
+            scanner: Scanner::new(),
+            pending: None,
+            completions: VecDeque::new(),
+            offset: 0,
+            next_id: 0,
+            ready: false,
+            integrated: true,
+            last_status: None,
+        };
+    }
+
+    /// for shells without integration, every submit is taken as a new command
+    /// and nothing is ever running
+    pub fn without_markers() -> Tracker {
+        return Tracker {
+            integrated: false,
+            ..Tracker::new()
+        };
+    }
+
+    /// start tracking a command if the shell is waiting for one,
+    /// `None` if the input goes to a command that's already running
+    pub fn submit(&mut self, command: &str) -> Option<CommandId> {
+        if self.pending.is_some() {
+            return None;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        if !self.integrated {
+            return Some(id);
+        }
+
+        self.pending = Some(Pending {
+            id: id,
+            command: command.trim_end().to_string(),
+            started: SystemTime::now(),
+            output_start: self.offset,
+        });
+
+        return Some(id);
+    }
+
+    /// strip markers from terminal output and complete any finished commands
+    pub fn process(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let (output, markers) = self.scanner.scan(bytes);
+        let base = self.offset;
+
+        for (position, marker) in markers {
+            match marker {
+                Marker::CommandFinished(exit_code) => {
+                    self.complete(exit_code, base + position);
+                }
+            }
+        }
+
+        self.offset += output.len();
+        return output;
+    }
+
+    pub fn poll_completion(&mut self) -> Option<Completion> {
+        return self.completions.pop_front();
+    }
+
+    /// the status of the running command,
+    /// or of the last finished command if nothing is running
+    pub fn status(&self) -> Option<CommandStatus> {
+        if self.pending.is_some() {
+            return Some(CommandStatus::Running);
+        }
+        return self.last_status;
+    }
+
+    fn complete(&mut self, exit_code: i32, output_end: usize) {
+        if !self.ready {
+            self.ready = true;
+            return;
+        }
+
+        if let Some(pending) = self.pending.take() {
+            let completion = Completion {
+                id: pending.id,
+                command: pending.command,
+                exit_code: exit_code,
+                started: pending.started,
+                finished: SystemTime::now(),
+                output: pending.output_start..output_end,
+            };
+
+            self.last_status = Some(completion.status());
+            self.completions.push_back(completion);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPT: &[u8] = b"\x1b]133;D;0\x07$ ";
+
+    #[test]
+    fn test_completion() {
+        let mut tracker = Tracker::new();
+        tracker.process(PROMPT);
+        assert_eq!(tracker.status(), None);
+
+        let id = tracker.submit("false\n").expect("the shell should be idle");
+        assert_eq!(tracker.status(), Some(CommandStatus::Running));
+
+        let output = tracker.process(b"nope\r\n\x1b]133;D;1\x07$ ");
+        assert_eq!(output.as_slice(), b"nope\r\n$ ");
+
+        let completion = tracker.poll_completion().expect("command should be complete");
+        assert_eq!(completion.id, id);
+        assert_eq!(completion.command, "false");
+        assert_eq!(completion.exit_code, 1);
+        assert_eq!(completion.output, 2..8);
+        assert_eq!(tracker.status(), Some(CommandStatus::Failed(1)));
+        assert!(tracker.poll_completion().is_none());
+    }
+
+    #[test]
+    fn test_input_to_running_command() {
+        let mut tracker = Tracker::new();
+        tracker.process(PROMPT);
+
+        let id = tracker.submit("read x\n").expect("the shell should be idle");
+        // read by `read`, not run by the shell
+        assert_eq!(tracker.submit("bob\n"), None);
+
+        tracker.process(b"\x1b]133;D;0\x07$ ");
+        let completion = tracker.poll_completion().expect("command should be complete");
+        assert_eq!(completion.id, id);
+        assert_eq!(completion.command, "read x");
+        assert_eq!(tracker.status(), Some(CommandStatus::Succeeded));
+        assert!(tracker.submit("true\n").is_some());
+    }
+
+    #[test]
+    fn test_without_markers() {
+        let mut tracker = Tracker::without_markers();
+
+        assert_eq!(tracker.submit("vim\n"), Some(0));
+        assert_eq!(tracker.submit(":q\n"), Some(1));
+        assert_eq!(tracker.status(), None);
+    }
+
+    #[test]
+    fn test_status_display() {
+        assert_eq!(CommandStatus::Running.to_string(), "running");
+        assert_eq!(CommandStatus::Succeeded.to_string(), "succeeded (0)");
+        assert_eq!(CommandStatus::Failed(2).to_string(), "failed (2)");
+    }
+}
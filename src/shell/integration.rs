@@ -0,0 +1,298 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use xdg::BaseDirectories;
+
+/// where germ's rc file is kept, under the xdg data directory
+const RC_FILE: &str = "germ/bashrc";
+
+/// sourced by bash in place of its own rc file, it sources the startup files
+/// bash would have, named by `GERM_STARTUP` and `GERM_RCFILE`,
+/// and then wraps the PROMPT_COMMAND they left
+///
+/// the wrapper saves the exit status before the user's prompt commands can
+/// change it, and then reports it with an OSC 133 marker and the working
+/// directory with OSC 7, escaping `%` so it can be decoded
+/// PROMPT_COMMAND is kept out of the environment, a shell started from this one
+/// would otherwise print markers for the command that started it
+const BASHRC: &str = r#"# written by germ, changes are overwritten
+case "${GERM_STARTUP-}" in
+login)
+    if [ -f /etc/profile ]; then
+        . /etc/profile
+    fi
+    for __germ_profile in ~/.bash_profile ~/.bash_login ~/.profile; do
+        if [ -f "$__germ_profile" ]; then
+            . "$__germ_profile"
+            break
+        fi
+    done
+    unset __germ_profile
+    ;;
+rc)
+    if [ -f "${GERM_RCFILE:-$HOME/.bashrc}" ]; then
+        . "${GERM_RCFILE:-$HOME/.bashrc}"
+    fi
+    ;;
+esac
+unset GERM_STARTUP GERM_RCFILE
+
+__germ_prompt() {
+    printf '\033]133;D;%s\007\033]7;file://%s%s\007' "$1" "$HOSTNAME" "${PWD//%/%25}"
+}
+PROMPT_COMMAND="__germ_status=\$?
+${PROMPT_COMMAND-}
+__germ_prompt \"\$__germ_status\""
+export -n PROMPT_COMMAND
+"#;
+
+const RCFILE_FLAG: &str = "--rcfile";
+const INIT_FILE_FLAG: &str = "--init-file";
+const NORC_FLAG: &str = "--norc";
+const NOPROFILE_FLAG: &str = "--noprofile";
+
+/// how to start bash so it sources germ's rc file
+#[derive(Debug, PartialEq)]
+pub struct Startup {
+    pub args: Vec<String>,
+    /// tells the rc file which of the user's startup files to source
+    pub env: Vec<(String, String)>,
+}
+
+/// true for bash, the only shell germ can give its rc file to
+pub fn is_bash(path: &str) -> bool {
+    return Path::new(path).file_name().map_or(false, |name| name == "bash");
+}
+
+/// write germ's rc file to the xdg data directory unless it's already there
+/// returns where it is
+pub fn install_rcfile() -> io::Result<PathBuf> {
+    let path = BaseDirectories::new()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+        .get_data_home()
+        .join(RC_FILE);
+
+    match fs::read_to_string(&path) {
+        Ok(ref text) if text == BASHRC => return Ok(path),
+        _ => (),
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // a shell starting in another window may be reading the old one
+    let temporary = path.with_extension(process::id().to_string());
+    fs::write(&temporary, BASHRC)?;
+    fs::rename(&temporary, &path)?;
+    return Ok(path);
+}
+
+/// the arguments that start bash with the rc file at `rcfile`,
+/// which sources the startup files that `args` and `login` would have
+/// germ's rc file can't be given to a login shell, so `login` is carried out by it
+pub fn bash_startup(args: &[String], login: bool, rcfile: &Path) -> Startup {
+    // long options have to come before the user's single character ones
+    let mut startup_args = vec![
+        RCFILE_FLAG.to_string(),
+        rcfile.to_string_lossy().into_owned(),
+    ];
+    let mut user_rcfile = None;
+    let mut norc = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            RCFILE_FLAG | INIT_FILE_FLAG => user_rcfile = args.next().cloned(),
+            NORC_FLAG => norc = true,
+            _ => startup_args.push(arg.clone()),
+        }
+    }
+
+    let noprofile = startup_args.iter().any(|arg| arg == NOPROFILE_FLAG);
+    let startup = match (login, noprofile, norc) {
+        (true, false, _) => "login",
+        (false, _, false) => "rc",
+        _ => "none",
+    };
+    let mut env = vec![("GERM_STARTUP".to_string(), startup.to_string())];
+    if let Some(user_rcfile) = user_rcfile {
+        env.push(("GERM_RCFILE".to_string(), user_rcfile));
+    }
+
+    return Startup {
+        args: startup_args,
+        env: env,
+    };
+}
+
+const MARKER_PREFIX: &[u8] = b"\x1b]133;D;";
+const BEL: u8 = 0x07;
+
+/// a shell integration event found in the output
+#[derive(Debug, PartialEq)]
+pub enum Marker {
+    /// a command finished with the given exit code
+    CommandFinished(i32),
+}
+
+/// strips shell integration markers out of a byte stream
+/// markers split across chunks are held until they are complete
+pub struct Scanner {
+    partial: Vec<u8>,
+}
+
+impl Scanner {
+    pub fn new() -> Scanner {
+        return Scanner {
+            partial: Vec::new(),
+        };
+    }
+
+    /// returns the output with markers removed
+    /// along with each marker and its offset into the returned output
+    pub fn scan(&mut self, bytes: &[u8]) -> (Vec<u8>, Vec<(usize, Marker)>) {
+        let mut data = Vec::with_capacity(self.partial.len() + bytes.len());
+        data.extend_from_slice(&self.partial);
+        data.extend_from_slice(bytes);
+        self.partial.clear();
+
+        let mut output = Vec::with_capacity(data.len());
+        let mut markers = Vec::new();
+
+        let mut i = 0;
+        while i < data.len() {
+            if data[i] != MARKER_PREFIX[0] {
+                output.push(data[i]);
+                i += 1;
+                continue;
+            }
+
+            match parse_marker(&data[i..]) {
+                Parse::Complete(marker, length) => {
+                    markers.push((output.len(), marker));
+                    i += length;
+                }
+                Parse::Incomplete => {
+                    self.partial.extend_from_slice(&data[i..]);
+                    break;
+                }
+                Parse::NotAMarker => {
+                    output.push(data[i]);
+                    i += 1;
+                }
+            }
+        }
+
+        return (output, markers);
+    }
+}
+
+enum Parse {
+    Complete(Marker, usize),
+    Incomplete,
+    NotAMarker,
+}
+
+fn parse_marker(data: &[u8]) -> Parse {
+    if data.len() < MARKER_PREFIX.len() {
+        if MARKER_PREFIX.starts_with(data) {
+            return Parse::Incomplete;
+        }
+        return Parse::NotAMarker;
+    }
+    if !data.starts_with(MARKER_PREFIX) {
+        return Parse::NotAMarker;
+    }
+
+    let code = &data[MARKER_PREFIX.len()..];
+    for (i, &byte) in code.iter().enumerate() {
+        match byte {
+            BEL => {
+                return match ::std::str::from_utf8(&code[..i])
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                {
+                    Some(exit_code) => Parse::Complete(
+                        Marker::CommandFinished(exit_code),
+                        MARKER_PREFIX.len() + i + 1,
+                    ),
+                    None => Parse::NotAMarker,
+                };
+            }
+            b'0'..=b'9' | b'-' => {}
+            _ => return Parse::NotAMarker,
+        }
+    }
+
+    return Parse::Incomplete;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_marker() {
+        let mut scanner = Scanner::new();
+        let (output, markers) = scanner.scan(b"hello\r\n\x1b]133;D;0\x07$ ");
+
+        assert_eq!(output.as_slice(), b"hello\r\n$ ");
+        assert_eq!(markers, vec![(7, Marker::CommandFinished(0))]);
+    }
+
+    #[test]
+    fn test_split_marker() {
+        let mut scanner = Scanner::new();
+
+        let (output, markers) = scanner.scan(b"oops\x1b]13");
+        assert_eq!(output.as_slice(), b"oops");
+        assert!(markers.is_empty());
+
+        let (output, markers) = scanner.scan(b"3;D;12");
+        assert!(output.is_empty());
+        assert!(markers.is_empty());
+
+        let (output, markers) = scanner.scan(b"7\x07$ ");
+        assert_eq!(output.as_slice(), b"$ ");
+        assert_eq!(markers, vec![(0, Marker::CommandFinished(127))]);
+    }
+
+    #[test]
+    fn test_bash_startup() {
+        let rcfile = Path::new("/germ/bashrc");
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let env = |startup: &str| vec![("GERM_STARTUP".to_string(), startup.to_string())];
+
+        assert_eq!(
+            bash_startup(&args(&["-O", "extglob"]), false, rcfile),
+            Startup {
+                args: args(&["--rcfile", "/germ/bashrc", "-O", "extglob"]),
+                env: env("rc"),
+            }
+        );
+        assert_eq!(bash_startup(&args(&["--norc"]), false, rcfile).env, env("none"));
+        assert_eq!(bash_startup(&[], true, rcfile).env, env("login"));
+        assert_eq!(
+            bash_startup(&args(&["--noprofile"]), true, rcfile),
+            Startup {
+                args: args(&["--rcfile", "/germ/bashrc", "--noprofile"]),
+                env: env("none"),
+            }
+        );
+
+        let startup = bash_startup(&args(&["--rcfile", "/my/rc"]), false, rcfile);
+        assert_eq!(startup.args, args(&["--rcfile", "/germ/bashrc"]));
+        assert_eq!(startup.env[1], ("GERM_RCFILE".to_string(), "/my/rc".to_string()));
+    }
+
+    #[test]
+    fn test_other_escapes() {
+        let mut scanner = Scanner::new();
+        let input = b"\x1b[1mbold\x1b]0;title\x07";
+        let (output, markers) = scanner.scan(input);
+
+        assert_eq!(output.as_slice(), &input[..]);
+        assert!(markers.is_empty());
+    }
+}
@@ -1,121 +1,503 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::File;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::{Child, ChildStdin, Command, Stdio};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use futures::{Async, Stream};
+use futures::Async;
+use libc;
 
+use config;
+use encoding::Decoder;
+use pty;
+use pty::Pty;
 use stream;
 
-type ShellError = ::std::option::NoneError;
+pub mod command;
+pub mod integration;
+pub mod syntax;
+
+pub use self::command::{CommandId, CommandStatus, Completion};
+
+use self::command::Tracker;
+
+/// variables by name, sorted so they are listed the same way every time
+pub type Environment = BTreeMap<OsString, OsString>;
+
+const LOGIN_FLAG: &str = "-l";
+
+const TERM: &str = "xterm-256color";
+const COLORTERM: &str = "truecolor";
+const GERM_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const DEFAULT_EXIT_TIMEOUT: Duration = Duration::from_millis(500);
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug)]
+pub enum Error {
+    /// the pseudo-terminal could not be set up
+    Pty(io::Error),
+    /// the shell binary could not be started
+    Spawn { path: String, source: io::Error },
+    /// the terminal's output could not be read
+    Read(io::Error),
+    /// every output stream has closed, usually because the shell exited
+    Closed,
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        return Error::Io(error);
+    }
+}
+
+impl From<stream::Error<()>> for Error {
+    fn from(error: stream::Error<()>) -> Self {
+        return Error::Read(error.source);
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Pty(ref e) => write!(f, "could not open a pseudo-terminal: {}", e),
+            Error::Spawn {
+                ref path,
+                ref source,
+            } if source.kind() == io::ErrorKind::NotFound => write!(
+                f,
+                "could not find the shell `{}`, check `path` in the [shell] section of the config",
+                path
+            ),
+            Error::Spawn {
+                ref path,
+                ref source,
+            } => write!(f, "could not start the shell `{}`: {}", path, source),
+            Error::Read(ref e) => write!(f, "could not read the shell's output: {}", e),
+            Error::Closed => write!(f, "the shell's output has closed"),
+            Error::Io(ref e) => write!(f, "shell i/o failed: {}", e),
+        }
+    }
+}
+
+/// terminal control characters forwarded to the foreground job
+/// the line discipline turns them into signals or end-of-file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    /// ^C, SIGINT
+    Interrupt,
+    /// ^Z, SIGTSTP
+    Suspend,
+    /// ^\, SIGQUIT
+    Quit,
+    /// ^D, end-of-file
+    Eof,
+}
+
+impl Control {
+    pub fn byte(&self) -> u8 {
+        match *self {
+            Control::Interrupt => 0x03,
+            Control::Suspend => 0x1a,
+            Control::Quit => 0x1c,
+            Control::Eof => 0x04,
+        }
+    }
+}
 
 pub struct Shell {
+    config: config::Shell,
     child: Child,
-    stdin: ChildStdin,
-    stdout: stream::LockByteStream,
-    stderr: stream::LockByteStream,
+    pty: Pty,
+    stdin: File,
+    /// stdout and stderr both go to the terminal, so there is one stream of output
+    output: stream::ChunkStream<()>,
+    decoder: Decoder,
+    /// the variables the shell was started with
+    environment: Environment,
+    tracker: Tracker,
+    exit_timeout: Duration,
+    exit_status: Option<ExitStatus>,
+    /// rows and columns last set with `resize`
+    size: Option<(u16, u16)>,
 }
 
 impl Shell {
-    pub fn create(bin_path: PathBuf) -> Result<Shell, ShellError> {
-        let mut child = Command::new(&bin_path)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("should work dummy");
+    /// start a shell with the default launch configuration
+    pub fn create(bin_path: PathBuf) -> Result<Shell, Error> {
+        return Shell::from_config(config::Shell::new(bin_path));
+    }
+
+    pub fn from_config(config: config::Shell) -> Result<Shell, Error> {
+        let (pty, slave) = Pty::open().map_err(Error::Pty)?;
 
-        let stdin = child.stdin.take()?;
-        let stdout = child.stdout.take()?;
-        let stderr = child.stderr.take()?;
+        // commands can only be tracked in bash, through germ's rc file
+        let rcfile = if integration::is_bash(&config.path) {
+            match integration::install_rcfile() {
+                Ok(rcfile) => Some(rcfile),
+                Err(e) => {
+                    eprintln!("could not write the shell integration rc file:\n{}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let tracker = match rcfile {
+            Some(_) => Tracker::new(),
+            None => Tracker::without_markers(),
+        };
+
+        let environment = environment(&config);
+        let rcfile = rcfile.as_ref().map(PathBuf::as_path);
+        let mut command = build_command(&config, &environment, rcfile);
+        pty::attach(&mut command, &slave).map_err(Error::Pty)?;
+
+        let child = command.spawn().map_err(|e| Error::Spawn {
+            path: config.path.clone(),
+            source: e,
+        })?;
+        // the child holds its own handles to the slave
+        drop(slave);
 
-        let stdout_stream = stream::LockByteStream::spawn(stdout);
-        let stderr_stream = stream::LockByteStream::spawn(stderr);
+        let stdin = pty.try_clone_master().map_err(Error::Pty)?;
+        let reader = pty.reader().map_err(Error::Pty)?;
+        let output = stream::ChunkStream::spawn(vec![((), reader)]);
+
+        let exit_timeout = config
+            .exit_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_EXIT_TIMEOUT);
+        let encoding = config.encoding.unwrap_or_default();
 
         return Ok(Shell {
+            config: config,
             child: child,
+            pty: pty,
             stdin: stdin,
-            stdout: stdout_stream,
-            stderr: stderr_stream,
+            output: output,
+            decoder: Decoder::new(encoding),
+            environment: environment,
+            tracker: tracker,
+            exit_timeout: exit_timeout,
+            exit_status: None,
+            size: None,
         });
     }
 
     pub fn execute(&mut self, command: &str) -> io::Result<()> {
-        return self.stdin.write_all(command.as_ref());
+        return self.send(command.as_bytes());
+    }
+
+    /// write to the terminal as if typed
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        return self.stdin.write_all(bytes);
+    }
+
+    /// run a command and track it until the shell reports it finished
+    /// while a command is running the input is sent to it and `None` returned,
+    /// shells without integration take every input as a command that never finishes
+    pub fn submit(&mut self, command: &str) -> io::Result<Option<CommandId>> {
+        let mut command = String::from(command);
+        if !command.ends_with('\n') {
+            command.push('\n');
+        }
+
+        self.execute(&command)?;
+        return Ok(self.tracker.submit(&command));
+    }
+
+    /// true if the program in the foreground reads key presses itself
+    /// instead of lines, like an editor or a pager
+    pub fn raw_mode(&self) -> bool {
+        match self.pty.is_canonical() {
+            Ok(canonical) => return !canonical,
+            Err(e) => {
+                eprintln!("could not get the terminal mode:\n{}", e);
+                return false;
+            }
+        }
+    }
+
+    /// tell the shell and its jobs how many rows and columns the terminal has
+    pub fn resize(&mut self, rows: u16, columns: u16) -> io::Result<()> {
+        self.pty.resize(rows, columns)?;
+        self.size = Some((rows, columns));
+        return Ok(());
+    }
+
+    /// send a control character to the foreground process group
+    pub fn send_control(&mut self, control: Control) -> io::Result<()> {
+        return self.stdin.write_all(&[control.byte()]);
+    }
+
+    pub fn poll_completion(&mut self) -> Option<Completion> {
+        return self.tracker.poll_completion();
     }
 
-    pub fn poll_stdout(&mut self) -> io::Result<Option<Vec<u8>>> {
-        match self.stdout.poll() {
-            Ok(Async::Ready(Some(result))) => {
-                return Ok(Some(result));
+    pub fn status(&self) -> Option<CommandStatus> {
+        return self.tracker.status();
+    }
+
+    /// how the shell was started
+    pub fn config(&self) -> &config::Shell {
+        return &self.config;
+    }
+
+    /// the variables the shell was started with, it may have changed them since
+    pub fn environment(&self) -> &Environment {
+        return &self.environment;
+    }
+
+    /// get notified from another thread when output is ready to be polled
+    pub fn set_waker(&self, waker: stream::Waker) {
+        self.output.set_waker(waker);
+    }
+
+    /// poll for the next chunk of decoded terminal output
+    /// shell integration markers are removed from the output
+    pub fn poll_output(&mut self) -> Result<Option<String>, Error> {
+        match self.output.try_next()? {
+            Async::Ready(Some(chunk)) => {
+                let bytes = self.tracker.process(&chunk.bytes);
+                return Ok(Some(self.decoder.decode(&bytes)));
             }
-            Ok(Async::NotReady) => {
-                return Ok(None);
+            Async::NotReady => return Ok(None),
+            Async::Ready(None) => {
+                // flush sequences cut off by the end of the stream
+                let text = self.decoder.finish();
+                if !text.is_empty() {
+                    return Ok(Some(text));
+                }
+                return Err(Error::Closed);
             }
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::BrokenPipe,
-                    "could not access stream",
-                ));
+        }
+    }
+
+    /// check if the shell has exited without blocking,
+    /// reaping the child if it has
+    pub fn poll_exit(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.exit_status.is_none() {
+            self.exit_status = self.child.try_wait()?;
+        }
+        return Ok(self.exit_status);
+    }
+
+    /// shut down the shell, escalating from end-of-file to SIGHUP,
+    /// SIGTERM and finally SIGKILL if it doesn't exit in time
+    pub fn exit(&mut self) -> io::Result<ExitStatus> {
+        if let Some(status) = self.poll_exit()? {
+            return Ok(status);
+        }
+
+        // the shell may already be gone, in which case the write fails
+        let _ = self.send_control(Control::Eof);
+        if let Some(status) = self.wait_timeout()? {
+            return Ok(status);
+        }
+
+        for &signal in &[libc::SIGHUP, libc::SIGTERM] {
+            self.signal_group(signal)?;
+            if let Some(status) = self.wait_timeout()? {
+                return Ok(status);
+            }
+        }
+
+        self.signal_group(libc::SIGKILL)?;
+        let status = self.child.wait()?;
+        self.exit_status = Some(status);
+        return Ok(status);
+    }
+
+    /// exit the shell if it's still running and start a new one
+    pub fn restart(&mut self) -> Result<(), Error> {
+        if let Err(e) = self.exit() {
+            eprintln!("could not shut down shell:\n{}", e);
+        }
+
+        let waker = self.output.waker();
+        let size = self.size;
+        *self = Shell::from_config(self.config.clone())?;
+        if let Some(waker) = waker {
+            self.set_waker(waker);
+        }
+        if let Some((rows, columns)) = size {
+            self.resize(rows, columns)?;
+        }
+
+        return Ok(());
+    }
+
+    fn wait_timeout(&mut self) -> io::Result<Option<ExitStatus>> {
+        let start = Instant::now();
+        while start.elapsed() < self.exit_timeout {
+            if let Some(status) = self.poll_exit()? {
+                return Ok(Some(status));
             }
+            sleep(EXIT_POLL_INTERVAL);
         }
+        return Ok(None);
     }
 
-    pub fn exit(&mut self) -> io::Result<()> {
-        return self.child.kill();
+    /// the shell is a session leader, so its process group shares its pid
+    fn signal_group(&self, signal: libc::c_int) -> io::Result<()> {
+        let pgid = self.child.id() as libc::pid_t;
+        if unsafe { libc::killpg(pgid, signal) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(());
     }
 }
 
+impl Drop for Shell {
+    fn drop(&mut self) {
+        if let Err(e) = self.exit() {
+            eprintln!("could not shut down shell:\n{}", e);
+        }
+    }
+}
+
+/// `rcfile` is germ's rc file if the shell is bash and it could be written
+fn build_command(
+    config: &config::Shell,
+    environment: &Environment,
+    rcfile: Option<&Path>,
+) -> Command {
+    let mut command = Command::new(&config.path);
+    command.env_clear().envs(environment);
+    let no_args = Vec::new();
+    let args = config.args.as_ref().unwrap_or(&no_args);
+    let login = config.login.unwrap_or(false);
+
+    match rcfile {
+        Some(rcfile) => {
+            let startup = integration::bash_startup(args, login, rcfile);
+            command.args(&startup.args).envs(startup.env);
+        }
+        None => {
+            // user arguments go first, since bash only accepts long options
+            // before single character ones
+            command.args(args);
+            if login {
+                command.arg(LOGIN_FLAG);
+            }
+        }
+    }
+
+    if let Some(ref dir) = config.working_directory {
+        command.current_dir(expand_home(dir));
+    }
+
+    return command;
+}
+
+/// germ's own variables with the ones for terminals and the configured changes
+fn environment(config: &config::Shell) -> Environment {
+    let mut environment: Environment = env::vars_os().collect();
+    environment.insert(OsString::from("TERM"), OsString::from(TERM));
+    environment.insert(OsString::from("COLORTERM"), OsString::from(COLORTERM));
+    environment.insert(OsString::from("GERM_VERSION"), OsString::from(GERM_VERSION));
+
+    if let Some(ref vars) = config.env {
+        for (name, value) in vars {
+            environment.insert(OsString::from(name), OsString::from(value));
+        }
+    }
+    if let Some(ref vars) = config.env_remove {
+        for var in vars {
+            environment.remove(OsStr::new(var));
+        }
+    }
+    return environment;
+}
+
+/// expand a leading `~` to the user's home directory
+fn expand_home(path: &str) -> PathBuf {
+    if path == "~" || path.starts_with("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return PathBuf::from(home).join(path[1..].trim_start_matches('/'));
+        }
+    }
+    return PathBuf::from(path);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::fs;
     use std::io;
+    use std::process;
 
     use test::Bencher;
 
     const BASH_SHELL_PATH: &str = "/bin/bash";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// skip the user's rc files so tests don't depend on them
+    fn test_config() -> config::Shell {
+        let mut config = config::Shell::new(BASH_SHELL_PATH.into());
+        config.args = Some(vec!["--norc".into()]);
+        return config;
+    }
 
     fn test_shell() -> Shell {
-        return Shell::create(BASH_SHELL_PATH.into()).expect("could not create test `bash` shell");
+        return Shell::from_config(test_config()).expect("could not create test `bash` shell");
     }
 
     #[test]
     fn test_create() {
-        test_shell();
+        Shell::create(BASH_SHELL_PATH.into()).expect("could not create test `bash` shell");
+    }
+
+    #[test]
+    fn test_missing_binary() {
+        match Shell::create("/nonexistent/germ-shell".into()) {
+            Err(Error::Spawn { path, source }) => {
+                assert_eq!(path, "/nonexistent/germ-shell");
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            Err(e) => assert!(false, "unexpected error: {}", e),
+            Ok(_) => assert!(false, "shell started from a missing binary"),
+        }
     }
 
     fn test_command_with_known_output(
         shell: &mut Shell,
         command: &str,
         expected_output: &String,
-    ) -> io::Result<()> {
-        shell.execute(command);
+    ) -> Result<(), Error> {
+        shell.execute(command).expect("could not execute command");
         let mut buffer: String = String::new();
-        let max_iters = 1000000;
-        let mut iters = 0;
-        while (!buffer.eq(expected_output)) {
-            iters += 1;
-            if (iters >= max_iters) {
-                return Err(io::Error::new(
+        let start = Instant::now();
+        while !buffer.contains(expected_output.as_str()) {
+            if start.elapsed() >= TIMEOUT {
+                return Err(Error::Io(io::Error::new(
                     io::ErrorKind::TimedOut,
-                    format!("too many iterations\nbuffer: {}", buffer),
-                ));
+                    format!("timed out\nbuffer: {}", buffer),
+                )));
             }
-            match shell.poll_stdout() {
-                Ok(output) => output.map(|s| {
-                    let s = String::from_utf8(s).expect("could not parse shell stdout");
-                    buffer.push_str(&s);
-                }),
-                Err(ioerr) => return Err(ioerr),
+            match shell.poll_output() {
+                Ok(Some(text)) => buffer.push_str(&text),
+                Ok(_) => (),
+                Err(e) => return Err(e),
             };
         }
         return Ok(());
     }
 
-    fn hello_world(shell: &mut Shell) -> io::Result<()> {
-        let expected_output = "hello world\n".to_string();
-        let command = "echo hello world\n";
+    fn hello_world(shell: &mut Shell) -> Result<(), Error> {
+        // the terminal translates newlines and the quotes keep
+        // the echoed command from matching the output
+        let expected_output = "hello world\r\n".to_string();
+        let command = "echo hello' 'world\n";
 
         return test_command_with_known_output(shell, command, &expected_output);
     }
@@ -125,6 +507,166 @@ mod tests {
         hello_world(&mut test_shell()).expect("could not run hello world");
     }
 
+    #[test]
+    fn test_stderr() {
+        let mut shell = test_shell();
+        // stderr is the terminal too, so it comes through with the rest of the output
+        let expected_output = "oops\r\n";
+
+        shell
+            .execute("echo oo''ps >&2\n")
+            .expect("could not write command");
+
+        let mut buffer: String = String::new();
+        let start = Instant::now();
+        while !buffer.contains(expected_output) {
+            assert!(start.elapsed() < TIMEOUT, "timed out\nbuffer: {}", buffer);
+
+            if let Some(text) = shell.poll_output().expect("could not poll shell output") {
+                buffer.push_str(&text);
+            }
+        }
+    }
+
+    fn wait_for_completion(shell: &mut Shell) -> Completion {
+        return wait_for_output(shell).0;
+    }
+
+    /// the next completion and the output seen until it
+    fn wait_for_output(shell: &mut Shell) -> (Completion, String) {
+        let mut buffer = String::new();
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < TIMEOUT, "command did not complete\nbuffer: {}", buffer);
+
+            if let Some(text) = shell.poll_output().expect("could not poll shell output") {
+                buffer.push_str(&text);
+            }
+            if let Some(completion) = shell.poll_completion() {
+                return (completion, buffer);
+            }
+        }
+    }
+
+    #[test]
+    fn test_launch_config() {
+        let mut config = test_config();
+        config.working_directory = Some("/".into());
+        config.env = Some(
+            vec![("GERM_TEST".to_string(), "hello".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        config.env_remove = Some(vec!["COLORTERM".into()]);
+
+        let mut shell = Shell::from_config(config).expect("could not create configured shell");
+
+        test_command_with_known_output(
+            &mut shell,
+            "echo \"[$PWD:$GERM_TEST:$TERM:${COLORTERM-unset}]\"\n",
+            &"[/:hello:xterm-256color:unset]\r\n".to_string(),
+        )
+        .expect("launch configuration was not applied");
+    }
+
+    #[test]
+    fn test_latin1() {
+        let mut config = test_config();
+        config.encoding = Some(::encoding::Encoding::Latin1);
+        let mut shell = Shell::from_config(config).expect("could not create latin-1 shell");
+
+        test_command_with_known_output(
+            &mut shell,
+            "printf '[caf\\351]'\n",
+            &"[café]".to_string(),
+        )
+        .expect("output was not decoded as latin-1");
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut shell = test_shell();
+        shell.resize(30, 100).expect("could not resize shell");
+
+        test_command_with_known_output(&mut shell, "stty size\n", &"30 100".to_string())
+            .expect("shell did not see the new size");
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut shell = test_shell();
+
+        shell.submit("true").expect("could not submit command");
+        assert_eq!(shell.status(), Some(CommandStatus::Running));
+        assert_eq!(wait_for_completion(&mut shell).exit_code, 0);
+
+        shell.submit("false").expect("could not submit command");
+        assert_eq!(wait_for_completion(&mut shell).exit_code, 1);
+        assert_eq!(shell.status(), Some(CommandStatus::Failed(1)));
+    }
+
+    #[test]
+    fn test_user_prompt_command() {
+        // the status of the user's prompt command isn't the command's
+        let rcfile = env::temp_dir().join(format!("germ-{}-bashrc", process::id()));
+        fs::write(&rcfile, "export PROMPT_COMMAND='printf \"<%s>\" user; false'\n")
+            .expect("could not write test rc file");
+        let mut config = config::Shell::new(BASH_SHELL_PATH.into());
+        config.args = Some(vec![
+            "--rcfile".into(),
+            rcfile.to_string_lossy().into_owned(),
+        ]);
+        let mut shell = Shell::from_config(config).expect("could not create test `bash` shell");
+
+        shell.submit("true").expect("could not submit command");
+        let (completion, output) = wait_for_output(&mut shell);
+        assert_eq!(completion.exit_code, 0);
+        assert!(output.contains("<user>"), "output: {}", output);
+
+        // a shell started from this one would print markers if it inherited it
+        shell
+            .submit("env | grep -q ^PROMPT_COMMAND=")
+            .expect("could not submit command");
+        assert_eq!(wait_for_completion(&mut shell).exit_code, 1);
+
+        fs::remove_file(&rcfile).expect("could not remove test rc file");
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut shell = test_shell();
+        // wait for the shell to be ready for input
+        shell.submit("true").expect("could not submit command");
+        wait_for_completion(&mut shell);
+
+        shell
+            .submit("sleep 60")
+            .expect("could not start long running command");
+        sleep(Duration::from_millis(200));
+        shell
+            .send_control(Control::Interrupt)
+            .expect("could not interrupt command");
+
+        // 128 + SIGINT
+        assert_eq!(wait_for_completion(&mut shell).exit_code, 130);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut shell = test_shell();
+        // wait for the shell to be ready for input
+        shell.submit("true").expect("could not submit command");
+        wait_for_completion(&mut shell);
+
+        let status = shell.exit().expect("could not exit shell");
+
+        assert!(status.success());
+        assert_eq!(
+            shell.poll_exit().expect("could not poll exit status"),
+            Some(status)
+        );
+    }
+
     #[test]
     fn test_usr_bin() {}
 
@@ -133,7 +675,7 @@ mod tests {
         let mut shell: Shell = test_shell();
 
         bencher.iter(|| {
-            hello_world(&mut shell);
+            hello_world(&mut shell).expect("could not run hello world");
         })
     }
 }
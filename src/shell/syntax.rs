@@ -0,0 +1,472 @@
+/// operators, longest first so `&&` isn't read as two `&`
+const OPERATORS: &[&str] = &[
+    "<<<", "<<-", "&>>", "&&", "||", ";;", "|&", "<<", ">>", "<&", ">&", "<>", "&>", ">|", "|",
+    "&", ";", "(", ")", "<", ">",
+];
+/// operators that separate commands, the next word starts a command
+const CONTROL_OPERATORS: &[&str] = &["|", "||", "&&", "|&", ";", ";;", "&", "(", ")"];
+/// operators that can't end the input, the command after them is still to come
+const CONTINUING_OPERATORS: &[&str] = &["|", "||", "&&", "|&"];
+
+/// why the input isn't a whole command yet
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incomplete {
+    /// a quote of this kind is open
+    Quote(char),
+    /// the input ends with a backslash
+    Escape,
+    /// a `$(`, `${` or backtick substitution is open
+    Substitution,
+    /// a here document hasn't had the line with its delimiter
+    Heredoc(String),
+    /// the input ends with an operator that needs a command after it
+    Operator(&'static str),
+    /// a block is missing the keyword that closes it
+    Block(&'static str),
+}
+
+/// a piece of shell input
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    /// a word as it was typed, with its quotes and escapes
+    Word(String),
+    /// a control operator like `&&` or a redirection like `>>`
+    Operator(&'static str),
+    Newline,
+}
+
+/// why `input` can't be run yet, none if the shell would run it as it is,
+/// input the shell would reject is left for the shell to report
+pub fn incomplete(input: &str) -> Option<Incomplete> {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(incomplete) => return Some(incomplete),
+    };
+
+    // empty lines after a pipe don't end the pipeline
+    match tokens.iter().rev().find(|token| **token != Token::Newline) {
+        Some(&Token::Operator(operator)) if CONTINUING_OPERATORS.contains(&operator) => {
+            return Some(Incomplete::Operator(operator))
+        }
+        _ => (),
+    }
+    return open_block(&tokens).map(Incomplete::Block);
+}
+
+pub fn is_complete(input: &str) -> bool {
+    return incomplete(input).is_none();
+}
+
+/// split input into words and operators, here document bodies are skipped
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Incomplete> {
+    let mut lexer = Lexer {
+        chars: input.chars().collect(),
+        position: 0,
+        heredocs: Vec::new(),
+    };
+    return lexer.tokens(false);
+}
+
+/// the keyword that closes the innermost block left open, following the
+/// keywords that can only be keywords where a command starts
+fn open_block(tokens: &[Token]) -> Option<&'static str> {
+    let mut open: Vec<&'static str> = Vec::new();
+    let mut command_start = true;
+    // a function's name comes between `function` and its body
+    let mut function_name = false;
+
+    for token in tokens {
+        let word = match *token {
+            Token::Newline => {
+                command_start = true;
+                continue;
+            }
+            Token::Operator(operator) => {
+                match operator {
+                    "(" => open.push(")"),
+                    // also the end of a case pattern
+                    ")" if open.last() == Some(&")") => {
+                        open.pop();
+                    }
+                    _ => (),
+                }
+                command_start = CONTROL_OPERATORS.contains(&operator);
+                continue;
+            }
+            Token::Word(ref word) => word.as_str(),
+        };
+
+        if function_name {
+            function_name = false;
+            command_start = true;
+            continue;
+        }
+        if !command_start {
+            continue;
+        }
+        command_start = false;
+        match word {
+            "if" => {
+                open.push("fi");
+                command_start = true;
+            }
+            "while" | "until" => {
+                open.push("done");
+                command_start = true;
+            }
+            // the loop's variable comes first
+            "for" | "select" => open.push("done"),
+            "case" => open.push("esac"),
+            "{" => {
+                open.push("}");
+                command_start = true;
+            }
+            "then" | "else" | "elif" | "do" | "!" | "time" => command_start = true,
+            "function" => function_name = true,
+            "fi" | "done" | "esac" | "}" => {
+                if open.last() == Some(&word) {
+                    open.pop();
+                }
+            }
+            _ => (),
+        }
+    }
+    return open.last().cloned();
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    position: usize,
+    /// here documents whose bodies start after the next newline,
+    /// with their delimiters and whether leading tabs are stripped
+    heredocs: Vec<(String, bool)>,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        return self.chars.get(self.position).cloned();
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.position += 1;
+        }
+        return c;
+    }
+
+    /// the operator at the current position
+    fn operator(&self) -> Option<&'static str> {
+        let rest = &self.chars[self.position..];
+        return OPERATORS
+            .iter()
+            .find(|operator| {
+                operator.chars().count() <= rest.len()
+                    && operator.chars().zip(rest).all(|(a, &b)| a == b)
+            })
+            .cloned();
+    }
+
+    /// tokens up to the end of the input, or if `nested` in a command substitution,
+    /// up to and including the `)` that closes it
+    fn tokens(&mut self, nested: bool) -> Result<Vec<Token>, Incomplete> {
+        let mut tokens = Vec::new();
+        // parentheses opened since the substitution started
+        let mut depth = 0;
+        // set after `<<` until the delimiter, with whether tabs are stripped
+        let mut heredoc = None;
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => self.position += 1,
+                '\n' => {
+                    self.position += 1;
+                    tokens.push(Token::Newline);
+                    heredoc = None;
+                    self.heredoc_bodies()?;
+                }
+                '#' => {
+                    while self.peek().map_or(false, |c| c != '\n') {
+                        self.position += 1;
+                    }
+                }
+                _ => match self.operator() {
+                    Some(")") if nested && depth == 0 => {
+                        self.position += 1;
+                        return Ok(tokens);
+                    }
+                    Some(operator) => {
+                        match operator {
+                            "(" => depth += 1,
+                            ")" if depth > 0 => depth -= 1,
+                            "<<" => heredoc = Some(false),
+                            "<<-" => heredoc = Some(true),
+                            _ => (),
+                        }
+                        self.position += operator.len();
+                        tokens.push(Token::Operator(operator));
+                    }
+                    None => {
+                        let word = self.word()?;
+                        if let Some(strip_tabs) = heredoc.take() {
+                            self.heredocs.push((unquote(&word), strip_tabs));
+                        }
+                        tokens.push(Token::Word(word));
+                    }
+                },
+            }
+        }
+
+        if nested {
+            return Err(Incomplete::Substitution);
+        }
+        if let Some(&(ref delimiter, _)) = self.heredocs.first() {
+            return Err(Incomplete::Heredoc(delimiter.clone()));
+        }
+        return Ok(tokens);
+    }
+
+    /// skip the lines of the pending here documents, each up to its delimiter
+    fn heredoc_bodies(&mut self) -> Result<(), Incomplete> {
+        let heredocs: Vec<_> = self.heredocs.drain(..).collect();
+        for (delimiter, strip_tabs) in heredocs {
+            loop {
+                if self.peek().is_none() {
+                    return Err(Incomplete::Heredoc(delimiter));
+                }
+                let mut line = String::new();
+                while let Some(c) = self.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                    line.push(c);
+                }
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    line.as_str()
+                };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// a word up to the next unquoted blank or operator
+    fn word(&mut self) -> Result<String, Incomplete> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '<' | '>' | '(' | ')' => break,
+                _ => self.word_part()?,
+            }
+        }
+        return Ok(self.chars[start..self.position].iter().collect());
+    }
+
+    /// a character of a word, or all of a quote or substitution that starts there
+    fn word_part(&mut self) -> Result<(), Incomplete> {
+        match self.next() {
+            Some('\\') => match self.next() {
+                // a line ending in a backslash goes on in the next
+                Some('\n') if self.peek().is_none() => return Err(Incomplete::Escape),
+                Some(_) => (),
+                None => return Err(Incomplete::Escape),
+            },
+            Some('\'') => self.skip_until('\'', false, Incomplete::Quote('\''))?,
+            Some('"') => self.double_quote()?,
+            Some('`') => self.skip_until('`', true, Incomplete::Substitution)?,
+            Some('$') => self.dollar()?,
+            _ => (),
+        }
+        return Ok(());
+    }
+
+    /// skip past `close`, returning `open` if the input ends first
+    fn skip_until(
+        &mut self,
+        close: char,
+        escapes: bool,
+        open: Incomplete,
+    ) -> Result<(), Incomplete> {
+        loop {
+            match self.next() {
+                Some(c) if c == close => return Ok(()),
+                Some('\\') if escapes => {
+                    if self.next().is_none() {
+                        return Err(open);
+                    }
+                }
+                Some(_) => (),
+                None => return Err(open),
+            }
+        }
+    }
+
+    /// skip the rest of a double quoted string, substitutions in it included
+    fn double_quote(&mut self) -> Result<(), Incomplete> {
+        loop {
+            match self.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => {
+                    if self.next().is_none() {
+                        return Err(Incomplete::Quote('"'));
+                    }
+                }
+                Some('`') => self.skip_until('`', true, Incomplete::Substitution)?,
+                Some('$') => self.dollar()?,
+                Some(_) => (),
+                None => return Err(Incomplete::Quote('"')),
+            }
+        }
+    }
+
+    /// skip what follows a `$`, if it opens a substitution or a quote
+    fn dollar(&mut self) -> Result<(), Incomplete> {
+        match self.peek() {
+            Some('(') => {
+                self.position += 1;
+                if self.peek() == Some('(') {
+                    self.position += 1;
+                    return self.arithmetic();
+                }
+                self.tokens(true)?;
+            }
+            Some('{') => {
+                self.position += 1;
+                loop {
+                    match self.next() {
+                        Some('}') => break,
+                        Some('\\') => {
+                            if self.next().is_none() {
+                                return Err(Incomplete::Escape);
+                            }
+                        }
+                        Some('\'') => self.skip_until('\'', false, Incomplete::Quote('\''))?,
+                        Some('"') => self.double_quote()?,
+                        Some('`') => self.skip_until('`', true, Incomplete::Substitution)?,
+                        Some('$') => self.dollar()?,
+                        Some(_) => (),
+                        None => return Err(Incomplete::Substitution),
+                    }
+                }
+            }
+            // `$'...'` quotes with backslash escapes
+            Some('\'') => {
+                self.position += 1;
+                self.skip_until('\'', true, Incomplete::Quote('\''))?;
+            }
+            _ => (),
+        }
+        return Ok(());
+    }
+
+    /// skip the rest of a `$((` arithmetic expansion
+    fn arithmetic(&mut self) -> Result<(), Incomplete> {
+        let mut depth = 2;
+        loop {
+            match self.next() {
+                Some('(') => depth += 1,
+                Some(')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                Some(_) => (),
+                None => return Err(Incomplete::Substitution),
+            }
+        }
+    }
+}
+
+/// a here document's delimiter without the quotes that keep its body from being expanded
+fn unquote(word: &str) -> String {
+    return word.chars().filter(|&c| c != '\'' && c != '"' && c != '\\').collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let word = |text: &str| Token::Word(text.to_string());
+        assert_eq!(
+            tokenize("ls -l 'a b'|grep \"x $(echo ')')\" && echo ${a:-}>>out # c"),
+            Ok(vec![
+                word("ls"),
+                word("-l"),
+                word("'a b'"),
+                Token::Operator("|"),
+                word("grep"),
+                word("\"x $(echo ')')\""),
+                Token::Operator("&&"),
+                word("echo"),
+                word("${a:-}"),
+                Token::Operator(">>"),
+                word("out"),
+            ])
+        );
+        assert_eq!(
+            tokenize("cat <<-EOF\n\tx\n\tEOF\necho"),
+            Ok(vec![
+                word("cat"),
+                Token::Operator("<<-"),
+                word("EOF"),
+                Token::Newline,
+                word("echo"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_complete() {
+        let complete = [
+            "",
+            "ls -l",
+            "echo 'a\nb'",
+            "echo \"$(date)\" $((1 + (2 * 3)))",
+            "echo a \\\n b",
+            "ls |\n grep x",
+            "cat <<'EOF'\nif\nEOF",
+            "if true; then echo yes; fi",
+            "for i in if fi; do echo $i; done | sort",
+            "case $x in a) echo a;; *) echo b;; esac",
+            "f() { echo hi; }",
+            "function f {\n echo hi\n}",
+            "(cd /tmp && ls)",
+            "echo done fi",
+            // the shell reports this one
+            "fi",
+        ];
+        for input in complete.iter() {
+            assert_eq!(incomplete(input), None, "{:?}", input);
+        }
+
+        let incomplete_inputs = [
+            ("echo 'a", Incomplete::Quote('\'')),
+            ("echo \"a\n", Incomplete::Quote('"')),
+            ("echo $'it\\'s", Incomplete::Quote('\'')),
+            ("ls \\", Incomplete::Escape),
+            ("ls \\\n", Incomplete::Escape),
+            ("echo $(date", Incomplete::Substitution),
+            ("echo `date", Incomplete::Substitution),
+            ("echo ${a", Incomplete::Substitution),
+            ("cat <<EOF\nhello", Incomplete::Heredoc("EOF".to_string())),
+            ("ls |", Incomplete::Operator("|")),
+            ("make &&\n", Incomplete::Operator("&&")),
+            ("if true; then\n echo yes", Incomplete::Block("fi")),
+            ("for i in 1 2\ndo", Incomplete::Block("done")),
+            ("while true; do if x; then y; fi", Incomplete::Block("done")),
+            ("case $x in", Incomplete::Block("esac")),
+            ("f() {", Incomplete::Block("}")),
+            ("(cd /tmp", Incomplete::Block(")")),
+        ];
+        for &(ref input, ref expected) in incomplete_inputs.iter() {
+            assert_eq!(incomplete(input).as_ref(), Some(expected), "{:?}", input);
+        }
+    }
+}
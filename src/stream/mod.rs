@@ -1,257 +1,250 @@
 use std::io;
-use std::io::{BufReader, Read};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
-use std::sync::Arc;
-use std::thread::{spawn, JoinHandle};
+use std::io::Read;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
 
+use futures::task;
+use futures::task::Task;
 use futures::{Async, Poll, Stream};
 
-pub enum Signal {
-    Stop,
-}
-
-struct ByteStream {
-    thread_handle: JoinHandle<io::Result<()>>,
-    receiver: Receiver<u8>,
-    signal_sender: Sender<Signal>,
-}
+/// size of the buffer each reader thread reads into
+const CHUNK_SIZE: usize = 64 * 1024;
+/// chunks held for the consumer before the reader threads wait for it to catch up
+const CHANNEL_BOUND: usize = 16;
 
-impl ByteStream {
-    pub fn spawn<R: Read + Send + 'static>(readable: R) -> Self {
-        let (stream_tx, stream_rx) = channel();
-        let (signal_tx, signal_rx) = channel();
-
-        let thread_handle = spawn(move || {
-            let reader = BufReader::new(readable);
-            let mut bytes = reader.bytes();
-
-            loop {
-                match signal_rx.try_recv() {
-                    Ok(Signal::Stop) => {
-                        return Ok(());
-                    }
-                    Err(TryRecvError::Disconnected) => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::BrokenPipe,
-                            "signal channel is disconnected",
-                        ));
-                    }
-                    _ => {
-                        // empty signal queue
-                    }
-                }
-
-                match bytes.next() {
-                    Some(Ok(byte)) => match stream_tx.send(byte) {
-                        Err(err) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::ConnectionAborted,
-                                format!("unable to send stream\n{}", err),
-                            ))
-                        }
-                        Ok(()) => {}
-                    },
-                    Some(Err(e)) => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "unable to read from stream",
-                        ));
-                    }
-                    None => {}
-                }
-            }
-        });
+/// called from a reader thread whenever new data is available
+/// the consumer should poll its stream from its own thread when woken
+pub type Waker = Arc<dyn Fn() + Send + Sync>;
 
-        return ByteStream {
-            thread_handle: thread_handle,
-            receiver: stream_rx,
-            signal_sender: signal_tx,
-        };
-    }
+/// wake a futures task
+pub fn task_waker(task: Task) -> Waker {
+    return Arc::new(move || task.notify());
+}
 
-    pub fn stop(&self) -> io::Result<()> {
-        match self.signal_sender.send(Signal::Stop) {
-            Ok(()) => {}
-            Err(e) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotConnected,
-                    "signal channel is closed",
-                ));
-            }
-        }
+/// bytes read from one of the sources of a `ChunkStream`
+#[derive(Debug)]
+pub struct Chunk<T> {
+    pub tag: T,
+    pub bytes: Vec<u8>,
+}
 
-        return Ok(());
-    }
+/// a source of a `ChunkStream` failed, it won't be read from again
+#[derive(Debug)]
+pub struct Error<T> {
+    pub tag: T,
+    pub source: io::Error,
 }
 
-impl Stream for ByteStream {
-    type Item = u8;
-    type Error = io::Error;
+#[derive(Clone)]
+struct Notifier {
+    waker: Arc<Mutex<Option<Waker>>>,
+    task: Arc<Mutex<Option<Task>>>,
+}
 
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match self.receiver.try_recv() {
-            Ok(t) => return Ok(Async::Ready(Some(t))),
-            Err(TryRecvError::Empty) => {
-                return Ok(Async::NotReady);
-            }
-            Err(TryRecvError::Disconnected) => {
-                eprintln!("disconnected");
-                return Ok(Async::Ready(None));
-            }
+impl Notifier {
+    fn notify(&self) {
+        if let Some(ref waker) = *self.waker.lock().expect("waker lock poisoned") {
+            waker();
+        }
+        if let Some(ref task) = *self.task.lock().expect("task lock poisoned") {
+            task.notify();
         }
     }
 }
 
-pub struct LockByteStream {
-    thread_handle: JoinHandle<io::Result<()>>,
-    receiver: Receiver<Vec<u8>>,
-    lock: Arc<AtomicBool>,
+/// merges several readers into one stream of tagged chunks in arrival order
+/// each reader gets a thread that blocks on `read`,
+/// so nothing runs while the sources are idle
+pub struct ChunkStream<T> {
+    receiver: Receiver<Result<Chunk<T>, Error<T>>>,
+    notifier: Notifier,
 }
 
-impl LockByteStream {
-    pub fn spawn<R: Read + Send + 'static>(readable: R) -> Self {
-        let (stream_tx, stream_rx) = channel();
-        // let (lock_tx, lock_rx) = channel();
-
-        let lock_test = Arc::new(AtomicBool::new(false));
-
-        let child_lock = lock_test.clone();
-        let thread_handle = spawn(move || {
-            let mut byte_stream = ByteStream::spawn(readable);
-            let mut buffer: Vec<u8> = Vec::new();
-            let local_lock = child_lock;
-
-            loop {
-                match byte_stream.poll() {
-                    Ok(Async::Ready(Some(byte))) => {
-                        buffer.push(byte);
-                    }
-                    Ok(Async::NotReady) => {}
-                    Ok(Async::Ready(None)) => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::ConnectionRefused,
-                            format!("unable to reach byte stream"),
-                        ));
-                    }
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
-
-                if local_lock.load(Ordering::SeqCst) == true && !buffer.is_empty() {
-                    let result = buffer.clone();
-                    match stream_tx.send(result) {
-                        Err(err) => {
-                            return Err(io::Error::new(
-                                io::ErrorKind::ConnectionAborted,
-                                format!("unable to send stream\n{}", err),
-                            ))
-                        }
-                        Ok(()) => buffer.clear(),
-                    }
-                    local_lock.store(false, Ordering::SeqCst);
-                }
-            }
-        });
+impl<T: Clone + Send + 'static> ChunkStream<T> {
+    pub fn spawn<R: Read + Send + 'static>(sources: Vec<(T, R)>) -> Self {
+        let (sender, receiver) = sync_channel(CHANNEL_BOUND);
+        let notifier = Notifier {
+            waker: Arc::new(Mutex::new(None)),
+            task: Arc::new(Mutex::new(None)),
+        };
 
-        return LockByteStream {
-            thread_handle: thread_handle,
-            receiver: stream_rx,
-            lock: lock_test,
+        for (tag, readable) in sources {
+            let sender = sender.clone();
+            let notifier = notifier.clone();
+            spawn(move || read_chunks(tag, readable, sender, notifier));
+        }
+
+        return ChunkStream {
+            receiver: receiver,
+            notifier: notifier,
         };
     }
+}
 
-    pub fn send_buffer(&self) {
-        self.lock.store(true, Ordering::SeqCst);
+impl<T> ChunkStream<T> {
+    pub fn set_waker(&self, waker: Waker) {
+        *self.notifier.waker.lock().expect("waker lock poisoned") = Some(waker);
     }
 
-    pub fn close(self) -> ::std::thread::Result<io::Result<()>> {
-        return self.thread_handle.join();
+    pub fn waker(&self) -> Option<Waker> {
+        return self
+            .notifier
+            .waker
+            .lock()
+            .expect("waker lock poisoned")
+            .clone();
+    }
+
+    /// get the next chunk without blocking,
+    /// for consumers that don't run inside a futures task
+    pub fn try_next(&mut self) -> Poll<Option<Chunk<T>>, Error<T>> {
+        match self.receiver.try_recv() {
+            Ok(Ok(chunk)) => return Ok(Async::Ready(Some(chunk))),
+            Ok(Err(e)) => return Err(e),
+            Err(TryRecvError::Empty) => return Ok(Async::NotReady),
+            // every reader has finished
+            Err(TryRecvError::Disconnected) => return Ok(Async::Ready(None)),
+        }
     }
 }
 
-impl Stream for LockByteStream {
-    type Item = Vec<u8>;
-    type Error = io::Error;
+impl<T> Stream for ChunkStream<T> {
+    type Item = Chunk<T>;
+    type Error = Error<T>;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        self.lock.store(true, Ordering::SeqCst);
-        match self.receiver.try_recv() {
-            Ok(vec) => return Ok(Async::Ready(Some(vec))),
-            Err(TryRecvError::Empty) => {
-                return Ok(Async::NotReady);
-            }
-            Err(TryRecvError::Disconnected) => {
-                return Ok(Async::Ready(None));
-            }
-        }
+        // register before checking the channel so a chunk sent in between isn't missed
+        *self.notifier.task.lock().expect("task lock poisoned") = Some(task::current());
+        return self.try_next();
     }
 }
 
-fn debug_error<T>(msg: &'static str) -> io::Result<T> {
-    return Err(io::Error::new(io::ErrorKind::Other, msg));
+fn read_chunks<T, R: Read>(
+    tag: T,
+    mut readable: R,
+    sender: SyncSender<Result<Chunk<T>, Error<T>>>,
+    notifier: Notifier,
+) where
+    T: Clone,
+{
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+
+    loop {
+        let result = match readable.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(len) => Ok(Chunk {
+                tag: tag.clone(),
+                bytes: buffer[..len].to_vec(),
+            }),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            // reading a pty master fails once the slave side is closed
+            Err(ref e) if e.raw_os_error() == Some(::libc::EIO) => break,
+            Err(e) => Err(Error {
+                tag: tag.clone(),
+                source: e,
+            }),
+        };
+
+        let is_error = result.is_err();
+        if sender.send(result).is_err() {
+            // the stream was dropped
+            return;
+        }
+        notifier.notify();
+
+        if is_error {
+            break;
+        }
+    }
+
+    // disconnect before waking so the consumer sees the end of the stream
+    drop(sender);
+    notifier.notify();
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use futures::{Async, Future};
-
-    use std::io;
-    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::thread::sleep;
-    use std::time::{Duration, Instant};
+    use std::time::Duration;
 
-    const test_readable: &[u8] = "hello world".as_bytes();
-    const SHORT_TIMEOUT_SECS: u64 = 1;
-    const TIMEOUT_SECS: u64 = 3;
+    use test::Bencher;
+
+    const TEST_READABLE: &[u8] = b"hello world";
+    const SHORT_TIMEOUT_MILLIS: u64 = 100;
+
+    fn collect<T: Clone + Send + ::std::fmt::Debug + 'static>(
+        stream: ChunkStream<T>,
+    ) -> Vec<Chunk<T>> {
+        return stream
+            .wait()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("could not collect output from stream");
+    }
 
     #[test]
-    fn test_create_byte_stream() {
-        let mut stream = ByteStream::spawn(test_readable);
-        let mut result: Vec<u8> = Vec::new();
-        let expected = test_readable;
+    fn test_create_stream() {
+        let stream = ChunkStream::spawn(vec![((), TEST_READABLE)]);
 
-        let start_instant = Instant::now();
+        let result: Vec<u8> = collect(stream)
+            .into_iter()
+            .flat_map(|chunk| chunk.bytes)
+            .collect();
 
-        sleep(Duration::from_secs(SHORT_TIMEOUT_SECS));
-        stream.stop().expect("unable to send signal to stream");
+        assert_eq!(result.as_slice(), TEST_READABLE);
+    }
 
-        let result: Vec<u8> = stream
-            .take(expected.len() as u64)
-            .collect()
-            .wait()
-            .expect("could not collect output from stream");
+    #[test]
+    fn test_tagged_sources() {
+        let stream = ChunkStream::spawn(vec![(1, &b"one"[..]), (2, &b"two"[..])]);
+
+        let mut chunks = collect(stream);
+        chunks.sort_by_key(|chunk| chunk.tag);
 
-        assert_eq!(&result, &expected);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].bytes.as_slice(), b"one");
+        assert_eq!(chunks[1].bytes.as_slice(), b"two");
     }
 
     #[test]
-    fn test_create_stream() {
-        let mut stream = LockByteStream::spawn(test_readable);
-
-        let mut result: Vec<u8> = Vec::new();
-        let expected = test_readable;
-
-        sleep(Duration::from_secs(SHORT_TIMEOUT_SECS));
-        stream.send_buffer();
-        sleep(Duration::from_secs(SHORT_TIMEOUT_SECS));
-
-        match stream.poll() {
-            Ok(Async::Ready(Some(result))) => {
-                assert_eq!(result.as_slice(), expected);
-            }
-            Ok(Async::NotReady) => {
-                assert!(false, "stream has no output");
-            }
-            Ok(Async::Ready(None)) => {
-                assert!(false, "error in stream thread");
-            }
-            Err(_) => {
-                assert!(false, "undefined stream error");
-            }
-        };
+    fn test_waker() {
+        let wakes = Arc::new(AtomicUsize::new(0));
+        let (reader, mut writer) = ::pty::Pty::open()
+            .map(|(pty, slave)| (pty.try_clone_master().expect("could not clone master"), slave))
+            .expect("could not open pty");
+
+        let mut stream = ChunkStream::spawn(vec![((), reader)]);
+        let counter = wakes.clone();
+        stream.set_waker(Arc::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        // nothing to read, so nothing should wake
+        sleep(Duration::from_millis(SHORT_TIMEOUT_MILLIS));
+        assert_eq!(wakes.load(Ordering::SeqCst), 0);
+        assert!(stream.try_next().expect("stream failed").is_not_ready());
+
+        ::std::io::Write::write_all(&mut writer, TEST_READABLE).expect("could not write");
+        sleep(Duration::from_millis(SHORT_TIMEOUT_MILLIS));
+        assert!(wakes.load(Ordering::SeqCst) > 0);
+
+        match stream.try_next() {
+            Ok(Async::Ready(Some(chunk))) => assert_eq!(chunk.bytes.as_slice(), TEST_READABLE),
+            _ => assert!(false, "stream has no output"),
+        }
+    }
+
+    #[bench]
+    fn bench_throughput(bencher: &mut Bencher) {
+        let data = vec![b'x'; 16 * 1024 * 1024];
+        bencher.bytes = data.len() as u64;
+
+        bencher.iter(|| {
+            let stream = ChunkStream::spawn(vec![((), ::std::io::Cursor::new(data.clone()))]);
+            collect(stream).len()
+        });
     }
 }
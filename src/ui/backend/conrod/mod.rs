@@ -1,35 +1,67 @@
 pub mod text;
 
+use std::path::Path;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clipboard::{ClipboardContext, ClipboardProvider};
 use conrod;
 use conrod::backend::glium::glium;
 use conrod::backend::glium::glium::glutin;
 use conrod::backend::glium::glium::glutin::{
-    ElementState, Event, EventsLoop, KeyboardInput, ModifiersState, VirtualKeyCode, WindowEvent,
+    ElementState, Event, EventsLoop, KeyboardInput, ModifiersState, MouseButton,
+    MouseScrollDelta, VirtualKeyCode, WindowEvent,
 };
 use conrod::backend::glium::glium::texture::Texture2d;
 use conrod::glium::Surface;
 use conrod::text::Font;
-use conrod::{color, image, widget, Borderable, Colorable, UiCell, Widget};
+use conrod::{color, image, widget, Borderable, Colorable, Positionable, UiCell, Widget};
 
-use super::{load_font, Ui};
-use constants::{DEFAULT_DIMENSIONS, DEFAULT_TITLE};
+use super::{load_font, load_font_style, Error, TextView, Ui};
+use bindings::{Action, Bindings};
+use constants::{DEFAULT_DIMENSIONS, DEFAULT_TITLE, RESIZE_DEBOUNCE_MS};
+use completion;
+use completion::{Completer, Completions};
+use history::{History, Search};
+use input;
+use input::{Key, Modifiers};
 
-use shell::Shell;
+use screen::{char_width, text_width, Attributes, Link, Screen};
+use session;
+use session::{Session, SessionId, SessionManager};
+use shell::Environment;
 
 use ui;
-use ui::TextView;
 
-use self::text::Text;
+use self::text::{Fonts, Style, StyledText, Text, FONT_SIZE, PADDING};
+
+const STDOUT_COLOR: color::Color = color::WHITE;
+const BACKGROUND_COLOR: color::Color = color::BLACK;
+const STATUS_COLOR: color::Color = color::LIGHT_GREY;
+const STATUS_HEIGHT: f64 = 24.0;
+const COMPLETION_COLOR: color::Color = color::DARK_CHARCOAL;
+
+/// frames to keep drawing after the last activity before sleeping,
+/// conrod needs a few updates for its widget state to settle
+const IDLE_FRAMES: usize = 3;
+/// limit the session events handled per frame so input stays responsive
+const MAX_EVENTS_PER_FRAME: usize = 64;
+/// lines scrolled by a step of the mouse wheel
+const SCROLL_LINES: isize = 3;
 
 widget_ids! {
     struct Ids {
         main_canvas,
         input_canvas,
+        status_canvas,
         output_canvas,
 
         command_input,
-        command_output,
-        scrollbar
+        command_status,
+
+        completion_canvas,
+        completion_list
     }
 }
 
@@ -42,7 +74,42 @@ pub struct Conrod {
 
     ids: Ids,
     input_view: Text,
-    output_view: Text,
+    output_view: StyledText,
+    status: String,
+    exit_status: Option<ExitStatus>,
+    /// set when the active session's screen changed since it was last drawn
+    screen_dirty: bool,
+    /// the faces of the output font
+    fonts: Fonts,
+    /// width of a character in the output font at its current size
+    cell_width: f64,
+    /// when the window was last resized, until the sessions are resized to match
+    resized: Option<Instant>,
+    /// how many lines the view is scrolled back into the scrollback
+    scroll_offset: usize,
+    /// set while a full-screen program has the whole window and every key press
+    passthrough: bool,
+    /// held with the last key press, typed characters arrive without them
+    modifiers: Modifiers,
+    /// the window's current title
+    title: String,
+    /// the row and link the pointer is over, drawn underlined
+    hovered: Option<(usize, Link)>,
+    /// set when the pointer moved since the link under it was last found
+    pointer_moved: bool,
+    clipboard: Option<ClipboardContext>,
+    /// where a drag over the output started and where it is now, as rows and columns
+    selection: Option<((usize, usize), (usize, usize))>,
+    /// set while the button that started the selection is held
+    selecting: bool,
+    /// text waiting for the user to agree to paste it
+    pending_paste: Option<String>,
+    /// set while searching the history with ctrl+r
+    search: Option<Search>,
+    /// fills in the word before the cursor on tab
+    completer: Completer,
+    /// set while there's a choice of candidates to tab through, they're listed over the output
+    completions: Option<Completions>,
 }
 
 pub trait Update {
@@ -51,7 +118,7 @@ pub trait Update {
 
 impl Conrod {
     // TODO change name to fit semantics
-    pub fn new(font_family: String, vsync: bool) -> Result<Self, String> {
+    pub fn new(font_family: String, vsync: bool) -> Result<Self, Error> {
         let events_loop = EventsLoop::new();
         let window = glutin::WindowBuilder::new()
             .with_title(DEFAULT_TITLE) // TODO
@@ -61,7 +128,12 @@ impl Conrod {
             .with_multisampling(4); // TODO ??
         let display = match glium::Display::new(window, context, &events_loop) {
             Ok(d) => d,
-            Err(e) => return Err(format!("could not create the display: {}", e)),
+            Err(e) => {
+                return Err(Error::Graphics(format!(
+                    "could not create the display: {}",
+                    e
+                )))
+            }
         };
         let mut ui =
             conrod::UiBuilder::new([DEFAULT_DIMENSIONS[0] as f64, DEFAULT_DIMENSIONS[1] as f64])
@@ -71,21 +143,61 @@ impl Conrod {
 
         let renderer = match conrod::backend::glium::Renderer::new(&display) {
             Ok(r) => r,
-            Err(e) => return Err(format!("could not create renderer: {}", e)),
+            Err(e) => {
+                return Err(Error::Graphics(format!(
+                    "could not create renderer: {}",
+                    e
+                )))
+            }
         };
 
         let image_map = image::Map::<Texture2d>::new();
 
-        let font = load_font(&font_family)
-            .map_err(|e| format!("could not load font:\n{}", e))
-            .and_then(|bytes| {
-                Font::from_bytes(bytes)
-                    .map_err(|e| format!("could not parse font from bytes:\n{}", e))
-            })?;
-        ui.fonts.insert(font);
+        let font = load_font(&font_family).and_then(|bytes| {
+            Font::from_bytes(bytes).map_err(|e| Error::Font {
+                family: font_family.clone(),
+                reason: e.to_string(),
+            })
+        })?;
+        let cell_width = conrod::text::line::width("M", &font, FONT_SIZE);
+        let regular = ui.fonts.insert(font);
+
+        let fonts = {
+            // the regular face stands in for any style the family doesn't have
+            let mut load_style = |bold: bool, italic: bool| {
+                let font = load_font_style(&font_family, bold, italic).and_then(|bytes| {
+                    Font::from_bytes(bytes).map_err(|e| Error::Font {
+                        family: font_family.clone(),
+                        reason: e.to_string(),
+                    })
+                });
+                match font {
+                    Ok(font) => ui.fonts.insert(font),
+                    Err(e) => {
+                        eprintln!("using the regular font face:\n{}", e);
+                        regular
+                    }
+                }
+            };
+
+            Fonts {
+                regular: regular,
+                bold: load_style(true, false),
+                italic: load_style(false, true),
+                bold_italic: load_style(true, true),
+            }
+        };
+
+        let clipboard = match ClipboardContext::new() {
+            Ok(clipboard) => Some(clipboard),
+            Err(e) => {
+                eprintln!("could not open the clipboard:\n{}", e);
+                None
+            }
+        };
 
         let input_view = Text::new(ids.command_input, ids.input_canvas, true);
-        let output_view = Text::new(ids.command_output, ids.output_canvas, false);
+        let output_view = StyledText::new(ids.output_canvas, fonts);
 
         return Ok(Conrod {
             display: display,
@@ -97,79 +209,315 @@ impl Conrod {
 
             input_view: input_view,
             output_view: output_view,
+            status: String::new(),
+            exit_status: None,
+            screen_dirty: true,
+            fonts: fonts,
+            cell_width: cell_width,
+            resized: None,
+            scroll_offset: 0,
+            passthrough: false,
+            modifiers: Modifiers::default(),
+            title: DEFAULT_TITLE.to_string(),
+            hovered: None,
+            pointer_moved: false,
+            clipboard: clipboard,
+            selection: None,
+            selecting: false,
+            pending_paste: None,
+            search: None,
+            completer: Completer::new(),
+            completions: None,
         });
     }
 
     /// convert glutin event into app level event
     /// returns an event and whether to capture it
-    fn process_event(&mut self, event: &Event) -> Option<(ui::Event, bool)> {
+    fn process_event(&mut self, event: &Event, bindings: &Bindings) -> Option<(ui::Event, bool)> {
+        // enter or escape answers the paste prompt
+        if self.pending_paste.is_some() {
+            match pressed_key(event) {
+                Some(VirtualKeyCode::Return) => {
+                    return self
+                        .pending_paste
+                        .take()
+                        .map(|text| (ui::Event::PasteConfirmed(text), true));
+                }
+                Some(VirtualKeyCode::Escape) => {
+                    self.pending_paste = None;
+                    return None;
+                }
+                _ => (),
+            }
+        }
+
+        let action = key_chord(event).and_then(|(key, modifiers)| bindings.action(key, modifiers));
+        // typing anything but the complete key closes the list of candidates
+        if self.completions.is_some() {
+            match pressed_key(event) {
+                None => (),
+                Some(_) if action == Some(Action::Complete) => (),
+                Some(keycode) if is_modifier(keycode) => (),
+                // escape only closes the list
+                Some(VirtualKeyCode::Escape) => {
+                    self.completions = None;
+                    return None;
+                }
+                Some(_) => self.completions = None,
+            }
+        }
+
+        // typing goes into the history search while there is one
+        if self.search.is_some() {
+            match search_key(event, action) {
+                // the key goes on to do what it usually does
+                Some(ui::SearchKey::Accept) => self.search = None,
+                Some(key) => return Some((ui::Event::SearchHistory(key), true)),
+                None => (),
+            }
+        }
+
+        if self.passthrough {
+            // keys bound to actions for the whole window are never sent to the program
+            if let Some(event) = action
+                .filter(Action::is_global)
+                .and_then(|action| self.action_event(action))
+            {
+                return Some((event, true));
+            }
+            if let Some(key_event) = self.passthrough_event(event) {
+                return Some((key_event, true));
+            }
+        }
+
+        // any enter press restarts a shell that has exited
+        if self.exit_status.is_some() && pressed_key(event) == Some(VirtualKeyCode::Return) {
+            return Some((ui::Event::Restart, true));
+        }
+        if let Some(event) = action.and_then(|action| self.input_event(action)) {
+            return Some((event, true));
+        }
+
         match *event {
             Event::WindowEvent { ref event, .. } => match event {
-                // closed or ESC pressed
-                WindowEvent::Closed
-                | WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        },
+                WindowEvent::Closed => Some((ui::Event::Exit, false)),
+                // conrod tracks the pointer, links are found once the events are handled
+                WindowEvent::CursorMoved { .. } => {
+                    self.pointer_moved = true;
+                    None
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    modifiers: ModifiersState { ctrl: true, .. },
                     ..
-                } => Some((ui::Event::Exit, false)),
+                } => self
+                    .hovered
+                    .as_ref()
+                    .map(|&(_, ref link)| (ui::Event::OpenLink(link.url.clone()), true)),
+                // a plain click starts selecting output
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    let xy = self.ui.global_input().current.mouse.xy;
+                    self.selection = self
+                        .output_view
+                        .hit_test(&self.ui, xy)
+                        .map(|position| (position, position));
+                    self.selecting = self.selection.is_some();
+                    self.screen_dirty = true;
+                    None
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Released,
+                    button: MouseButton::Left,
+                    ..
+                } => {
+                    self.selecting = false;
+                    None
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let line_height = self.output_view.line_height() as f32;
+                    let lines = match *delta {
+                        MouseScrollDelta::LineDelta(_, y) => (y * SCROLL_LINES as f32).round(),
+                        MouseScrollDelta::PixelDelta(_, y) => (y / line_height).round(),
+                    } as isize;
+                    if lines == 0 {
+                        None
+                    } else {
+                        Some((ui::Event::ScrollLines(lines), true))
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// the event for an action bound to a key pressed in the input,
+    /// none if the key should go on to the text edit
+    fn input_event(&mut self, action: Action) -> Option<ui::Event> {
+        match action {
+            Action::Submit => return Some(ui::Event::Submit(self.input_view.submit())),
+            Action::ForceSubmit => return Some(ui::Event::ForceSubmit(self.input_view.submit())),
+            // up and down walk the history unless they move between lines of the input
+            Action::HistoryPrevious | Action::HistoryNext
+                if self.input_view.get_text().contains('\n') =>
+            {
+                return None
+            }
+            _ => return self.action_event(action),
+        }
+    }
+
+    /// the event for an action, reading the clipboard to paste
+    fn action_event(&mut self, action: Action) -> Option<ui::Event> {
+        if action != Action::Paste {
+            return ui::Event::from_action(action);
+        }
+        let contents = self
+            .clipboard
+            .as_mut()
+            .map(|clipboard| clipboard.get_contents());
+        match contents {
+            Some(Ok(text)) => return Some(ui::Event::Paste(text)),
+            Some(Err(e)) => {
+                eprintln!("could not read the clipboard:\n{}", e);
+                return None;
+            }
+            None => return None,
+        }
+    }
+
+    /// turn key presses into keys for a program that has the terminal
+    fn passthrough_event(&mut self, event: &Event) -> Option<ui::Event> {
+        match *event {
+            Event::WindowEvent { ref event, .. } => match event {
                 WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
-                            modifiers:
-                                ModifiersState {
-                                    shift: true,
-                                    ctrl: false,
-                                    alt: false,
-                                    logo: false,
-                                },
+                            modifiers: state,
                             state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Return),
+                            virtual_keycode: Some(keycode),
                             ..
                         },
                     ..
-                } => Some((ui::Event::Submit(self.input_view.submit()), true)),
-                _ => None,
+                } => {
+                    self.modifiers = Modifiers {
+                        shift: state.shift,
+                        ctrl: state.ctrl,
+                        alt: state.alt,
+                    };
+                    return terminal_key(*keycode, self.modifiers)
+                        .map(|key| ui::Event::Key(key, self.modifiers));
+                }
+                // control characters come from the key presses
+                WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                    let modifiers = Modifiers {
+                        alt: self.modifiers.alt,
+                        ..Modifiers::default()
+                    };
+                    return Some(ui::Event::Key(Key::Char(*c), modifiers));
+                }
+                _ => return None,
             },
-            _ => None,
+            _ => return None,
         }
     }
 
-    fn draw(&mut self) -> Result<(), String> {
+    fn draw(&mut self) -> Result<(), Error> {
         // put ui in a memory cage and draw elements
         {
             let mut ui_cell: conrod::UiCell = self.ui.set_widgets();
 
+            // a full-screen program gets the whole window,
+            // the status line comes back to ask about a paste
+            let show_status = !self.passthrough || self.pending_paste.is_some();
+            let mut canvases = Vec::new();
+            if !self.passthrough {
+                canvases.push((
+                    self.ids.input_canvas,
+                    widget::Canvas::new()
+                        .color(color::BLACK)
+                        .length_weight(0.25)
+                        .parent(self.ids.main_canvas),
+                ));
+            }
+            if show_status {
+                canvases.push((
+                    self.ids.status_canvas,
+                    widget::Canvas::new()
+                        .color(color::BLACK)
+                        .length(STATUS_HEIGHT)
+                        .parent(self.ids.main_canvas),
+                ));
+            }
+            canvases.push((
+                self.ids.output_canvas,
+                widget::Canvas::new()
+                    .color(color::BLACK)
+                    .border(2.0)
+                    .border_color(color::WHITE)
+                    .parent(self.ids.main_canvas),
+            ));
+
             widget::Canvas::new()
                 .color(color::BLACK)
-                .flow_down(&[
-                    (
-                        self.ids.input_canvas,
-                        widget::Canvas::new()
-                            .color(color::BLACK)
-                            .length_weight(0.25)
-                            .parent(self.ids.main_canvas),
-                    ),
-                    (
-                        self.ids.output_canvas,
-                        widget::Canvas::new()
-                            .color(color::BLACK)
-                            .border(2.0)
-                            .border_color(color::WHITE)
-                            .scroll_kids_vertically()
-                            .parent(self.ids.main_canvas),
-                    ),
-                ])
+                .flow_down(&canvases)
                 .set(self.ids.main_canvas, &mut ui_cell);
 
-            widget::Scrollbar::y_axis(self.ids.output_canvas)
-                .auto_hide(true)
-                .set(self.ids.scrollbar, &mut ui_cell);
-
-            self.input_view.update(&mut ui_cell);
+            if show_status {
+                widget::Text::new(self.status.as_str())
+                    .color(STATUS_COLOR)
+                    .mid_left_with_margin_on(self.ids.status_canvas, 16.0)
+                    .parent(self.ids.status_canvas)
+                    .set(self.ids.command_status, &mut ui_cell);
+            }
+            if !self.passthrough {
+                self.input_view.update(&mut ui_cell);
+            }
             self.output_view.update(&mut ui_cell);
+
+            // the candidates are listed over the bottom of the output, the chosen one marked
+            if let (false, &Some(ref completions)) = (self.passthrough, &self.completions) {
+                let (first, shown) = completions.shown();
+                let lines: Vec<String> = shown
+                    .iter()
+                    .enumerate()
+                    .map(|(index, candidate)| {
+                        let marker = if completions.index() == Some(first + index) {
+                            ">"
+                        } else {
+                            " "
+                        };
+                        format!("{} {}", marker, candidate.label)
+                    })
+                    .collect();
+                let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+                let font_size = self.output_view.font_size();
+                let line_height = self.output_view.line_height();
+
+                widget::Canvas::new()
+                    .color(COMPLETION_COLOR)
+                    .border(1.0)
+                    .border_color(color::WHITE)
+                    .w_h(
+                        columns as f64 * self.cell_width + 2.0 * PADDING,
+                        lines.len() as f64 * line_height + 2.0 * PADDING,
+                    )
+                    .bottom_left_with_margin_on(self.ids.output_canvas, PADDING)
+                    .floating(true)
+                    .set(self.ids.completion_canvas, &mut ui_cell);
+                widget::Text::new(&lines.join("\n"))
+                    .color(STDOUT_COLOR)
+                    .font_size(font_size)
+                    .line_spacing(line_height - font_size as f64)
+                    .top_left_with_margin_on(self.ids.completion_canvas, PADDING)
+                    .parent(self.ids.completion_canvas)
+                    .set(self.ids.completion_list, &mut ui_cell);
+            }
         }
 
         if let Some(primitives) = self.ui.draw_if_changed() {
@@ -179,14 +527,18 @@ impl Conrod {
             target.clear_color(0.0, 0.0, 0.0, 1.0);
             self.renderer
                 .draw(&self.display, &mut target, &self.image_map)
-                .unwrap();
-            target.finish().unwrap();
+                .map_err(|e| Error::Graphics(format!("could not draw: {:?}", e)))?;
+            target
+                .finish()
+                .map_err(|e| Error::Graphics(format!("could not swap buffers: {:?}", e)))?;
         }
 
         return Ok(());
     }
 
-    fn events(&mut self) -> Vec<ui::Event> {
+    /// collect pending window events, translating keys with `bindings`,
+    /// blocking until there is one if `wait` is set
+    fn events(&mut self, wait: bool, bindings: &Bindings) -> Vec<ui::Event> {
         let mut events = Vec::new();
         let mut app_events = Vec::new();
 
@@ -194,16 +546,24 @@ impl Conrod {
             events.push(event);
         });
 
-        // TODO move idle check somewhere else
-        // if events.is_empty() {
-        //     self.events_loop.run_forever(|event| {
-        //         events.push(event);
-        //         glium::glutin::ControlFlow::Break
-        //     });
-        // }
+        // the shell wakes the events loop when it has output
+        if wait && events.is_empty() {
+            self.events_loop.run_forever(|event| {
+                events.push(event);
+                glutin::ControlFlow::Break
+            });
+        }
 
         for event in events {
-            let input_captured = match self.process_event(&event) {
+            if let Event::WindowEvent {
+                event: WindowEvent::Resized(..),
+                ..
+            } = event
+            {
+                self.resized = Some(Instant::now());
+            }
+
+            let input_captured = match self.process_event(&event, bindings) {
                 Some((app_event, capture)) => {
                     app_events.push(app_event);
                     capture
@@ -222,51 +582,747 @@ impl Conrod {
         return app_events;
     }
 
-    fn set_output(&mut self, string: &str) {
-        self.output_view.set_text(string);
+    /// size the sessions to the output canvas once the window has kept its size for a moment,
+    /// so a drag doesn't reflow every shell at each step
+    fn apply_resize(&mut self, sessions: &mut SessionManager) {
+        match self.resized {
+            Some(resized) if resized.elapsed() >= Duration::from_millis(RESIZE_DEBOUNCE_MS) => (),
+            _ => return,
+        }
+
+        if let Some([width, height]) = self.ui.wh_of(self.ids.output_canvas) {
+            self.resized = None;
+            let rows = ((height - 2.0 * PADDING) / self.output_view.line_height()).max(1.0);
+            let columns = ((width - 2.0 * PADDING) / self.cell_width).max(1.0);
+            sessions.resize(rows as usize, columns as usize);
+            self.screen_dirty = true;
+        }
+    }
+
+    fn set_title(&mut self, title: String) {
+        if title != self.title {
+            self.display.gl_window().set_title(&title);
+            self.title = title;
+        }
+    }
+
+    /// draw the output at `font_size`, the sessions are resized to fit as many cells as it allows
+    fn set_font_size(&mut self, font_size: u32) {
+        self.output_view.set_font_size(font_size);
+        let font_size = self.output_view.font_size();
+        if let Some(font) = self.ui.fonts.get(self.fonts.regular) {
+            self.cell_width = conrod::text::line::width("M", font, font_size);
+        }
+        self.resized = Some(Instant::now());
+        self.screen_dirty = true;
+    }
+
+    /// show another session, forgetting what was shown of the last one
+    fn switch_session(&mut self, session: &Session) {
+        self.exit_status = session.exit_status();
+        self.scroll_offset = 0;
+        self.selection = None;
+        self.selecting = false;
+        self.hovered = None;
+        self.search = None;
+        self.completions = None;
+        self.screen_dirty = true;
     }
+
+    /// scroll the view by `lines`, back into the scrollback if positive
+    fn scroll(&mut self, screen: &Screen, lines: isize) {
+        let max_offset = screen.max_offset();
+        let offset = (self.scroll_offset.min(max_offset) as isize + lines).max(0) as usize;
+        self.scroll_offset = offset.min(max_offset);
+        // the selection is of rows on the screen, not of the text
+        self.selection = None;
+        self.screen_dirty = true;
+    }
+
+    /// the selected output from its first position up to, but not including, the end
+    fn selected_range(&self) -> Option<((usize, usize), (usize, usize))> {
+        let (anchor, end) = self.selection?;
+        if anchor == end {
+            return None;
+        }
+        let (first, last) = if anchor < end {
+            (anchor, end)
+        } else {
+            (end, anchor)
+        };
+        return Some((first, (last.0, last.1 + 1)));
+    }
+
+    /// copy the selected output to the clipboard
+    fn copy(&mut self, screen: &Screen, styled: bool) {
+        let (start, end) = match self.selected_range() {
+            Some(range) => range,
+            None => return,
+        };
+        let offset = self.scroll_offset.min(screen.max_offset());
+        let text = screen.view_text(offset, start, end, styled);
+        if let Some(ref mut clipboard) = self.clipboard {
+            if let Err(e) = clipboard.set_contents(text) {
+                eprintln!("could not copy to the clipboard:\n{}", e);
+            }
+        }
+    }
+
+    /// send text to the program if it has the terminal, or add it to the input editor
+    fn paste(
+        &mut self,
+        sessions: &mut SessionManager,
+        active: SessionId,
+        text: String,
+    ) -> Result<(), Error> {
+        if !self.passthrough {
+            self.input_view.insert(&text);
+            return Ok(());
+        }
+        sessions
+            .get_mut(active)
+            .ok_or(session::Error::NotFound(active))?
+            .paste(&text)
+            .map_err(session::Error::from)?;
+        return Ok(());
+    }
+
+    /// replace the input with an older command if `back`, or a newer one
+    fn walk_history(&mut self, history: &mut History, back: bool) {
+        let command = if back {
+            history.previous(self.input_view.get_text())
+        } else {
+            history.next()
+        };
+        if let Some(command) = command {
+            self.input_view.set_text(command);
+        }
+    }
+
+    /// update the history search and show its match in the input
+    fn search_history(&mut self, history: &History, key: ui::SearchKey, cwd: Option<&Path>) {
+        let text = ui::search_history(
+            &mut self.search,
+            history,
+            key,
+            self.input_view.get_text(),
+            cwd,
+        );
+        if let Some(text) = text {
+            self.input_view.set_text(&text);
+        }
+    }
+
+    /// complete the word at the end of the input, the text edit doesn't share its cursor
+    fn complete(&mut self, history: &History, cwd: Option<&Path>, env: &Environment) {
+        let edit = {
+            let text = self.input_view.get_text();
+            completion::tab(
+                &mut self.completions,
+                &self.completer,
+                text,
+                text.len(),
+                cwd,
+                env,
+                history,
+            )
+        };
+        if let Some(edit) = edit {
+            self.input_view.set_text(&edit.text);
+        }
+    }
+
+    /// the row and link under the pointer in the output view
+    fn link_under_pointer(&self, screen: &Screen) -> Option<(usize, Link)> {
+        let xy = self.ui.global_input().current.mouse.xy;
+        let (row, column) = self.output_view.hit_test(&self.ui, xy)?;
+        let offset = self.scroll_offset.min(screen.max_offset());
+        return screen
+            .view_links(offset, row)
+            .into_iter()
+            .find(|link| link.contains(column))
+            .map(|link| (row, link));
+    }
+
+    /// rebuild the output view from the visible rows of a screen and its scrollback,
+    /// one line per row
+    fn render_screen(&mut self, screen: &Screen) {
+        self.screen_dirty = false;
+        self.output_view.clear();
+
+        let offset = self.scroll_offset.min(screen.max_offset());
+        let selection = self.selected_range();
+        let hovered = self.hovered.clone();
+
+        for row in 0..screen.rows() {
+            if row > 0 {
+                self.output_view.new_line();
+            }
+            let runs = if self.passthrough && offset == 0 {
+                screen.runs_with_cursor(row)
+            } else {
+                screen.view_runs(offset, row)
+            };
+            let selected = selected_columns(selection, row);
+            let mut column = 0;
+            for run in runs {
+                // selected cells are drawn inverted
+                let pieces = split_columns(&run.text, column, selected);
+                for (piece_column, text, in_selection) in pieces {
+                    let attributes = Attributes {
+                        inverse: run.attributes.inverse != in_selection,
+                        ..run.attributes
+                    };
+                    match hovered {
+                        Some((hovered_row, ref link)) if hovered_row == row => {
+                            self.push_linked(&text, style(&attributes), piece_column, link)
+                        }
+                        _ => self.output_view.push(&text, style(&attributes)),
+                    }
+                }
+                column += text_width(&run.text);
+            }
+        }
+    }
+
+    /// push text that starts at `column`, underlining the part covered by `link`
+    fn push_linked(&mut self, text: &str, style: Style, column: usize, link: &Link) {
+        for (_, piece, in_link) in split_columns(text, column, Some((link.start, link.end))) {
+            let style = Style {
+                underline: style.underline || in_link,
+                ..style
+            };
+            self.output_view.push(&piece, style);
+        }
+    }
+
+    /// show session output, turning lifecycle changes into app events
+    fn handle_session_event(&mut self, event: session::Event, events: &mut Vec<ui::Event>) {
+        match event {
+            session::Event::Output(_) => self.screen_dirty = true,
+            // the status line shows how it went
+            session::Event::CommandFinished(_) => (),
+            session::Event::Error(e) => eprintln!("could not read shell output:\n{}", e),
+            session::Event::Exited(status) => events.push(ui::Event::ShellExited(status)),
+            session::Event::Restarted => self.screen_dirty = true,
+            session::Event::Created | session::Event::Closed => (),
+        }
+    }
+}
+
+/// what a key press bound to `action` does while searching the history,
+/// keys that type characters are left to the character events
+fn search_key(event: &Event, action: Option<Action>) -> Option<ui::SearchKey> {
+    if action == Some(Action::SearchHistory) {
+        return Some(ui::SearchKey::Next);
+    }
+    match *event {
+        Event::WindowEvent { ref event, .. } => match event {
+            WindowEvent::ReceivedCharacter(c) if !c.is_control() => {
+                return Some(ui::SearchKey::Char(*c))
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        modifiers,
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => match *keycode {
+                VirtualKeyCode::T if modifiers.ctrl => return Some(ui::SearchKey::ToggleDirectory),
+                VirtualKeyCode::G if modifiers.ctrl => return Some(ui::SearchKey::Cancel),
+                VirtualKeyCode::Escape => return Some(ui::SearchKey::Cancel),
+                VirtualKeyCode::Back => return Some(ui::SearchKey::Backspace),
+                keycode if is_modifier(keycode) => return None,
+                _ if modifiers.ctrl => return Some(ui::SearchKey::Accept),
+                VirtualKeyCode::Return
+                | VirtualKeyCode::Tab
+                | VirtualKeyCode::Up
+                | VirtualKeyCode::Down
+                | VirtualKeyCode::Left
+                | VirtualKeyCode::Right
+                | VirtualKeyCode::Home
+                | VirtualKeyCode::End
+                | VirtualKeyCode::PageUp
+                | VirtualKeyCode::PageDown
+                | VirtualKeyCode::Delete => return Some(ui::SearchKey::Accept),
+                _ => return None,
+            },
+            _ => return None,
+        },
+        _ => return None,
+    }
+}
+
+/// true for keys that only modify other keys
+fn is_modifier(keycode: VirtualKeyCode) -> bool {
+    match keycode {
+        VirtualKeyCode::LShift
+        | VirtualKeyCode::RShift
+        | VirtualKeyCode::LControl
+        | VirtualKeyCode::RControl
+        | VirtualKeyCode::LAlt
+        | VirtualKeyCode::RAlt
+        | VirtualKeyCode::LWin
+        | VirtualKeyCode::RWin
+        | VirtualKeyCode::Capital => return true,
+        _ => return false,
+    }
+}
+
+/// the key and modifiers of a key press event, for finding what it's bound to
+fn key_chord(event: &Event) -> Option<(Key, Modifiers)> {
+    let (keycode, state) = match *event {
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(keycode),
+                            modifiers,
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } => (keycode, modifiers),
+        _ => return None,
+    };
+    let modifiers = Modifiers {
+        shift: state.shift,
+        ctrl: state.ctrl,
+        alt: state.alt,
+    };
+
+    if let Some(key) = terminal_key(keycode, Modifiers::default()) {
+        return Some((key, modifiers));
+    }
+    let index = keycode as u32;
+    let c = match keycode {
+        // letters and digits are numbered in order
+        _ if index >= VirtualKeyCode::A as u32 && index <= VirtualKeyCode::Z as u32 => {
+            (b'a' + (index - VirtualKeyCode::A as u32) as u8) as char
+        }
+        _ if index >= VirtualKeyCode::Key1 as u32 && index <= VirtualKeyCode::Key9 as u32 => {
+            (b'1' + (index - VirtualKeyCode::Key1 as u32) as u8) as char
+        }
+        VirtualKeyCode::Key0 => '0',
+        VirtualKeyCode::Space => ' ',
+        VirtualKeyCode::Minus | VirtualKeyCode::Subtract => '-',
+        VirtualKeyCode::Equals => '=',
+        VirtualKeyCode::Add => '+',
+        VirtualKeyCode::Backslash => '\\',
+        VirtualKeyCode::Slash => '/',
+        VirtualKeyCode::Comma => ',',
+        VirtualKeyCode::Period => '.',
+        VirtualKeyCode::Semicolon => ';',
+        VirtualKeyCode::Apostrophe => '\'',
+        VirtualKeyCode::LBracket => '[',
+        VirtualKeyCode::RBracket => ']',
+        VirtualKeyCode::Grave => '`',
+        _ => return None,
+    };
+    return Some((Key::Char(c), modifiers));
+}
+
+/// the key of a key press event
+fn pressed_key(event: &Event) -> Option<VirtualKeyCode> {
+    match *event {
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode,
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } => return virtual_keycode,
+        _ => return None,
+    }
+}
+
+/// the columns of `row` inside a selection from `start` up to `end`
+fn selected_columns(
+    selection: Option<((usize, usize), (usize, usize))>,
+    row: usize,
+) -> Option<(usize, usize)> {
+    let (start, end) = selection?;
+    if row < start.0 || row > end.0 {
+        return None;
+    }
+    let from = if row == start.0 { start.1 } else { 0 };
+    let to = if row == end.0 { end.1 } else { usize::max_value() };
+    return Some((from, to));
+}
+
+/// split text that starts at `column` into the pieces before, inside and after
+/// the columns `range`, with the column each starts at and whether it's inside
+fn split_columns(
+    text: &str,
+    column: usize,
+    range: Option<(usize, usize)>,
+) -> Vec<(usize, String, bool)> {
+    let (start, end) = range.unwrap_or((usize::max_value(), usize::max_value()));
+    let mut pieces: Vec<(usize, String, bool)> = Vec::new();
+    let mut at = column;
+    for c in text.chars() {
+        let inside = start <= at && at < end;
+        let extends = match pieces.last() {
+            Some(&(_, _, was_inside)) => was_inside == inside,
+            None => false,
+        };
+        if extends {
+            pieces.last_mut().expect("no piece to extend").1.push(c);
+        } else {
+            pieces.push((at, c.to_string(), inside));
+        }
+        // a wide character takes two columns
+        at += char_width(c);
+    }
+    return pieces;
+}
+
+/// the key a program reading the terminal should get for a key press,
+/// `None` for keys that arrive as typed characters
+fn terminal_key(keycode: VirtualKeyCode, modifiers: Modifiers) -> Option<Key> {
+    let key = match keycode {
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Up => Key::Up,
+        VirtualKeyCode::Down => Key::Down,
+        VirtualKeyCode::Right => Key::Right,
+        VirtualKeyCode::Left => Key::Left,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::F1 => Key::Function(1),
+        VirtualKeyCode::F2 => Key::Function(2),
+        VirtualKeyCode::F3 => Key::Function(3),
+        VirtualKeyCode::F4 => Key::Function(4),
+        VirtualKeyCode::F5 => Key::Function(5),
+        VirtualKeyCode::F6 => Key::Function(6),
+        VirtualKeyCode::F7 => Key::Function(7),
+        VirtualKeyCode::F8 => Key::Function(8),
+        VirtualKeyCode::F9 => Key::Function(9),
+        VirtualKeyCode::F10 => Key::Function(10),
+        VirtualKeyCode::F11 => Key::Function(11),
+        VirtualKeyCode::F12 => Key::Function(12),
+        // letters are numbered in order
+        letter if modifiers.ctrl
+            && letter as u32 >= VirtualKeyCode::A as u32
+            && letter as u32 <= VirtualKeyCode::Z as u32 =>
+        {
+            Key::Char((b'a' + (letter as u32 - VirtualKeyCode::A as u32) as u8) as char)
+        }
+        _ => return None,
+    };
+    return Some(key);
+}
+
+/// how to draw text with the given cell attributes
+fn style(attributes: &Attributes) -> Style {
+    let rgb = |(r, g, b): (u8, u8, u8)| color::rgb_bytes(r, g, b);
+    let foreground = attributes.foreground.rgb().map(&rgb).unwrap_or(STDOUT_COLOR);
+    let background = attributes.background.rgb().map(&rgb);
+
+    let (foreground, background) = if attributes.inverse {
+        (background.unwrap_or(BACKGROUND_COLOR), Some(foreground))
+    } else {
+        (foreground, background)
+    };
+
+    let foreground = if attributes.hidden {
+        background.unwrap_or(BACKGROUND_COLOR)
+    } else if attributes.dim {
+        foreground.with_alpha(0.5)
+    } else {
+        foreground
+    };
+
+    return Style {
+        foreground: foreground,
+        background: background,
+        bold: attributes.bold,
+        italic: attributes.italic,
+        underline: attributes.underline,
+    };
 }
 
 impl Ui for Conrod {
-    type Error = String;
+    fn show(
+        &mut self,
+        mut sessions: SessionManager,
+        mut history: History,
+        bindings: Bindings,
+        mut active: SessionId,
+    ) -> Result<(), Error> {
+        if sessions.get(active).is_none() {
+            return Err(session::Error::NotFound(active).into());
+        }
+
+        let proxy = Mutex::new(self.events_loop.create_proxy());
+        sessions.set_waker(Arc::new(move || {
+            if let Ok(proxy) = proxy.lock() {
+                // fails only if the events loop is gone
+                let _ = proxy.wakeup();
+            }
+        }));
 
-    fn show(&mut self, mut shell: Shell) -> Result<(), Self::Error> {
-        let mut buffer = String::new();
+        let mut idle_frames = 0;
+        // size the sessions to the window once it's laid out
+        self.resized = Some(Instant::now());
 
         'main: loop {
-            for event in self.events() {
+            // keep polling while a resize is pending
+            let wait = idle_frames >= IDLE_FRAMES && self.resized.is_none();
+            let mut events = self.events(wait, &bindings);
+            let mut active_frame = !events.is_empty();
+
+            for _ in 0..MAX_EVENTS_PER_FRAME {
+                match sessions.poll() {
+                    Some((id, event)) => {
+                        if let session::Event::CommandFinished(ref completion) = event {
+                            ui::record_completion(&mut history, completion);
+                        }
+                        // only the active session is shown
+                        if id != active {
+                            continue;
+                        }
+                        self.handle_session_event(event, &mut events);
+                        active_frame = true;
+                    }
+                    None => break,
+                }
+            }
+            // output closes just before the shell can be reaped
+            active_frame |= sessions.awaiting_exit();
+
+            let shown = active;
+            for event in events {
+                if let Some(control) = event.control() {
+                    sessions
+                        .shell_mut(active)?
+                        .send_control(control)
+                        .map_err(session::Error::from)?;
+                    continue;
+                }
+
                 match event {
-                    ui::Event::Submit(command) => {
+                    // enter in an unfinished command starts a new line
+                    ui::Event::Submit(ref command)
+                        if sessions
+                            .get(active)
+                            .map_or(false, |session| ui::continues_input(session, command)) =>
+                    {
+                        self.input_view.set_text(command);
+                        self.input_view.insert("\n");
+                    }
+                    ui::Event::Submit(command) | ui::Event::ForceSubmit(command) => {
                         eprintln!("submitted: {:?}", command);
 
                         // TODO sanitize commands
-                        let mut command = String::from(command);
-                        if !command.ends_with('\n') {
-                            command.push('\n');
+                        let started = sessions
+                            .shell_mut(active)?
+                            .submit(&command)
+                            .map_err(session::Error::from)?;
+                        // input read by a running command, like a password, isn't saved
+                        if let (Some(_), Some(session)) = (started, sessions.get(active)) {
+                            ui::record_command(&mut history, session, &command);
                         }
 
-                        shell.execute(&command).expect("could not execute command");
-
-                        buffer.clear();
+                        // follow the output of the new command
+                        self.scroll_offset = 0;
+                        self.screen_dirty = true;
+                    }
+                    ui::Event::ShellExited(status) => {
+                        self.exit_status = Some(status);
+                    }
+                    ui::Event::Restart => {
+                        sessions.restart(active)?;
+                        self.exit_status = None;
+                        self.screen_dirty = true;
+                    }
+                    ui::Event::Key(key, modifiers) => {
+                        sessions
+                            .get_mut(active)
+                            .ok_or(session::Error::NotFound(active))?
+                            .send_key(key, modifiers)
+                            .map_err(session::Error::from)?;
+                    }
+                    ui::Event::OpenLink(url) => ui::open_link(&url),
+                    ui::Event::Newline => self.input_view.insert("\n"),
+                    ui::Event::NewTab => match sessions.duplicate(active) {
+                        Ok(id) => active = id,
+                        Err(e) => eprintln!("could not open a new tab:\n{}", e),
+                    },
+                    ui::Event::CloseTab => {
+                        let next = sessions.adjacent(active, true).filter(|&id| id != active);
+                        if let Err(e) = sessions.close(active) {
+                            eprintln!("could not shut down session {}:\n{}", active, e);
+                        }
+                        match next {
+                            Some(id) => active = id,
+                            // the last session was closed
+                            None => return Ok(()),
+                        }
+                    }
+                    ui::Event::NextTab => {
+                        active = sessions.adjacent(active, true).unwrap_or(active);
+                    }
+                    ui::Event::PreviousTab => {
+                        active = sessions.adjacent(active, false).unwrap_or(active);
+                    }
+                    ui::Event::ZoomIn => {
+                        let font_size = self.output_view.font_size() + 1;
+                        self.set_font_size(font_size);
+                    }
+                    ui::Event::ZoomOut => {
+                        let font_size = self.output_view.font_size().saturating_sub(1);
+                        self.set_font_size(font_size);
+                    }
+                    ui::Event::ZoomReset => self.set_font_size(FONT_SIZE),
+                    ui::Event::HistoryPrevious => self.walk_history(&mut history, true),
+                    ui::Event::HistoryNext => self.walk_history(&mut history, false),
+                    ui::Event::SearchHistory(key) => {
+                        let cwd = sessions.get(active).and_then(Session::cwd);
+                        self.search_history(&history, key, cwd);
                     }
+                    ui::Event::Complete => {
+                        if let Some(session) = sessions.get(active) {
+                            let env = session.shell().environment();
+                            self.complete(&history, session.cwd(), env);
+                        }
+                    }
+                    ui::Event::Copy => {
+                        if let Some(session) = sessions.get(active) {
+                            self.copy(session.screen(), false);
+                        }
+                    }
+                    ui::Event::CopyStyled => {
+                        if let Some(session) = sessions.get(active) {
+                            self.copy(session.screen(), true);
+                        }
+                    }
+                    ui::Event::Paste(text) => {
+                        if input::is_safe_paste(&text) {
+                            self.paste(&mut sessions, active, text)?;
+                        } else {
+                            self.pending_paste = Some(text);
+                        }
+                    }
+                    ui::Event::PasteConfirmed(text) => self.paste(&mut sessions, active, text)?,
+                    ui::Event::ScrollLines(lines) => {
+                        if let Some(session) = sessions.get(active) {
+                            self.scroll(session.screen(), lines);
+                        }
+                    }
+                    ui::Event::ScrollPages(pages) => {
+                        if let Some(session) = sessions.get(active) {
+                            let lines = pages * session.screen().rows() as isize;
+                            self.scroll(session.screen(), lines);
+                        }
+                    }
+                    ui::Event::Interrupt
+                    | ui::Event::Suspend
+                    | ui::Event::Quit
+                    | ui::Event::Eof => {}
                     // break loop
-                    ui::Event::Exit => return Ok(()),
+                    ui::Event::Exit => {
+                        for (id, result) in sessions.close_all() {
+                            match result {
+                                Ok(status) => eprintln!("session {} exited with {}", id, status),
+                                Err(e) => eprintln!("could not shut down session {}:\n{}", id, e),
+                            }
+                        }
+                        return Ok(());
+                    }
                 }
             }
 
-            match shell.poll_stdout() {
-                Ok(Some(vec)) => {
-                    buffer.push_str(
-                        ::std::str::from_utf8(&vec).expect("could not push string to buffer"),
-                    );
+            if active != shown {
+                if let Some(session) = sessions.get(active) {
+                    self.switch_session(session);
                 }
-                Ok(None) => {}
-                Err(e) => {
-                    return Err(format!("could not read output:\n{}", e));
+            }
+
+            let passthrough = sessions
+                .get(active)
+                .map(Session::passthrough)
+                .unwrap_or(false);
+            if passthrough != self.passthrough {
+                self.passthrough = passthrough;
+                self.scroll_offset = 0;
+                self.screen_dirty = true;
+                // the output canvas changes size with the layout
+                self.resized = Some(Instant::now());
+            }
+
+            let moved = self.pointer_moved;
+            if moved {
+                self.pointer_moved = false;
+                if self.selecting {
+                    let xy = self.ui.global_input().current.mouse.xy;
+                    let position = self.output_view.hit_test(&self.ui, xy);
+                    if let (Some((anchor, _)), Some(position)) = (self.selection, position) {
+                        self.selection = Some((anchor, position));
+                        self.screen_dirty = true;
+                    }
+                }
+            }
+
+            // output or scrolling may have replaced the link under the pointer,
+            // only its row is looked at
+            if moved || self.screen_dirty {
+                let hovered = sessions
+                    .get(active)
+                    .and_then(|session| self.link_under_pointer(session.screen()));
+                if hovered != self.hovered {
+                    self.hovered = hovered;
+                    self.screen_dirty = true;
                 }
             }
 
-            self.set_output(&buffer);
+            self.apply_resize(&mut sessions);
+            if self.screen_dirty {
+                if let Some(session) = sessions.get(active) {
+                    self.set_title(ui::window_title(session));
+                    self.render_screen(session.screen());
+                }
+            }
+
+            self.status = match (&self.pending_paste, &self.search, self.exit_status) {
+                (&Some(ref text), _, _) => format!(
+                    "{} press enter to paste, escape to cancel",
+                    ui::paste_prompt(text)
+                ),
+                (&None, &Some(ref search), _) => ui::search_prompt(search, &history),
+                (&None, &None, Some(status)) => {
+                    format!("shell exited with {}, press enter to restart", status)
+                }
+                (&None, &None, None) => sessions
+                    .get(active)
+                    .and_then(|session| session.shell().status())
+                    .map(|status| status.to_string())
+                    .unwrap_or_default(),
+            };
+
+            if active_frame {
+                idle_frames = 0;
+            } else {
+                idle_frames += 1;
+            }
 
             self.draw()?;
         } // end main loop
@@ -1,15 +1,25 @@
 use conrod::color;
 use conrod::color::Colorable;
+use conrod::text::font;
 use conrod::widget;
 use conrod::widget::TextEdit;
 use conrod::UiCell;
 use conrod::Widget;
+use conrod::{Point, Ui};
 use conrod::{Positionable, Sizeable};
 
+use screen::text_width;
 use ui::TextView;
 
 use super::Update;
 
+pub const FONT_SIZE: u32 = 14;
+pub const LINE_HEIGHT: f64 = 18.0;
+/// the output font can't be zoomed out smaller than this
+pub const MIN_FONT_SIZE: u32 = 4;
+/// space between the output text and the edges of its canvas
+pub const PADDING: f64 = 8.0;
+
 pub struct Text {
     editable: bool,
     id: widget::Id,
@@ -27,6 +37,11 @@ impl Text {
         };
     }
 
+    /// add text at the end, the text edit widget doesn't share its cursor
+    pub fn insert(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
     pub fn submit(&mut self) -> String {
         let ret = self.get_text().clone();
         self.set_text("");
@@ -71,3 +86,191 @@ impl TextView for Text {
         self.text.push_str(text);
     }
 }
+
+/// the faces of the output font,
+/// styles that couldn't be loaded use the regular face
+#[derive(Clone, Copy)]
+pub struct Fonts {
+    pub regular: font::Id,
+    pub bold: font::Id,
+    pub italic: font::Id,
+    pub bold_italic: font::Id,
+}
+
+impl Fonts {
+    fn get(&self, bold: bool, italic: bool) -> font::Id {
+        match (bold, italic) {
+            (false, false) => return self.regular,
+            (true, false) => return self.bold,
+            (false, true) => return self.italic,
+            (true, true) => return self.bold_italic,
+        }
+    }
+}
+
+/// how a span of text is drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Style {
+    pub foreground: color::Color,
+    pub background: Option<color::Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+/// a run of text drawn in a single style
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// read only lines of text made of differently styled spans,
+/// each span is drawn with one text widget
+pub struct StyledText {
+    text_ids: widget::id::List,
+    background_ids: widget::id::List,
+    underline_ids: widget::id::List,
+    parent: widget::Id,
+    fonts: Fonts,
+    font_size: u32,
+    lines: Vec<Vec<Span>>,
+}
+
+impl StyledText {
+    pub fn new(parent_id: widget::Id, fonts: Fonts) -> StyledText {
+        return StyledText {
+            text_ids: widget::id::List::new(),
+            background_ids: widget::id::List::new(),
+            underline_ids: widget::id::List::new(),
+            parent: parent_id,
+            fonts: fonts,
+            font_size: FONT_SIZE,
+            lines: vec![Vec::new()],
+        };
+    }
+
+    pub fn font_size(&self) -> u32 {
+        return self.font_size;
+    }
+
+    pub fn set_font_size(&mut self, font_size: u32) {
+        self.font_size = font_size.max(MIN_FONT_SIZE);
+    }
+
+    /// the distance between lines, growing with the font size
+    pub fn line_height(&self) -> f64 {
+        return (LINE_HEIGHT * self.font_size as f64 / FONT_SIZE as f64).round();
+    }
+
+    /// append text to the last line, extending the last span if the style matches
+    pub fn push(&mut self, text: &str, style: Style) {
+        let line = self.lines.last_mut().expect("styled text has no lines");
+        if let Some(span) = line.last_mut() {
+            if span.style == style {
+                span.text.push_str(text);
+                return;
+            }
+        }
+
+        line.push(Span {
+            text: text.to_string(),
+            style: style,
+        });
+    }
+
+    pub fn new_line(&mut self) {
+        self.lines.push(Vec::new());
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.lines.push(Vec::new());
+    }
+
+    /// the line and column of the character at `xy`,
+    /// found from where the spans were last drawn
+    pub fn hit_test(&self, ui: &Ui, xy: Point) -> Option<(usize, usize)> {
+        let mut index = 0;
+        for (row, line) in self.lines.iter().enumerate() {
+            let mut column = 0;
+            for span in line {
+                let len = text_width(&span.text);
+                let rect = if index < self.text_ids.len() {
+                    ui.rect_of(self.text_ids[index])
+                } else {
+                    None
+                };
+                if let Some(rect) = rect {
+                    if len > 0 && rect.is_over(xy) {
+                        // every column is as wide as the others
+                        let offset = ((xy[0] - rect.left()) / rect.w() * len as f64) as usize;
+                        return Some((row, column + offset.min(len - 1)));
+                    }
+                }
+                column += len;
+                index += 1;
+            }
+        }
+        return None;
+    }
+}
+
+impl Update for StyledText {
+    fn update(&mut self, ui_cell: &mut UiCell) {
+        let spans = self.lines.iter().map(Vec::len).sum();
+        if self.text_ids.len() < spans {
+            let generator = &mut ui_cell.widget_id_generator();
+            self.text_ids.resize(spans, generator);
+            self.background_ids.resize(spans, generator);
+            self.underline_ids.resize(spans, generator);
+        }
+
+        let line_height = self.line_height();
+        let mut index = 0;
+        for (row, line) in self.lines.iter().enumerate() {
+            let mut previous: Option<widget::Id> = None;
+
+            for span in line {
+                let id = self.text_ids[index];
+                let text = widget::Text::new(span.text.as_str())
+                    .color(span.style.foreground)
+                    .font_id(self.fonts.get(span.style.bold, span.style.italic))
+                    .font_size(self.font_size)
+                    .no_line_wrap()
+                    .parent(self.parent);
+
+                // spans follow each other on a line, lines are a fixed height apart
+                match previous {
+                    Some(previous_id) => text.right_from(previous_id, 0.0).set(id, ui_cell),
+                    None => text
+                        .top_left_with_margins_on(
+                            self.parent,
+                            PADDING + row as f64 * line_height,
+                            PADDING,
+                        )
+                        .set(id, ui_cell),
+                }
+
+                if let Some(background) = span.style.background {
+                    widget::Rectangle::fill_with([0.0, line_height], background)
+                        .w_of(id)
+                        .middle_of(id)
+                        .depth(1.0)
+                        .parent(self.parent)
+                        .set(self.background_ids[index], ui_cell);
+                }
+
+                if span.style.underline {
+                    widget::Rectangle::fill_with([0.0, 1.0], span.style.foreground)
+                        .w_of(id)
+                        .mid_bottom_of(id)
+                        .parent(self.parent)
+                        .set(self.underline_ids[index], ui_cell);
+                }
+
+                previous = Some(id);
+                index += 1;
+            }
+        }
+    }
+}
@@ -1,8 +1,13 @@
-use std::cell::RefCell;
+mod tags;
+
+use std::cell::{Cell, RefCell};
 use std::io;
 use std::io::Write;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use futures;
 
@@ -10,15 +15,30 @@ use gio::prelude::*;
 use gtk::prelude::*;
 
 use gdk;
+use gdk::enums::key;
 use gio;
 use glib;
 use gtk;
 use gtk::TextBuffer;
+use pango;
 
+use bindings::{Action, Bindings};
+use constants::RESIZE_DEBOUNCE_MS;
+use completion;
+use completion::{Completer, Completions, Edit};
+use history::{History, Search};
+use input;
+use input::{Key, Modifiers};
+use screen;
+use screen::{Link, Screen};
 use ui;
 
+use session;
+use session::{SessionId, SessionManager};
 use shell::Shell;
-use ui::Ui;
+use ui::{Error, Ui};
+
+use self::tags::TagCache;
 
 macro_rules! clone {
     (@param _) => ( _ );
@@ -51,36 +71,861 @@ macro_rules! upgrade_weak {
 }
 
 thread_local! (
-    static GLOBAL_CONTEXT: RefCell<Option<Context>> = RefCell::new(None)
+    static GLOBAL_CONTEXT: RefCell<Option<Context>> = RefCell::new(None);
+    /// the latest terminal size from the window and when it was seen,
+    /// kept apart from the context since allocation can happen while it's borrowed
+    static PENDING_RESIZE: Cell<Option<(usize, usize, Instant)>> = Cell::new(None)
 );
 
-type Key = u32;
+type KeyVal = u32;
 
 const APP_ID: &str = "biz.covercash.germ";
-const KEY_ENTER: Key = 65293;
+
+const LINK_TAG: &str = "link";
+const LINK_HOVER_TAG: &str = "link-hover";
+
+/// limit the events handled per main loop iteration so input stays responsive
+const MAX_EVENTS_PER_IDLE: usize = 64;
+const EXIT_CHECK_INTERVAL_MS: u32 = 50;
+/// lines scrolled by a step of the mouse wheel
+const SCROLL_LINES: isize = 3;
+/// lines of a paste shown when asking whether to paste it
+const PASTE_PREVIEW_LINES: usize = 10;
+/// the output's font can't be zoomed out smaller than this, in points
+const MIN_FONT_SIZE: i32 = 4;
+
+/// set while a call to `receive_output` is queued on the main loop
+static OUTPUT_SCHEDULED: AtomicBool = AtomicBool::new(false);
 
 struct Context {
+    window: gtk::ApplicationWindow,
+    input_view: gtk::TextView,
+    output_view: gtk::TextView,
+    separator: gtk::Separator,
     stdin_buffer: TextBuffer,
-    stdout_buffer: TextBuffer,
-    shell: Shell,
+    output_buffer: TextBuffer,
+    tags: TagCache,
+    /// marks every link, its events open them
+    link_tag: gtk::TextTag,
+    /// underlines the link under the pointer
+    hover_tag: gtk::TextTag,
+    /// the links on each line of the output buffer, from and to buffer offsets
+    links: Vec<Vec<Link>>,
+    /// the line and link the pointer is over
+    hovered: Option<(usize, Link)>,
+    status_label: gtk::Label,
+    exit_box: gtk::Box,
+    exit_label: gtk::Label,
+    sessions: SessionManager,
+    /// the session shown in the window
+    active: SessionId,
+    /// set while `wait_for_exit` is running on a timer
+    exit_check_scheduled: bool,
+    /// set when the active session's screen changed since it was last drawn
+    screen_dirty: bool,
+    /// how many lines the view is scrolled back into the scrollback
+    scroll_offset: usize,
+    /// set while a full-screen program has the whole window and every key press
+    passthrough: bool,
+    /// commands submitted in every session
+    history: History,
+    /// set while searching the history with ctrl+r
+    search: Option<Search>,
+    /// fills in the word before the cursor on tab
+    completer: Completer,
+    /// set while there's a choice of candidates to tab through
+    completions: Option<Completions>,
+    /// lists the candidates above the cursor
+    completion_popover: gtk::Popover,
+    completion_list: gtk::ListBox,
+    /// points added to the output's font size
+    zoom: i32,
+    /// the output's font before it was zoomed
+    base_font: Option<pango::FontDescription>,
 }
 
 impl Context {
-    fn create_global_context(stdin_buffer: TextBuffer, stdout_buffer: TextBuffer, shell: Shell) {
+    fn create_global_context(
+        builder: &gtk::Builder,
+        sessions: SessionManager,
+        history: History,
+        active: SessionId,
+    ) {
+        let input_view: gtk::TextView = builder
+            .get_object("input_view")
+            .expect("could not get input view from builder");
+
+        let output_view: gtk::TextView = builder
+            .get_object("output_view")
+            .expect("could not get output view from builder");
+
+        let stdin_buffer = input_view
+            .get_buffer()
+            .expect("couldn't get input text buffer");
+
+        let output_buffer: gtk::TextBuffer = output_view
+            .get_buffer()
+            .expect("could not get buffer from output view");
+
+        let tag_table = output_buffer
+            .get_tag_table()
+            .expect("could not get output tag table");
+        let link_tag = gtk::TextTag::new(LINK_TAG);
+        link_tag.connect_event(|_, _, event, iter| link_event(event, iter));
+        tag_table.add(&link_tag);
+        let hover_tag = gtk::TextTag::new(LINK_HOVER_TAG);
+        hover_tag.set_property_underline(pango::Underline::Single);
+        tag_table.add(&hover_tag);
+
+        let completion_list = gtk::ListBox::new();
+        completion_list.connect_row_activated(|_, row| {
+            let row = row.get_index() as usize;
+            with_context(|context| context.choose_completion(row));
+        });
+        // typing goes on in the input while the candidates are shown
+        let completion_popover = gtk::Popover::new(Some(&input_view));
+        completion_popover.set_modal(false);
+        completion_popover.set_position(gtk::PositionType::Top);
+        completion_popover.add(&completion_list);
+
         GLOBAL_CONTEXT.with(|global_ref| {
             *global_ref.borrow_mut() = Some(Context {
+                window: builder
+                    .get_object("main_window")
+                    .expect("could not get main window from builder"),
+                input_view: input_view,
+                output_view: output_view,
+                separator: builder
+                    .get_object("separator")
+                    .expect("could not get separator from builder"),
                 stdin_buffer: stdin_buffer,
-                stdout_buffer: stdout_buffer,
-                shell: shell,
+                output_buffer: output_buffer,
+                tags: TagCache::new(tag_table),
+                link_tag: link_tag,
+                hover_tag: hover_tag,
+                links: Vec::new(),
+                hovered: None,
+                status_label: builder
+                    .get_object("status_label")
+                    .expect("could not get status label from builder"),
+                exit_box: builder
+                    .get_object("exit_box")
+                    .expect("could not get exit box from builder"),
+                exit_label: builder
+                    .get_object("exit_label")
+                    .expect("could not get exit label from builder"),
+                sessions: sessions,
+                active: active,
+                exit_check_scheduled: false,
+                screen_dirty: true,
+                scroll_offset: 0,
+                passthrough: false,
+                history: history,
+                search: None,
+                completer: Completer::new(),
+                completions: None,
+                completion_popover: completion_popover,
+                completion_list: completion_list,
+                zoom: 0,
+                base_font: None,
             });
         });
     }
+
+    fn shell(&mut self) -> Result<&mut Shell, session::Error> {
+        return self.sessions.shell_mut(self.active);
+    }
+
+    /// replace the output buffer with the visible part of the active session's output,
+    /// only the rows on screen are ever in the buffer
+    fn render_screen(&mut self) {
+        self.screen_dirty = false;
+        let (passthrough, title) = match self.sessions.get(self.active) {
+            Some(session) => (session.passthrough(), ui::window_title(session)),
+            None => return,
+        };
+        self.set_passthrough(passthrough);
+        if self.window.get_title().as_ref() != Some(&title) {
+            self.window.set_title(&title);
+        }
+
+        let screen = match self.sessions.get(self.active) {
+            Some(session) => session.screen(),
+            None => return,
+        };
+
+        let offset = self.scroll_offset.min(screen.max_offset());
+        let rows: Vec<_> = (0..screen.rows())
+            .map(|row| screen.view_runs(offset, row))
+            .collect();
+        // the buffer counts characters, which can be wider than a column
+        let links: Vec<Vec<Link>> = rows
+            .iter()
+            .enumerate()
+            .map(|(row, runs)| {
+                screen
+                    .view_links(offset, row)
+                    .into_iter()
+                    .map(|link| Link {
+                        start: screen::offset_at(runs, link.start),
+                        end: screen::offset_at(runs, link.end),
+                        ..link
+                    })
+                    .collect()
+            })
+            .collect();
+        let (cursor_row, cursor_column) = screen.cursor();
+        let cursor_offset = screen::offset_at(&rows[cursor_row], cursor_column);
+        let text = rows
+            .iter()
+            .enumerate()
+            .map(|(row, runs)| {
+                let mut line: String = runs.iter().map(|run| run.text.as_str()).collect();
+                // the cursor may be past the end of its line's text
+                if offset == 0 && row == cursor_row {
+                    let width = screen::text_width(&line);
+                    line.extend((width..cursor_column).map(|_| ' '));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        // replacing the text clears the selection, put it back where it was
+        let selection = self
+            .output_buffer
+            .get_selection_bounds()
+            .map(|(start, end)| (position(&start), position(&end)));
+        self.output_buffer.set_text(&text);
+
+        for (row, runs) in rows.into_iter().enumerate() {
+            let mut column = 0;
+            for run in runs {
+                let len = run.text.chars().count();
+                let start = self
+                    .output_buffer
+                    .get_iter_at_line_offset(row as i32, column as i32);
+                let end = self
+                    .output_buffer
+                    .get_iter_at_line_offset(row as i32, (column + len) as i32);
+                column += len;
+
+                if let Some(tag) = self.tags.get(&run.attributes) {
+                    self.output_buffer.apply_tag(&tag, &start, &end);
+                }
+            }
+        }
+
+        for (row, row_links) in links.iter().enumerate() {
+            for link in row_links {
+                self.tag_link(&self.link_tag, row, link);
+            }
+        }
+        self.links = links;
+        // setting the text removed the underline, keep it if the link is still there
+        if let Some((row, link)) = self.hovered.take() {
+            if self.links.get(row).map_or(false, |links| links.contains(&link)) {
+                self.tag_link(&self.hover_tag, row, &link);
+                self.hovered = Some((row, link));
+            }
+        }
+
+        match selection {
+            Some((start, end)) => {
+                let start = self.iter_at(start);
+                let end = self.iter_at(end);
+                self.output_buffer.select_range(&start, &end);
+            }
+            None if self.passthrough && offset == 0 => {
+                let cursor = self
+                    .output_buffer
+                    .get_iter_at_line_offset(cursor_row as i32, cursor_offset as i32);
+                self.output_buffer.place_cursor(&cursor);
+            }
+            None => (),
+        }
+    }
+
+    /// replace the input with an older command if `back`, or a newer one,
+    /// with the cursor at its end
+    fn walk_history(&mut self, back: bool) {
+        let (current, _) = self.input_text();
+        let command = if back {
+            self.history.previous(&current)
+        } else {
+            self.history.next()
+        };
+
+        if let Some(command) = command {
+            self.stdin_buffer.set_text(command);
+            let end = self.stdin_buffer.get_end_iter();
+            self.stdin_buffer.place_cursor(&end);
+        }
+    }
+
+    /// update the history search and show its match in the input
+    fn search_history(&mut self, key: ui::SearchKey) {
+        let (input, _) = self.input_text();
+        let text = {
+            let cwd = self.sessions.get(self.active).and_then(|session| session.cwd());
+            ui::search_history(&mut self.search, &self.history, key, &input, cwd)
+        };
+
+        if let Some(text) = text {
+            self.stdin_buffer.set_text(&text);
+            let end = self.stdin_buffer.get_end_iter();
+            self.stdin_buffer.place_cursor(&end);
+        }
+        self.refresh_status();
+    }
+
+    /// the input's text and the byte offset of the cursor in it
+    fn input_text(&self) -> (String, usize) {
+        let (start, end) = self.stdin_buffer.get_bounds();
+        let text = self
+            .stdin_buffer
+            .get_text(&start, &end, true)
+            .unwrap_or("".into());
+        let offset = self
+            .stdin_buffer
+            .get_insert()
+            .map(|mark| self.stdin_buffer.get_iter_at_mark(&mark).get_offset() as usize)
+            .unwrap_or(0);
+        let cursor = text
+            .char_indices()
+            .nth(offset)
+            .map_or(text.len(), |(index, _)| index);
+        return (text, cursor);
+    }
+
+    /// complete the word before the cursor and list the candidates if there's a choice
+    fn complete(&mut self) {
+        let (text, cursor) = self.input_text();
+        let edit = {
+            let session = match self.sessions.get(self.active) {
+                Some(session) => session,
+                None => return,
+            };
+            completion::tab(
+                &mut self.completions,
+                &self.completer,
+                &text,
+                cursor,
+                session.cwd(),
+                session.shell().environment(),
+                &self.history,
+            )
+        };
+
+        if let Some(edit) = edit {
+            self.apply_edit(&edit);
+        }
+        self.show_completions();
+    }
+
+    /// put the candidate in a row of the list in the input
+    fn choose_completion(&mut self, row: usize) {
+        let edit = match self.completions {
+            Some(ref mut completions) => {
+                let (first, _) = completions.shown();
+                completions.choose(first + row)
+            }
+            None => return,
+        };
+        self.apply_edit(&edit);
+        self.dismiss_completions();
+        self.input_view.grab_focus();
+    }
+
+    fn apply_edit(&self, edit: &Edit) {
+        self.stdin_buffer.set_text(&edit.text);
+        let offset = edit.text[..edit.cursor].chars().count();
+        let cursor = self.stdin_buffer.get_iter_at_offset(offset as i32);
+        self.stdin_buffer.place_cursor(&cursor);
+    }
+
+    fn dismiss_completions(&mut self) {
+        self.completions = None;
+        self.completion_popover.hide();
+    }
+
+    /// list the candidates above the cursor with the one in the input selected
+    fn show_completions(&self) {
+        let completions = match self.completions {
+            Some(ref completions) => completions,
+            None => {
+                self.completion_popover.hide();
+                return;
+            }
+        };
+
+        for child in self.completion_list.get_children() {
+            self.completion_list.remove(&child);
+        }
+        let (first, shown) = completions.shown();
+        for candidate in shown {
+            let label = gtk::Label::new(Some(candidate.label.as_str()));
+            label.set_halign(gtk::Align::Start);
+            self.completion_list.add(&label);
+        }
+        match completions.index() {
+            Some(index) => {
+                let row = self.completion_list.get_row_at_index((index - first) as i32);
+                self.completion_list.select_row(row.as_ref());
+            }
+            None => self.completion_list.unselect_all(),
+        }
+
+        if let Some(mark) = self.stdin_buffer.get_insert() {
+            let cursor = self.stdin_buffer.get_iter_at_mark(&mark);
+            let location = self.input_view.get_iter_location(&cursor);
+            let (x, y) = self.input_view.buffer_to_window_coords(
+                gtk::TextWindowType::Widget,
+                location.x,
+                location.y,
+            );
+            self.completion_popover.set_pointing_to(&gdk::Rectangle {
+                x: x,
+                y: y,
+                width: 1,
+                height: location.height,
+            });
+        }
+        self.completion_popover.show_all();
+    }
+
+    /// the output buffer position at a line and offset, or the end of the line if it's shorter
+    fn iter_at(&self, (row, column): (usize, usize)) -> gtk::TextIter {
+        let mut iter = self.output_buffer.get_iter_at_line(row as i32);
+        let mut line_end = iter.clone();
+        if !line_end.ends_line() {
+            line_end.forward_to_line_end();
+        }
+        if column >= line_end.get_line_offset() as usize {
+            return line_end;
+        }
+        iter.set_line_offset(column as i32);
+        return iter;
+    }
+
+    /// copy the selected output to the clipboard
+    fn copy(&self, styled: bool) {
+        let (start, end) = match self.output_buffer.get_selection_bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let screen = match self.sessions.get(self.active) {
+            Some(session) => session.screen(),
+            None => return,
+        };
+        let offset = self.scroll_offset.min(screen.max_offset());
+        let text = screen.view_text(
+            offset,
+            screen_position(screen, offset, &start),
+            screen_position(screen, offset, &end),
+            styled,
+        );
+        gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).set_text(&text);
+    }
+
+    /// send text to the program if it has the terminal, or put it in the input editor,
+    /// asking first if it isn't `confirmed` and could run commands
+    fn paste(&mut self, text: String, confirmed: bool) {
+        if !confirmed && !input::is_safe_paste(&text) {
+            self.confirm_paste(text);
+            return;
+        }
+
+        if !self.passthrough {
+            self.stdin_buffer.insert_at_cursor(&text);
+            return;
+        }
+        match self.sessions.get_mut(self.active) {
+            Some(session) => {
+                if let Err(e) = session.paste(&text) {
+                    eprintln!("could not paste to shell:\n{}", e);
+                }
+            }
+            None => eprintln!("{}", session::Error::NotFound(self.active)),
+        }
+    }
+
+    /// ask before pasting, the answer comes back as `PasteConfirmed`
+    fn confirm_paste(&self, text: String) {
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            &ui::paste_prompt(&text),
+        );
+        let preview = text
+            .lines()
+            .take(PASTE_PREVIEW_LINES)
+            .collect::<Vec<_>>()
+            .join("\n");
+        dialog.set_property_secondary_text(Some(preview.as_str()));
+
+        // not `run`, which would handle events while the context is borrowed
+        dialog.connect_response(move |dialog, response| {
+            if gtk::ResponseType::from(response) == gtk::ResponseType::Ok {
+                let text = text.clone();
+                with_context(|context| context.handle_event(ui::Event::PasteConfirmed(text)));
+            }
+            dialog.destroy();
+        });
+        dialog.show_all();
+    }
+
+    fn tag_link(&self, tag: &gtk::TextTag, row: usize, link: &Link) {
+        let start = self
+            .output_buffer
+            .get_iter_at_line_offset(row as i32, link.start as i32);
+        let end = self
+            .output_buffer
+            .get_iter_at_line_offset(row as i32, link.end as i32);
+        self.output_buffer.apply_tag(tag, &start, &end);
+    }
+
+    fn link_at(&self, row: usize, column: usize) -> Option<&Link> {
+        return self
+            .links
+            .get(row)
+            .and_then(|links| links.iter().find(|link| link.contains(column)));
+    }
+
+    /// underline the link at a line and offset of the output buffer,
+    /// `None` when the pointer isn't over the text
+    fn hover_link(&mut self, position: Option<(usize, usize)>) {
+        let hovered = position.and_then(|(row, column)| {
+            self.link_at(row, column).cloned().map(|link| (row, link))
+        });
+        if hovered == self.hovered {
+            return;
+        }
+
+        let (start, end) = self.output_buffer.get_bounds();
+        self.output_buffer.remove_tag(&self.hover_tag, &start, &end);
+        if let Some((row, ref link)) = hovered {
+            self.tag_link(&self.hover_tag, row, link);
+        }
+        self.hovered = hovered;
+    }
+
+    /// switch between the line editor and a single full-window view
+    /// that sends every key to the program
+    fn set_passthrough(&mut self, passthrough: bool) {
+        if passthrough == self.passthrough {
+            return;
+        }
+        self.passthrough = passthrough;
+
+        self.input_view.set_visible(!passthrough);
+        self.separator.set_visible(!passthrough);
+        self.status_label.set_visible(!passthrough);
+        self.output_view.set_cursor_visible(passthrough);
+        if passthrough {
+            self.scroll_offset = 0;
+            self.output_view.grab_focus();
+        } else {
+            self.input_view.grab_focus();
+        }
+    }
+
+    /// forward a key press to the program while it has the terminal,
+    /// returns true if the key was used
+    fn passthrough_key(&mut self, key_event: &gdk::EventKey) -> bool {
+        if !self.passthrough {
+            return false;
+        }
+        match terminal_key(key_event) {
+            Some((key, modifiers)) => {
+                self.handle_event(ui::Event::Key(key, modifiers));
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    fn continues_input(&self, input: &str) -> bool {
+        return self
+            .sessions
+            .get(self.active)
+            .map_or(false, |session| ui::continues_input(session, input));
+    }
+
+    /// show another session in the window
+    fn switch_session(&mut self, id: SessionId) {
+        let exit_status = match self.sessions.get(id) {
+            Some(session) => session.exit_status(),
+            None => return,
+        };
+        self.active = id;
+        self.scroll_offset = 0;
+        self.hovered = None;
+        self.search = None;
+        self.dismiss_completions();
+        match exit_status {
+            Some(status) => self.handle_event(ui::Event::ShellExited(status)),
+            None => self.exit_box.hide(),
+        }
+        self.render_screen();
+        self.refresh_status();
+    }
+
+    /// open a session like the one shown and switch to it
+    fn new_tab(&mut self) {
+        match self.sessions.duplicate(self.active) {
+            Ok(id) => {
+                self.switch_session(id);
+                schedule_receive();
+            }
+            Err(e) => eprintln!("could not open a new tab:\n{}", e),
+        }
+    }
+
+    /// close the session shown and switch to the next one,
+    /// or close the window if it was the last
+    fn close_tab(&mut self) {
+        let next = self
+            .sessions
+            .adjacent(self.active, true)
+            .filter(|&id| id != self.active);
+        if let Err(e) = self.sessions.close(self.active) {
+            eprintln!("could not shut down session {}:\n{}", self.active, e);
+        }
+        match next {
+            Some(id) => self.switch_session(id),
+            None => self.close_window(),
+        }
+    }
+
+    /// show the next session, or the one before if not `forward`
+    fn cycle_tab(&mut self, forward: bool) {
+        if let Some(id) = self.sessions.adjacent(self.active, forward) {
+            self.switch_session(id);
+        }
+    }
+
+    /// close the window once the context isn't in use,
+    /// its handlers may need the context while it's destroyed
+    fn close_window(&self) {
+        let window = self.window.clone();
+        gtk::idle_add(move || {
+            window.close();
+            glib::Continue(false)
+        });
+    }
+
+    /// set the output's font size to `zoom` points more than the theme's
+    fn set_zoom(&mut self, zoom: i32) {
+        if self.base_font.is_none() {
+            self.base_font = self
+                .output_view
+                .get_pango_context()
+                .and_then(|context| context.get_font_description());
+        }
+        let mut font = match self.base_font {
+            Some(ref font) => font.clone(),
+            None => return,
+        };
+        let base_size = font.get_size() / pango::SCALE;
+        self.zoom = zoom.max(MIN_FONT_SIZE - base_size);
+        font.set_size((base_size + self.zoom) * pango::SCALE);
+        self.output_view.override_font(&font);
+
+        // the cells changed size, the area they fill didn't
+        if let Some(parent) = self.output_view.get_parent() {
+            let allocation = parent.get_allocation();
+            if let Some((rows, columns)) =
+                terminal_size(&self.output_view, allocation.width, allocation.height)
+            {
+                schedule_resize(rows, columns);
+            }
+        }
+    }
+
+    fn resize(&mut self, rows: usize, columns: usize) {
+        self.sessions.resize(rows, columns);
+        self.render_screen();
+    }
+
+    fn scroll(&mut self, lines: isize) {
+        let max_offset = match self.sessions.get(self.active) {
+            Some(session) => session.screen().max_offset(),
+            None => return,
+        };
+        let offset = (self.scroll_offset.min(max_offset) as isize + lines).max(0) as usize;
+        self.scroll_offset = offset.min(max_offset);
+        self.render_screen();
+    }
+
+    fn page_size(&self) -> isize {
+        let (rows, _) = self.sessions.size();
+        return rows as isize;
+    }
+
+    fn refresh_status(&mut self) {
+        let search = self
+            .search
+            .as_ref()
+            .map(|search| ui::search_prompt(search, &self.history));
+        let text = match search {
+            Some(text) => text,
+            None => self
+                .shell()
+                .ok()
+                .and_then(|shell| shell.status())
+                .map(|status| status.to_string())
+                .unwrap_or_default(),
+        };
+        update_status(&self.status_label, &text);
+    }
+
+    /// handle pending session events,
+    /// returns true if there may be more waiting
+    fn receive(&mut self) -> bool {
+        for _ in 0..MAX_EVENTS_PER_IDLE {
+            match self.sessions.poll() {
+                Some((id, event)) => self.handle_session_event(id, event),
+                None => {
+                    if self.screen_dirty {
+                        self.render_screen();
+                    }
+                    self.refresh_status();
+                    // a shell whose output closed may not have been reaped yet
+                    if self.sessions.awaiting_exit() && !self.exit_check_scheduled {
+                        self.exit_check_scheduled = true;
+                        gtk::timeout_add(EXIT_CHECK_INTERVAL_MS, wait_for_exit);
+                    }
+                    return false;
+                }
+            }
+        }
+
+        if self.screen_dirty {
+            self.render_screen();
+        }
+        self.refresh_status();
+        return true;
+    }
+
+    fn handle_session_event(&mut self, id: SessionId, event: session::Event) {
+        if let session::Event::CommandFinished(ref completion) = event {
+            ui::record_completion(&mut self.history, completion);
+        }
+        // only the active session is shown
+        if id != self.active {
+            return;
+        }
+
+        match event {
+            session::Event::Output(_) => self.screen_dirty = true,
+            // the status line shows how it went
+            session::Event::CommandFinished(_) => (),
+            session::Event::Error(e) => eprintln!("could not read shell output:\n{}", e),
+            session::Event::Exited(status) => self.handle_event(ui::Event::ShellExited(status)),
+            session::Event::Restarted => self.screen_dirty = true,
+            session::Event::Created | session::Event::Closed => (),
+        }
+    }
+
+    fn handle_event(&mut self, event: ui::Event) {
+        if let Some(control) = event.control() {
+            if let Err(e) = self
+                .shell()
+                .and_then(|shell| shell.send_control(control).map_err(session::Error::from))
+            {
+                eprintln!("could not send {:?} to shell:\n{}", control, e);
+            }
+            return;
+        }
+
+        match event {
+            // enter in an unfinished command starts a new line
+            ui::Event::Submit(ref string) if self.continues_input(string) => {
+                self.stdin_buffer.insert_at_cursor("\n")
+            }
+            ui::Event::Submit(string) | ui::Event::ForceSubmit(string) => match self
+                .shell()
+                .and_then(|shell| shell.submit(&string).map_err(session::Error::from))
+            {
+                Ok(started) => {
+                    // input read by a running command, like a password, isn't saved
+                    if let (Some(_), Some(session)) = (started, self.sessions.get(self.active)) {
+                        ui::record_command(&mut self.history, session, &string);
+                    }
+                    self.stdin_buffer.set_text("");
+                    // follow the output of the new command
+                    if self.scroll_offset > 0 {
+                        self.scroll_offset = 0;
+                        self.render_screen();
+                    }
+                }
+                Err(e) => eprintln!("could not submit command:\n{}", e),
+            },
+            ui::Event::ShellExited(status) => {
+                self.exit_label
+                    .set_text(&format!("shell exited with {}", status));
+                self.exit_box.show();
+            }
+            ui::Event::Restart => match self.sessions.restart(self.active) {
+                Ok(()) => {
+                    self.exit_box.hide();
+                    schedule_receive();
+                }
+                Err(e) => eprintln!("could not restart shell:\n{}", e),
+            },
+            ui::Event::Key(key, modifiers) => match self.sessions.get_mut(self.active) {
+                Some(session) => {
+                    if let Err(e) = session.send_key(key, modifiers) {
+                        eprintln!("could not send {:?} to shell:\n{}", key, e);
+                    }
+                }
+                None => eprintln!("{}", session::Error::NotFound(self.active)),
+            },
+            ui::Event::OpenLink(url) => ui::open_link(&url),
+            ui::Event::Newline => self.stdin_buffer.insert_at_cursor("\n"),
+            ui::Event::NewTab => self.new_tab(),
+            ui::Event::CloseTab => self.close_tab(),
+            ui::Event::NextTab => self.cycle_tab(true),
+            ui::Event::PreviousTab => self.cycle_tab(false),
+            ui::Event::ZoomIn => {
+                let zoom = self.zoom + 1;
+                self.set_zoom(zoom);
+            }
+            ui::Event::ZoomOut => {
+                let zoom = self.zoom - 1;
+                self.set_zoom(zoom);
+            }
+            ui::Event::ZoomReset => self.set_zoom(0),
+            ui::Event::Exit => self.close_window(),
+            ui::Event::HistoryPrevious => self.walk_history(true),
+            ui::Event::HistoryNext => self.walk_history(false),
+            ui::Event::SearchHistory(key) => self.search_history(key),
+            ui::Event::Complete => self.complete(),
+            ui::Event::Copy => self.copy(false),
+            ui::Event::CopyStyled => self.copy(true),
+            ui::Event::Paste(text) => self.paste(text, false),
+            ui::Event::PasteConfirmed(text) => self.paste(text, true),
+            ui::Event::ScrollLines(lines) => self.scroll(lines),
+            ui::Event::ScrollPages(pages) => {
+                let lines = pages * self.page_size();
+                self.scroll(lines);
+            }
+            _ => (),
+        }
+    }
 }
 
 fn destroy_default_context() {
     GLOBAL_CONTEXT.with(|global_ref| {
         if let Some(ref mut context) = *global_ref.borrow_mut() {
-            context.shell.exit();
+            for (id, result) in context.sessions.close_all() {
+                match result {
+                    Ok(status) => eprintln!("session {} exited with {}", id, status),
+                    Err(e) => eprintln!("could not shut down session {}:\n{}", id, e),
+                }
+            }
         }
         *global_ref.borrow_mut() = None;
     });
@@ -88,42 +933,59 @@ fn destroy_default_context() {
 
 pub struct Gtk {
     app: gtk::Application,
+    title: String,
+    dimensions: [i32; 2],
 }
 
 impl Gtk {
-    pub fn create(title: String, dimensions: [i32; 2]) -> Result<Gtk, glib::BoolError> {
-        let app = gtk::Application::new(APP_ID, gio::ApplicationFlags::empty())?;
-        return Ok(Gtk { app: app });
+    pub fn create(title: String, dimensions: [i32; 2]) -> Result<Gtk, Error> {
+        let app =
+            gtk::Application::new(APP_ID, gio::ApplicationFlags::empty()).map_err(Error::Gtk)?;
+        return Ok(Gtk {
+            app: app,
+            title: title,
+            dimensions: dimensions,
+        });
     }
 }
 
 impl Ui for Gtk {
-    type Error = String;
-    fn show(&mut self, mut shell: Shell) -> Result<(), Self::Error> {
+    fn show(
+        &mut self,
+        mut sessions: SessionManager,
+        history: History,
+        bindings: Bindings,
+        active: SessionId,
+    ) -> Result<(), Error> {
+        let bindings = Rc::new(bindings);
         let builder = gtk::Builder::new_from_string(include_str!("main_window.glade"));
         let main_window: gtk::ApplicationWindow = builder
             .get_object("main_window")
             .expect("could not get main window");
+        main_window.set_title(&self.title);
+        main_window.set_default_size(self.dimensions[0], self.dimensions[1]);
 
-        let input_view: gtk::TextView = builder
-            .get_object("input_view")
-            .expect("could not get input view from builder");
+        let output_scroll: gtk::ScrolledWindow = builder
+            .get_object("output_scroll")
+            .expect("could not get output scroll from builder");
 
-        let stdout_view: gtk::TextView = builder
+        let output_view: gtk::TextView = builder
             .get_object("output_view")
             .expect("could not get output view from builder");
 
-        let stdout_buffer: gtk::TextBuffer = stdout_view
-            .get_buffer()
-            .expect("could not get buffer from output view");
-
-        let buffer = input_view
-            .get_buffer()
-            .expect("couldn't get input text buffer");
+        let input_view: gtk::TextView = builder
+            .get_object("input_view")
+            .expect("could not get input view from builder");
 
-        let buffer_clone = buffer.clone();
+        let restart_button: gtk::Button = builder
+            .get_object("restart_button")
+            .expect("could not get restart button from builder");
 
-        Context::create_global_context(buffer.clone(), stdout_buffer, shell);
+        if sessions.get(active).is_none() {
+            return Err(session::Error::NotFound(active).into());
+        }
+        sessions.set_waker(Arc::new(schedule_receive));
+        Context::create_global_context(&builder, sessions, history, active);
 
         self.app.connect_startup(move |app| {
             main_window.set_application(app);
@@ -134,97 +996,364 @@ impl Ui for Gtk {
 
             main_window.show_all();
 
-            input_view.connect_key_press_event(move |view, key| {
-                let mut buffer = view.get_buffer().expect("couldn't get input text buffer");
-
-                GLOBAL_CONTEXT.with(|global_ref| {
-                    if let Some(ref mut context) = *global_ref.borrow_mut() {
-                        let ref mut shell = context.shell;
-                        let ref mut buffer = context.stdin_buffer;
-
-                        match process_key_event(view, key) {
-                            Some(ui::Event::Submit(string)) => {
-                                shell
-                                    .execute(&string)
-                                    .expect("shell could not execute command");
-                                // TODO
-                                // figure out how to keep the enter key from making a new line
-                                buffer.set_text("");
-                            }
-                            _ => (),
-                        }
+            input_view.connect_key_press_event(clone!(bindings => move |view, key| {
+                let action = terminal_key(key)
+                    .and_then(|(key, modifiers)| bindings.action(key, modifiers));
+                // typing anything but the complete key closes the list of candidates
+                if action != Some(Action::Complete) && !is_modifier(key.get_keyval()) {
+                    with_context(|context| context.dismiss_completions());
+                }
+
+                let mut searching = false;
+                with_context(|context| searching = context.search.is_some());
+                if let Some(search_key) = search_key(key, action).filter(|_| searching) {
+                    with_context(|context| {
+                        context.handle_event(ui::Event::SearchHistory(search_key))
+                    });
+                    // any other key keeps the match and does what it usually does
+                    if search_key != ui::SearchKey::Accept {
+                        return Inhibit(true);
                     }
-                });
+                }
+
+                // a bound key doesn't also do what it does in the text view
+                match action.and_then(|action| input_event(view, action)) {
+                    Some(event) => {
+                        with_context(|context| context.handle_event(event));
+                        Inhibit(true)
+                    }
+                    None => Inhibit(false),
+                }
+            }));
 
+            restart_button.connect_clicked(|_| {
+                with_context(|context| context.handle_event(ui::Event::Restart));
+            });
+
+            output_view.connect_key_press_event(clone!(bindings => move |_, key| {
+                // keys bound to actions for the whole window are never sent to the program
+                let event = terminal_key(key)
+                    .and_then(|(key, modifiers)| bindings.action(key, modifiers))
+                    .filter(Action::is_global)
+                    .and_then(action_event);
+                if let Some(event) = event {
+                    with_context(|context| context.handle_event(event));
+                    return Inhibit(true);
+                }
+                let mut handled = false;
+                with_context(|context| handled = context.passthrough_key(key));
+                Inhibit(handled)
+            }));
+
+            // every paste goes through the same check as ctrl+shift+v
+            input_view.connect_paste_clipboard(|view| {
+                view.stop_signal_emission("paste-clipboard");
+                if let Some(text) = clipboard_text() {
+                    with_context(|context| context.handle_event(ui::Event::Paste(text)));
+                }
+            });
+
+            output_view.connect_motion_notify_event(|view, event| {
+                let (x, y) = event.get_position();
+                let (x, y) =
+                    view.window_to_buffer_coords(gtk::TextWindowType::Text, x as i32, y as i32);
+                let position = view
+                    .get_iter_at_location(x, y)
+                    .map(|iter| (iter.get_line() as usize, iter.get_line_offset() as usize));
+                with_context(|context| context.hover_link(position));
+                Inhibit(false)
+            });
+
+            output_view.connect_leave_notify_event(|_, _| {
+                with_context(|context| context.hover_link(None));
                 Inhibit(false)
             });
+
+            output_view.connect_scroll_event(|_, event| {
+                if let Some(lines) = scroll_lines(event) {
+                    with_context(|context| context.handle_event(ui::Event::ScrollLines(lines)));
+                }
+                // the buffer only holds what's visible, scrolling is done by redrawing it
+                Inhibit(true)
+            });
+
+            // the scrolled window's allocation doesn't depend on the text it shows
+            output_scroll.connect_size_allocate(clone!(output_view => move |_, allocation| {
+                if let Some((rows, columns)) =
+                    terminal_size(&output_view, allocation.width, allocation.height)
+                {
+                    schedule_resize(rows, columns);
+                }
+            }));
         });
 
-        gtk::idle_add(receive_stdout);
+        schedule_receive();
 
         // included to suppress warnings
         self.app.connect_activate(|_| {});
 
         self.app.run(&::std::env::args().collect::<Vec<_>>());
+        destroy_default_context();
         return Ok(());
     }
 }
 
-fn receive_stdout() -> glib::Continue {
-    return glib::Continue(GLOBAL_CONTEXT.with(|global_ref| {
+/// the text on the clipboard, waiting for its owner to send it
+fn clipboard_text() -> Option<String> {
+    return gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD).wait_for_text();
+}
+
+/// the line and offset of a position in a buffer
+fn position(iter: &gtk::TextIter) -> (usize, usize) {
+    return (iter.get_line() as usize, iter.get_line_offset() as usize);
+}
+
+/// the row and column of a position in the buffer showing `screen` scrolled back `offset` lines
+fn screen_position(screen: &Screen, offset: usize, iter: &gtk::TextIter) -> (usize, usize) {
+    let (row, line_offset) = position(iter);
+    return (row, screen::column_at(&screen.view_runs(offset, row), line_offset));
+}
+
+/// open a link when it's clicked with ctrl held
+fn link_event(event: &gdk::Event, iter: &gtk::TextIter) -> Inhibit {
+    if event.get_event_type() != gdk::EventType::ButtonPress {
+        return Inhibit(false);
+    }
+    let ctrl_click = match event.clone().downcast::<gdk::EventButton>() {
+        Ok(button) => {
+            button.get_button() == 1
+                && button
+                    .get_state()
+                    .contains(gdk::ModifierType::CONTROL_MASK)
+        }
+        Err(_) => false,
+    };
+    if !ctrl_click {
+        return Inhibit(false);
+    }
+
+    let (row, column) = (iter.get_line() as usize, iter.get_line_offset() as usize);
+    let mut opened = false;
+    with_context(|context| {
+        let url = context.link_at(row, column).map(|link| link.url.clone());
+        if let Some(url) = url {
+            context.handle_event(ui::Event::OpenLink(url));
+            opened = true;
+        }
+    });
+    // keep the click from moving the cursor or starting a selection
+    return Inhibit(opened);
+}
+
+fn with_context<F: FnOnce(&mut Context)>(f: F) {
+    GLOBAL_CONTEXT.with(|global_ref| {
         if let Some(ref mut context) = *global_ref.borrow_mut() {
-            let ref mut shell = context.shell;
-            let ref mut stdout_buffer = context.stdout_buffer;
-
-            match shell.poll_stdout() {
-                Ok(Some(bytes)) => match ::std::str::from_utf8(&bytes) {
-                    Ok(s) => {
-                        let mut end_iter = stdout_buffer.get_end_iter();
-                        stdout_buffer.insert(&mut end_iter, s);
-                        return true;
-                    }
-                    Err(e) => {
-                        eprintln!("unable to parse string from shell output:\n{}", e);
-                        return false;
-                    }
-                },
-                Err(e) => {
-                    eprintln!("shell output stream closed:\n{}", e);
-                    return false;
-                }
-                _ => return true,
-            }
+            f(context);
         } else {
             eprintln!("couldn't get context");
-            return false;
         }
-    }));
-}
-
-fn process_key_event(text_view: &gtk::TextView, key_event: &gdk::EventKey) -> Option<ui::Event> {
-    // shift modified
-    if key_event
-        .get_state()
-        .contains(gdk::ModifierType::SHIFT_MASK)
-    {
-        // enter pressed
-        match key_event.get_keyval() {
-            KEY_ENTER => {
-                return Some(ui::Event::Submit(
-                    text_view
-                        .get_buffer()
-                        .map(|buffer| {
-                            let (start, end) = buffer.get_bounds();
-                            let mut text = buffer.get_text(&start, &end, true).unwrap_or("".into());
-                            text.push('\n');
-                            text
-                        })
-                        .unwrap_or("".into()),
-                ))
+    });
+}
+
+/// queue a call to `receive_output` on the main loop
+/// called from the shells' reader threads whenever output arrives
+fn schedule_receive() {
+    if !OUTPUT_SCHEDULED.swap(true, Ordering::SeqCst) {
+        glib::idle_add(receive_output);
+    }
+}
+
+fn receive_output() -> glib::Continue {
+    OUTPUT_SCHEDULED.store(false, Ordering::SeqCst);
+
+    let mut more = false;
+    with_context(|context| more = context.receive());
+    // run again unless another call is already queued
+    return glib::Continue(more && !OUTPUT_SCHEDULED.swap(true, Ordering::SeqCst));
+}
+
+fn wait_for_exit() -> glib::Continue {
+    let mut waiting = false;
+    with_context(|context| {
+        context.receive();
+        waiting = context.sessions.awaiting_exit();
+        context.exit_check_scheduled = waiting;
+    });
+    return glib::Continue(waiting);
+}
+
+/// the key a program reading the terminal should get for a key press
+fn terminal_key(key_event: &gdk::EventKey) -> Option<(Key, Modifiers)> {
+    let state = key_event.get_state();
+    let modifiers = Modifiers {
+        shift: state.contains(gdk::ModifierType::SHIFT_MASK),
+        ctrl: state.contains(gdk::ModifierType::CONTROL_MASK),
+        alt: state.contains(gdk::ModifierType::MOD1_MASK),
+    };
+
+    let key = match key_event.get_keyval() {
+        key::Return | key::KP_Enter => Key::Enter,
+        key::Tab | key::ISO_Left_Tab => Key::Tab,
+        key::BackSpace => Key::Backspace,
+        key::Escape => Key::Escape,
+        key::Up => Key::Up,
+        key::Down => Key::Down,
+        key::Right => Key::Right,
+        key::Left => Key::Left,
+        key::Home => Key::Home,
+        key::End => Key::End,
+        key::Insert => Key::Insert,
+        key::Delete => Key::Delete,
+        key::Page_Up => Key::PageUp,
+        key::Page_Down => Key::PageDown,
+        keyval @ key::F1..=key::F12 => Key::Function((keyval - key::F1 + 1) as u8),
+        keyval => Key::Char(gdk::keyval_to_unicode(keyval)?),
+    };
+    return Some((key, modifiers));
+}
+
+/// lines to scroll back for a mouse wheel event
+fn scroll_lines(event: &gdk::EventScroll) -> Option<isize> {
+    match event.get_direction() {
+        gdk::ScrollDirection::Up => return Some(SCROLL_LINES),
+        gdk::ScrollDirection::Down => return Some(-SCROLL_LINES),
+        gdk::ScrollDirection::Smooth => {
+            let (_, dy) = event.get_delta();
+            let lines = (-dy * SCROLL_LINES as f64).round() as isize;
+            if lines == 0 {
+                return None;
             }
+            return Some(lines);
+        }
+        _ => return None,
+    }
+}
+
+/// rows and columns of monospace text that fit in an area of the output view
+fn terminal_size(view: &gtk::TextView, width: i32, height: i32) -> Option<(usize, usize)> {
+    let (cell_width, cell_height) = view.create_pango_layout(Some("M"))?.get_pixel_size();
+    if cell_width <= 0 || cell_height <= 0 {
+        return None;
+    }
+    return Some((
+        (height / cell_height).max(1) as usize,
+        (width / cell_width).max(1) as usize,
+    ));
+}
+
+/// resize the sessions once the window has kept its size for a moment,
+/// so a drag doesn't reflow every shell at each step
+fn schedule_resize(rows: usize, columns: usize) {
+    PENDING_RESIZE.with(|pending| {
+        let scheduled = pending.get().is_some();
+        pending.set(Some((rows, columns, Instant::now())));
+        if !scheduled {
+            gtk::timeout_add(RESIZE_DEBOUNCE_MS as u32, apply_resize);
+        }
+    });
+}
+
+fn apply_resize() -> glib::Continue {
+    match PENDING_RESIZE.with(Cell::get) {
+        Some((rows, columns, requested))
+            if requested.elapsed() >= Duration::from_millis(RESIZE_DEBOUNCE_MS) =>
+        {
+            PENDING_RESIZE.with(|pending| pending.set(None));
+            with_context(|context| context.resize(rows, columns));
+            return glib::Continue(false);
+        }
+        Some(_) => return glib::Continue(true),
+        None => return glib::Continue(false),
+    }
+}
+
+fn update_status(status_label: &gtk::Label, text: &str) {
+    if status_label.get_text().map(|current| current != text).unwrap_or(true) {
+        status_label.set_text(&text);
+    }
+}
+
+/// true for keys that only modify other keys
+fn is_modifier(keyval: KeyVal) -> bool {
+    match keyval {
+        key::Shift_L | key::Shift_R | key::Control_L | key::Control_R | key::Alt_L
+        | key::Alt_R | key::Super_L | key::Super_R | key::Caps_Lock => return true,
+        _ => return false,
+    }
+}
+
+/// what a key press bound to `action` does while searching the history,
+/// none for a modifier on its own
+fn search_key(key_event: &gdk::EventKey, action: Option<Action>) -> Option<ui::SearchKey> {
+    if action == Some(Action::SearchHistory) {
+        return Some(ui::SearchKey::Next);
+    }
+    let state = key_event.get_state();
+    let keyval = key_event.get_keyval();
+    if state.contains(gdk::ModifierType::CONTROL_MASK) {
+        match keyval {
+            key::t => return Some(ui::SearchKey::ToggleDirectory),
+            key::g => return Some(ui::SearchKey::Cancel),
             _ => (),
         }
     }
 
-    return None;
+    if is_modifier(keyval) {
+        return None;
+    }
+    match keyval {
+        key::Escape => return Some(ui::SearchKey::Cancel),
+        key::BackSpace => return Some(ui::SearchKey::Backspace),
+        _ => (),
+    }
+
+    let modifiers = gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::MOD1_MASK;
+    match gdk::keyval_to_unicode(keyval) {
+        Some(c) if !c.is_control() && !state.intersects(modifiers) => {
+            return Some(ui::SearchKey::Char(c))
+        }
+        _ => return Some(ui::SearchKey::Accept),
+    }
+}
+
+/// the event for an action bound to a key pressed in the input,
+/// none if the key should do what it usually does in a text view
+fn input_event(text_view: &gtk::TextView, action: Action) -> Option<ui::Event> {
+    let buffer = text_view.get_buffer()?;
+    match action {
+        Action::Submit | Action::ForceSubmit => {
+            let (start, end) = buffer.get_bounds();
+            let mut text = buffer.get_text(&start, &end, true).unwrap_or("".into());
+            text.push('\n');
+            if action == Action::ForceSubmit {
+                return Some(ui::Event::ForceSubmit(text));
+            }
+            return Some(ui::Event::Submit(text));
+        }
+        // up on the first line and down on the last walk the history,
+        // elsewhere they move the cursor between lines
+        Action::HistoryPrevious | Action::HistoryNext => {
+            let line = buffer
+                .get_insert()
+                .map(|mark| buffer.get_iter_at_mark(&mark).get_line());
+            let edge = if action == Action::HistoryPrevious {
+                0
+            } else {
+                buffer.get_line_count() - 1
+            };
+            if line != Some(edge) {
+                return None;
+            }
+            return ui::Event::from_action(action);
+        }
+        _ => return action_event(action),
+    }
+}
+
+/// the event for an action, reading the clipboard to paste
+fn action_event(action: Action) -> Option<ui::Event> {
+    match action {
+        Action::Paste => return clipboard_text().map(ui::Event::Paste),
+        _ => return ui::Event::from_action(action),
+    }
 }
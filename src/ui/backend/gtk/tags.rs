@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use gtk::prelude::*;
+
+use gtk;
+use pango;
+
+use screen::Attributes;
+
+/// used for inverse video when a cell has the default colors
+const DEFAULT_FOREGROUND: (u8, u8, u8) = (0x00, 0x00, 0x00);
+const DEFAULT_BACKGROUND: (u8, u8, u8) = (0xff, 0xff, 0xff);
+/// `pango::Weight::Bold`
+const BOLD_WEIGHT: i32 = 700;
+
+/// text tags for cell attributes, created the first time a set of attributes is drawn
+/// and kept in the buffer's tag table for reuse
+pub struct TagCache {
+    table: gtk::TextTagTable,
+    tags: HashMap<Attributes, gtk::TextTag>,
+}
+
+impl TagCache {
+    pub fn new(table: gtk::TextTagTable) -> TagCache {
+        return TagCache {
+            table: table,
+            tags: HashMap::new(),
+        };
+    }
+
+    /// the tag for `attributes`, `None` if they're drawn as plain text
+    pub fn get(&mut self, attributes: &Attributes) -> Option<gtk::TextTag> {
+        // links have their own tag
+        let key = Attributes {
+            link: None,
+            ..*attributes
+        };
+        if key == Attributes::default() {
+            return None;
+        }
+
+        if let Some(tag) = self.tags.get(&key) {
+            return Some(tag.clone());
+        }
+
+        let tag = create_tag(&key);
+        self.table.add(&tag);
+        self.tags.insert(key, tag.clone());
+        return Some(tag);
+    }
+}
+
+fn create_tag(attributes: &Attributes) -> gtk::TextTag {
+    let tag = gtk::TextTag::new(None);
+
+    let (foreground, background) = if attributes.inverse {
+        (
+            Some(attributes.background.rgb().unwrap_or(DEFAULT_BACKGROUND)),
+            Some(attributes.foreground.rgb().unwrap_or(DEFAULT_FOREGROUND)),
+        )
+    } else {
+        (attributes.foreground.rgb(), attributes.background.rgb())
+    };
+
+    let foreground = match foreground {
+        Some(rgb) if attributes.dim => Some(dim(rgb)),
+        _ => foreground,
+    };
+
+    if attributes.hidden {
+        tag.set_property_invisible(true);
+    }
+    if let Some(rgb) = foreground {
+        tag.set_property_foreground(Some(hex(rgb).as_str()));
+    }
+    if let Some(rgb) = background {
+        tag.set_property_background(Some(hex(rgb).as_str()));
+    }
+    if attributes.bold {
+        tag.set_property_weight(BOLD_WEIGHT);
+    }
+    if attributes.italic {
+        tag.set_property_style(pango::Style::Italic);
+    }
+    if attributes.underline {
+        tag.set_property_underline(pango::Underline::Single);
+    }
+    if attributes.strikethrough {
+        tag.set_property_strikethrough(true);
+    }
+
+    return tag;
+}
+
+fn dim((r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+    return (r / 2, g / 2, b / 2);
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    return format!("#{:02x}{:02x}{:02x}", r, g, b);
+}
@@ -1,4 +1,6 @@
 pub mod conrod;
 pub mod gtk;
 
-pub use super::{load_font, Config, Ui, DEFAULT_DIMENSIONS, DEFAULT_FONT, DEFAULT_TITLE};
+pub use super::{
+    load_font, load_font_style, Config, Error, Ui, DEFAULT_DIMENSIONS, DEFAULT_FONT, DEFAULT_TITLE,
+};
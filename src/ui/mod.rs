@@ -6,12 +6,79 @@ use font_loader::system_fonts::FontPropertyBuilder;
 pub use super::config::Config;
 pub use super::constants::{DEFAULT_DIMENSIONS, DEFAULT_FONT, DEFAULT_TITLE};
 
-use shell::Shell;
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::process::ExitStatus;
 
-pub trait Ui {
-    type Error: ::std::fmt::Display;
+use gio;
+use glib;
+
+use bindings::{Action, Bindings};
+use history::{Entry, History, Search};
+use input::{Key, Modifiers};
+use session;
+use session::{Session, SessionId, SessionManager};
+use shell;
+use shell::syntax;
+use shell::{CommandStatus, Completion, Control};
+
+#[derive(Debug)]
+pub enum Error {
+    /// the gtk application could not be registered
+    Gtk(glib::BoolError),
+    /// the window or renderer could not be set up, or a frame failed to draw
+    Graphics(String),
+    /// no installed font matches the family
+    FontNotFound(String),
+    /// the font was found but could not be loaded
+    Font { family: String, reason: String },
+    Shell(shell::Error),
+    Session(session::Error),
+}
 
-    fn show(&mut self, shell: Shell) -> Result<(), Self::Error>;
+impl From<shell::Error> for Error {
+    fn from(error: shell::Error) -> Self {
+        return Error::Shell(error);
+    }
+}
+
+impl From<session::Error> for Error {
+    fn from(error: session::Error) -> Self {
+        return Error::Session(error);
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Gtk(ref e) => write!(f, "could not start gtk: {}", e),
+            Error::Graphics(ref reason) => write!(f, "graphics error: {}", reason),
+            Error::FontNotFound(ref family) => write!(
+                f,
+                "could not find the font `{}`, check `family` in the [font] section of the config",
+                family
+            ),
+            Error::Font {
+                ref family,
+                ref reason,
+            } => write!(f, "could not load the font `{}`: {}", family, reason),
+            Error::Shell(ref e) => write!(f, "{}", e),
+            Error::Session(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub trait Ui {
+    /// run until the window is closed, showing the `active` session,
+    /// recording submitted commands in `history` and handling keys with `bindings`
+    fn show(
+        &mut self,
+        sessions: SessionManager,
+        history: History,
+        bindings: Bindings,
+        active: SessionId,
+    ) -> Result<(), Error>;
 }
 
 pub trait TextView {
@@ -21,16 +88,256 @@ pub trait TextView {
 
 #[derive(Debug)]
 pub enum Event {
+    /// send the input, unless enter should start a new line in it
     Submit(String),
+    /// send the input even if it looks unfinished
+    ForceSubmit(String),
+    /// start a new line in the input
+    Newline,
+    Interrupt,
+    Suspend,
+    Quit,
+    Eof,
+    ShellExited(ExitStatus),
+    Restart,
     Exit,
+    /// move the output view back into the scrollback, or forward if negative
+    ScrollLines(isize),
+    /// like `ScrollLines`, by a screen at a time
+    ScrollPages(isize),
+    /// a key pressed while a full-screen program has the terminal
+    Key(Key, Modifiers),
+    /// a link in the output was ctrl clicked
+    OpenLink(String),
+    /// copy the selected output as plain text
+    Copy,
+    /// copy the selected output with SGR sequences for its colors and styles
+    CopyStyled,
+    /// text from the clipboard, confirmed first if it could run commands
+    Paste(String),
+    /// pasted text the user agreed to paste
+    PasteConfirmed(String),
+    /// replace the input with the command before the one shown
+    HistoryPrevious,
+    /// replace the input with the next command, or the line that was being edited
+    HistoryNext,
+    /// a key press for the history search, ctrl+r starts one
+    SearchHistory(SearchKey),
+    /// complete the word before the cursor, or show the next candidate
+    Complete,
+    /// open a session in the working directory of the one shown and show it
+    NewTab,
+    /// close the session shown, and the window with the last one
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    /// make the output text a step bigger
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+}
+
+/// what a key press does while searching the history
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchKey {
+    /// add to the query
+    Char(char),
+    /// remove the last character of the query
+    Backspace,
+    /// show the next best match
+    Next,
+    /// switch between every command and only those run in the current directory
+    ToggleDirectory,
+    /// stop searching and put back the line that was being edited
+    Cancel,
+    /// stop searching and keep the match
+    Accept,
+}
+
+impl Event {
+    /// the control character this event is forwarded to the shell as
+    pub fn control(&self) -> Option<Control> {
+        match *self {
+            Event::Interrupt => Some(Control::Interrupt),
+            Event::Suspend => Some(Control::Suspend),
+            Event::Quit => Some(Control::Quit),
+            Event::Eof => Some(Control::Eof),
+            _ => None,
+        }
+    }
+
+    /// the event for a bound action, none for submit and paste,
+    /// which need the input's text or the clipboard's from the backend
+    pub fn from_action(action: Action) -> Option<Event> {
+        let event = match action {
+            Action::Submit | Action::ForceSubmit | Action::Paste | Action::Unbound => {
+                return None
+            }
+            Action::Newline => Event::Newline,
+            Action::Interrupt => Event::Interrupt,
+            Action::Suspend => Event::Suspend,
+            Action::Quit => Event::Quit,
+            Action::Eof => Event::Eof,
+            Action::Copy => Event::Copy,
+            Action::CopyStyled => Event::CopyStyled,
+            Action::ScrollLineUp => Event::ScrollLines(1),
+            Action::ScrollLineDown => Event::ScrollLines(-1),
+            Action::ScrollPageUp => Event::ScrollPages(1),
+            Action::ScrollPageDown => Event::ScrollPages(-1),
+            Action::HistoryPrevious => Event::HistoryPrevious,
+            Action::HistoryNext => Event::HistoryNext,
+            Action::SearchHistory => Event::SearchHistory(SearchKey::Next),
+            Action::Complete => Event::Complete,
+            Action::NewTab => Event::NewTab,
+            Action::CloseTab => Event::CloseTab,
+            Action::NextTab => Event::NextTab,
+            Action::PreviousTab => Event::PreviousTab,
+            Action::ZoomIn => Event::ZoomIn,
+            Action::ZoomOut => Event::ZoomOut,
+            Action::ZoomReset => Event::ZoomReset,
+            Action::Restart => Event::Restart,
+            Action::Exit => Event::Exit,
+        };
+        return Some(event);
+    }
+}
+
+/// the title the session's program set,
+/// or the app name and the shell's working directory
+pub fn window_title(session: &Session) -> String {
+    if let Some(title) = session.title() {
+        return title.to_string();
+    }
+    match session.cwd() {
+        Some(cwd) => return format!("{}: {}", DEFAULT_TITLE, home_relative(cwd)),
+        None => return DEFAULT_TITLE.to_string(),
+    }
+}
+
+/// a path with the home directory shortened to `~`
+fn home_relative(path: &Path) -> String {
+    if let Some(home) = env::var_os("HOME") {
+        if let Ok(rest) = path.strip_prefix(&home) {
+            if rest.as_os_str().is_empty() {
+                return "~".to_string();
+            }
+            return format!("~/{}", rest.display());
+        }
+    }
+    return path.display().to_string();
+}
+
+/// the question asked before pasting text that isn't safe to paste
+pub fn paste_prompt(text: &str) -> String {
+    let lines = text.lines().count();
+    if lines > 1 {
+        return format!("paste {} lines?", lines);
+    }
+    if text.ends_with('\n') || text.ends_with('\r') {
+        return "paste a line ending in a newline?".to_string();
+    }
+    return "paste text with control characters?".to_string();
 }
 
-pub fn load_font(family: &str) -> Result<Vec<u8>, String> {
-    let property = FontPropertyBuilder::new().family(family).build();
+/// apply a key press to the history search, starting one with the `input`
+/// that's being edited if there's none, returns the text the input should show
+pub fn search_history(
+    search: &mut Option<Search>,
+    history: &History,
+    key: SearchKey,
+    input: &str,
+    cwd: Option<&Path>,
+) -> Option<String> {
+    let (mut current, started) = match search.take() {
+        Some(current) => (current, false),
+        None => (Search::new(input, cwd), true),
+    };
+    match key {
+        SearchKey::Char(c) => current.push(c),
+        SearchKey::Backspace => current.pop(),
+        // the first ctrl+r shows the best match
+        SearchKey::Next if started => (),
+        SearchKey::Next => current.next(history),
+        SearchKey::ToggleDirectory => current.toggle_directory(),
+        SearchKey::Cancel => return Some(current.draft().to_string()),
+        SearchKey::Accept => return None,
+    }
+
+    let text = current.current(history).map(|entry| entry.command.clone());
+    *search = Some(current);
+    return text;
+}
+
+/// the status line while searching the history
+pub fn search_prompt(search: &Search, history: &History) -> String {
+    let failing = if search.current(history).is_none() {
+        "failing "
+    } else {
+        ""
+    };
+    let scope = match search.directory() {
+        Some(cwd) => format!(" in {}", home_relative(cwd)),
+        None => String::new(),
+    };
+    return format!(
+        "{}search{}: {}_  (ctrl+r next, ctrl+t this directory, escape cancels)",
+        failing,
+        scope,
+        search.query()
+    );
+}
+
+/// true if enter should start a new line in `input` rather than submit it,
+/// because it isn't a complete command and the session's shell wants them complete
+/// input to a running command is a line for it to read, not shell syntax
+pub fn continues_input(session: &Session, input: &str) -> bool {
+    let shell = session.shell();
+    if shell.status() == Some(CommandStatus::Running) {
+        return false;
+    }
+    let smart_enter = shell.config().smart_enter.unwrap_or(true);
+    return smart_enter && !syntax::is_complete(input);
+}
+
+/// save a command the shell of `session` started, a history that can't be written
+/// still keeps it in memory
+pub fn record_command(history: &mut History, session: &Session, command: &str) {
+    if let Err(e) = history.push(Entry::new(command, session.cwd())) {
+        eprintln!("could not save command to history:\n{}", e);
+    }
+}
+
+/// save the exit code of a finished command with its history entry
+pub fn record_completion(history: &mut History, completion: &Completion) {
+    if let Err(e) = history.finish(&completion.command, completion.exit_code) {
+        eprintln!("could not save command status to history:\n{}", e);
+    }
+}
+
+/// open a url with the desktop's default handler for it
+pub fn open_link(url: &str) {
+    if let Err(e) = gio::AppInfo::launch_default_for_uri(url, None::<&gio::AppLaunchContext>) {
+        eprintln!("could not open {}:\n{}", url, e);
+    }
+}
+
+pub fn load_font(family: &str) -> Result<Vec<u8>, Error> {
+    return load_font_style(family, false, false);
+}
+
+/// load the bold and/or italic face of a font family
+pub fn load_font_style(family: &str, bold: bool, italic: bool) -> Result<Vec<u8>, Error> {
+    let mut property = FontPropertyBuilder::new().family(family);
+    if bold {
+        property = property.bold();
+    }
+    if italic {
+        property = property.italic();
+    }
 
-    return system_fonts::get(&property)
+    return system_fonts::get(&property.build())
         .map(|(font_bytes, _)| font_bytes) // get rid of c_int font Note (?)
-        .ok_or(format!("could not load font: {}", family));
+        .ok_or(Error::FontNotFound(family.to_string()));
 }
 
 #[cfg(test)]